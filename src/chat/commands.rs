@@ -0,0 +1,565 @@
+//! Slash commands typed in Slack to run a meeting from the chat:
+//!
+//! - `start`: starts the meeting of the team scheduled around now, with the
+//!   speaking order of its session
+//! - `next`: records the turn of whoever's speaking and passes the word on
+//! - `finish`: records the last turn and marks the meeting as held with its real
+//!   dates, replying with its summary
+//! - `stats`: how the running meeting goes, or the summary of the last one
+//!
+//! Slack users are mapped to users by the email of their profile, and every
+//! command takes the name of the team as argument when the user is in several.
+
+use std::collections::HashMap;
+
+use bson::{doc, oid::ObjectId};
+use chrono::{Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, ReturnDocument};
+use mongodb::{error::Error, Database};
+use rocket::serde::json::{json, Value};
+
+use super::{clock, slack, summarize};
+use crate::models::{
+    chat::ChatSession,
+    meeting::{meeting_event, session_members, Meeting, MeetingStatus},
+    meeting_config::MeetingConfig,
+    team::Team,
+    user::{normalize_email, User},
+    user_time::{UserTime, UserTimeView},
+    webhook::WebhookEvent,
+};
+use crate::utils::db::is_duplicate_key;
+use crate::webhooks;
+
+/// Hours around now a scheduled meeting can be started from the chat
+const START_WINDOW_HOURS: i64 = 12;
+
+const USAGE: &str = "*Usage:* `/standup <command> [team]`\n\
+    • `start`: starts the meeting scheduled around now\n\
+    • `next`: passes the word to the next member\n\
+    • `finish`: ends the meeting and shows its summary\n\
+    • `stats`: how the running meeting goes, or the summary of the last one\n\
+    The name of the team is only needed if you're in several.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Start,
+    Next,
+    Finish,
+    Stats,
+    Help,
+}
+
+/// Action and team typed after the command, ex: `start Team A`
+fn parse(text: &str) -> (Action, Option<String>) {
+    let text = text.trim();
+    let (action, team) = match text.split_once(char::is_whitespace) {
+        Some((action, team)) => (action, Some(team.trim().to_owned())),
+        None => (text, None),
+    };
+
+    let action = match action.to_lowercase().as_str() {
+        "start" => Action::Start,
+        "next" | "advance" => Action::Next,
+        "finish" | "end" => Action::Finish,
+        "stats" => Action::Stats,
+        _ => Action::Help,
+    };
+
+    (action, team)
+}
+
+/// Reply only seen by whoever typed the command
+fn ephemeral(text: impl Into<String>) -> Value {
+    json!({ "response_type": "ephemeral", "text": text.into() })
+}
+
+/// Reply seen by the whole channel
+fn in_channel(text: impl Into<String>) -> Value {
+    json!({ "response_type": "in_channel", "text": text.into() })
+}
+
+/// User with the email of the Slack user, or the reply explaining why there's none
+async fn find_user(db: &Database, slack_user_id: &str) -> Result<Result<User, Value>, Error> {
+    let token = match std::env::var("SLACK_BOT_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => {
+            return Ok(Err(ephemeral(
+                "The chat integration isn't set up to look up who you are (`SLACK_BOT_TOKEN`)",
+            )))
+        }
+    };
+
+    let email = match slack::user_email(&token, slack_user_id).await {
+        Ok(Some(email)) => email,
+        Ok(None) => return Ok(Err(ephemeral("Your Slack profile doesn't show your email"))),
+        Err(error) => {
+            eprintln!("[CHAT][SLACK] ~ {}", error);
+            return Ok(Err(ephemeral(
+                "Slack didn't tell who you are, try again later",
+            )));
+        }
+    };
+
+    let user = db
+        .collection::<User>("users")
+        .find_one(doc! { "email": normalize_email(&email), "deleted_at": null }, None)
+        .await?;
+
+    Ok(user.ok_or_else(|| ephemeral(format!("There's no user with the email {}", email))))
+}
+
+/// Team of the user the command is about, or the reply explaining why there's none
+async fn find_team(
+    db: &Database,
+    user: &User,
+    name: Option<&str>,
+) -> Result<Result<Team, Value>, Error> {
+    let teams = db
+        .collection::<Team>("teams")
+        .find(doc! { "users": user.id.unwrap(), "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<Team>>()
+        .await?;
+
+    let names = || {
+        teams
+            .iter()
+            .map(|team| slack::escape(&team.name))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let team = match name {
+        Some(name) => teams
+            .iter()
+            .find(|team| team.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                ephemeral(format!(
+                    "You aren't in a team named {}",
+                    slack::escape(name)
+                ))
+            }),
+        None if teams.len() == 1 => Ok(&teams[0]),
+        None if teams.is_empty() => Err(ephemeral("You aren't in any team")),
+        None => Err(ephemeral(format!(
+            "You're in several teams, add the name of one: {}",
+            names()
+        ))),
+    };
+
+    Ok(team.cloned())
+}
+
+async fn user_names(
+    db: &Database,
+    users_id: &[ObjectId],
+) -> Result<HashMap<ObjectId, String>, Error> {
+    Ok(db
+        .collection::<User>("users")
+        .find(doc! { "_id": { "$in": users_id } }, None)
+        .await?
+        .try_collect::<Vec<User>>()
+        .await?
+        .into_iter()
+        .map(|user| (user.id.unwrap(), slack::escape(&user.name)))
+        .collect())
+}
+
+async fn config_name(db: &Database, meeting: &Meeting) -> Result<String, Error> {
+    let config = match meeting.config_id {
+        Some(config_id) => {
+            db.collection::<MeetingConfig>("meeting_configs")
+                .find_one(doc! { "_id": config_id }, None)
+                .await?
+        }
+        None => None,
+    };
+
+    Ok(config
+        .map(|config| slack::escape(&config.config_name))
+        .unwrap_or_else(|| "Meeting".to_owned()))
+}
+
+/// Running session of the team, sessions whose meeting was closed, recorded or
+/// deleted meanwhile are dropped
+async fn running_session(
+    db: &Database,
+    team: &Team,
+) -> Result<Option<(ChatSession, Meeting)>, Error> {
+    let sessions = db.collection::<ChatSession>("chat_sessions");
+    let session = match sessions
+        .find_one(doc! { "team_id": team.id.unwrap() }, None)
+        .await?
+    {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    let meeting = db
+        .collection::<Meeting>("meetings")
+        .find_one(
+            doc! { "_id": session.meeting_id, "status": "SCHEDULED", "deleted_at": null },
+            None,
+        )
+        .await?;
+
+    match meeting {
+        Some(meeting) => Ok(Some((session, meeting))),
+        None => {
+            sessions
+                .delete_one(doc! { "_id": session.id.unwrap() }, None)
+                .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Records the turn of a member from `turn_started_at` until now
+async fn record_turn(
+    db: &Database,
+    meeting: &Meeting,
+    session: &ChatSession,
+    user_id: ObjectId,
+) -> Result<UserTime, Error> {
+    let mut user_time = UserTime {
+        id: None,
+        user_id: Some(user_id),
+        meeting_id: meeting.id,
+        start_utc: session.turn_started_at,
+        end_utc: Utc::now(),
+        marker: None,
+        deleted_at: None,
+        version: 0,
+    };
+
+    let result = db
+        .collection::<UserTime>("user_times")
+        .insert_one(&user_time, None)
+        .await?;
+    user_time.id = result.inserted_id.as_object_id();
+
+    let view = UserTimeView::from(user_time.clone());
+    webhooks::emit_for_meeting(db, meeting, WebhookEvent::USER_TIME_RECORDED, None, &view).await;
+
+    Ok(user_time)
+}
+
+async fn start(db: &Database, user: &User, team: &Team) -> Result<Value, Error> {
+    if let Some((session, _)) = running_session(db, team).await? {
+        let speaking = match session.speaking_order.get(session.current as usize) {
+            Some(user_id) => {
+                let names = user_names(db, &[*user_id]).await?;
+                format!(
+                    ", *{}* is speaking",
+                    names.get(user_id).cloned().unwrap_or_default()
+                )
+            }
+            None => String::new(),
+        };
+        return Ok(ephemeral(format!(
+            "The meeting is already running{}",
+            speaking
+        )));
+    }
+
+    let configs_id: Vec<ObjectId> = db
+        .collection::<MeetingConfig>("meeting_configs")
+        .find(
+            doc! { "team_id": team.id.unwrap(), "deleted_at": null },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<MeetingConfig>>()
+        .await?
+        .into_iter()
+        .map(|config| config.id.unwrap())
+        .collect();
+
+    let now = Utc::now();
+    let window = Duration::hours(START_WINDOW_HOURS);
+    let meeting = db
+        .collection::<Meeting>("meetings")
+        .find(
+            doc! {
+                "config_id": { "$in": configs_id },
+                "status": "SCHEDULED",
+                "date_utc": {
+                    "$gte": (now - window).timestamp_millis(),
+                    "$lte": (now + window).timestamp_millis()
+                },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<Meeting>>()
+        .await?
+        .into_iter()
+        .min_by_key(|meeting| (meeting.date_utc - now).num_milliseconds().abs());
+    let meeting = match meeting {
+        Some(meeting) => meeting,
+        None => {
+            return Ok(ephemeral(format!(
+                "{} has no meeting scheduled around now",
+                slack::escape(&team.name)
+            )))
+        }
+    };
+
+    let (speaking_order, absent) = session_members(db, &meeting, team).await?;
+    if speaking_order.is_empty() {
+        return Ok(ephemeral("Nobody in the team is available to speak today"));
+    }
+
+    let session = ChatSession {
+        id: None,
+        team_id: team.id.unwrap(),
+        meeting_id: meeting.id.unwrap(),
+        started_by: user.id.unwrap(),
+        speaking_order: speaking_order
+            .iter()
+            .map(|participant| ObjectId::parse_str(&participant.user_id).unwrap())
+            .collect(),
+        current: 0,
+        started_at: now,
+        turn_started_at: now,
+    };
+    match db
+        .collection::<ChatSession>("chat_sessions")
+        .insert_one(&session, None)
+        .await
+    {
+        Ok(_) => {}
+        Err(error) if is_duplicate_key(&error) => {
+            return Ok(ephemeral("The meeting was just started by someone else"))
+        }
+        Err(error) => return Err(error),
+    }
+
+    meeting_event(db, &meeting, WebhookEvent::MEETING_STARTED).await;
+
+    let order: Vec<String> = speaking_order
+        .iter()
+        .map(|participant| slack::escape(&participant.name))
+        .collect();
+    let mut text = format!(
+        "*{}* started by {}\nSpeaking order: {}",
+        config_name(db, &meeting).await?,
+        slack::escape(&user.name),
+        order.join(" → ")
+    );
+    if !absent.is_empty() {
+        let absent: Vec<String> = absent
+            .iter()
+            .map(|member| slack::escape(&member.name))
+            .collect();
+        text.push_str(&format!("\nOut today: {}", absent.join(", ")));
+    }
+    text.push_str(&format!(
+        "\n:microphone: *{}*, you're up. `/standup next` when you're done",
+        order[0]
+    ));
+
+    Ok(in_channel(text))
+}
+
+async fn next(db: &Database, team: &Team) -> Result<Value, Error> {
+    let (session, meeting) = match running_session(db, team).await? {
+        Some(running) => running,
+        None => {
+            return Ok(ephemeral(
+                "No meeting is running, `/standup start` starts one",
+            ))
+        }
+    };
+
+    let speaker = match session.speaking_order.get(session.current as usize) {
+        Some(user_id) => *user_id,
+        None => {
+            return Ok(ephemeral(
+                "Everyone spoke, `/standup finish` ends the meeting",
+            ))
+        }
+    };
+
+    // only one of several commands typed at the same time passes the word on
+    let now = Utc::now();
+    let result = db
+        .collection::<ChatSession>("chat_sessions")
+        .update_one(
+            doc! { "_id": session.id.unwrap(), "current": session.current },
+            doc! {
+                "$inc": { "current": 1 },
+                "$set": { "turn_started_at": now.timestamp_millis() }
+            },
+            None,
+        )
+        .await?;
+    if result.modified_count == 0 {
+        return Ok(ephemeral(
+            "Someone else passed the word on at the same time",
+        ));
+    }
+
+    let turn = record_turn(db, &meeting, &session, speaker).await?;
+    let names = user_names(db, &session.speaking_order).await?;
+    let name = |user_id: &ObjectId| names.get(user_id).cloned().unwrap_or_default();
+
+    let mut text = format!("{} spoke for {}", name(&speaker), clock(turn.duration_ms()));
+    match session.speaking_order.get(session.current as usize + 1) {
+        Some(next) => text.push_str(&format!("\n:microphone: *{}*, you're up", name(next))),
+        None => text.push_str("\nEveryone spoke, `/standup finish` ends the meeting"),
+    }
+
+    Ok(in_channel(text))
+}
+
+async fn finish(db: &Database, team: &Team) -> Result<Value, Error> {
+    let (session, meeting) = match running_session(db, team).await? {
+        Some(running) => running,
+        None => {
+            return Ok(ephemeral(
+                "No meeting is running, `/standup start` starts one",
+            ))
+        }
+    };
+
+    // deleting the session first, a meeting is only finished once
+    let deleted = db
+        .collection::<ChatSession>("chat_sessions")
+        .delete_one(doc! { "_id": session.id.unwrap() }, None)
+        .await?;
+    if deleted.deleted_count == 0 {
+        return Ok(ephemeral("The meeting was just finished by someone else"));
+    }
+
+    if let Some(speaker) = session.speaking_order.get(session.current as usize) {
+        record_turn(db, &meeting, &session, *speaker).await?;
+    }
+
+    let meeting = db
+        .collection::<Meeting>("meetings")
+        .find_one_and_update(
+            doc! { "_id": meeting.id.unwrap(), "status": "SCHEDULED", "deleted_at": null },
+            doc! {
+                "$set": {
+                    "status": "HELD",
+                    "date_utc": session.started_at.timestamp_millis(),
+                    "end_utc": Utc::now().timestamp_millis()
+                },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?;
+    let meeting = match meeting {
+        Some(meeting) => meeting,
+        None => return Ok(ephemeral("The meeting was closed or deleted meanwhile")),
+    };
+
+    meeting_event(db, &meeting, WebhookEvent::MEETING_FINISHED).await;
+
+    match summarize(db, &meeting).await? {
+        Some((_, summary)) => {
+            let mut reply = slack::message(&summary);
+            reply["response_type"] = json!("in_channel");
+            Ok(reply)
+        }
+        None => Ok(in_channel(format!(
+            "The meeting finished after {}",
+            clock(meeting.duration_ms())
+        ))),
+    }
+}
+
+async fn stats(db: &Database, team: &Team) -> Result<Value, Error> {
+    if let Some((session, meeting)) = running_session(db, team).await? {
+        let now = Utc::now();
+        let names = user_names(db, &session.speaking_order).await?;
+        let mut text = format!(
+            "*{}* running for {}: {} of {} spoke",
+            config_name(db, &meeting).await?,
+            clock((now - session.started_at).num_milliseconds()),
+            session.current.min(session.speaking_order.len() as i32),
+            session.speaking_order.len()
+        );
+        if let Some(speaker) = session.speaking_order.get(session.current as usize) {
+            text.push_str(&format!(
+                "\n:microphone: *{}* is speaking for {}",
+                names.get(speaker).cloned().unwrap_or_default(),
+                clock((now - session.turn_started_at).num_milliseconds())
+            ));
+        }
+        return Ok(ephemeral(text));
+    }
+
+    let configs_id: Vec<ObjectId> = db
+        .collection::<MeetingConfig>("meeting_configs")
+        .find(
+            doc! { "team_id": team.id.unwrap(), "deleted_at": null },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<MeetingConfig>>()
+        .await?
+        .into_iter()
+        .map(|config| config.id.unwrap())
+        .collect();
+
+    let mut filter = doc! { "config_id": { "$in": configs_id }, "deleted_at": null };
+    filter.extend(MeetingStatus::HELD.filter());
+    let last = db
+        .collection::<Meeting>("meetings")
+        .find_one(
+            filter,
+            FindOneOptions::builder()
+                .sort(doc! { "date_utc": -1 })
+                .build(),
+        )
+        .await?;
+
+    let summary = match last {
+        Some(meeting) => summarize(db, &meeting).await?,
+        None => None,
+    };
+    match summary {
+        Some((_, summary)) => {
+            let mut reply = slack::message(&summary);
+            reply["response_type"] = json!("ephemeral");
+            Ok(reply)
+        }
+        None => Ok(ephemeral(format!(
+            "{} hasn't held any meeting yet",
+            slack::escape(&team.name)
+        ))),
+    }
+}
+
+/// Runs the command typed by a Slack user, returning the reply to show
+pub async fn run(db: &Database, slack_user_id: &str, text: &str) -> Result<Value, Error> {
+    let (action, team) = parse(text);
+    if action == Action::Help {
+        return Ok(ephemeral(USAGE));
+    }
+
+    let user = match find_user(db, slack_user_id).await? {
+        Ok(user) => user,
+        Err(reply) => return Ok(reply),
+    };
+    let team = match find_team(db, &user, team.as_deref()).await? {
+        Ok(team) => team,
+        Err(reply) => return Ok(reply),
+    };
+
+    match action {
+        Action::Start => start(db, &user, &team).await,
+        Action::Next => next(db, &team).await,
+        Action::Finish => finish(db, &team).await,
+        Action::Stats => stats(db, &team).await,
+        Action::Help => Ok(ephemeral(USAGE)),
+    }
+}
@@ -0,0 +1,423 @@
+//! Summaries of the meetings posted to the channels of the teams, through the
+//! incoming webhook configured in their chat settings (Slack or Microsoft Teams).
+//!
+//! The `post_meeting_summaries` job posts the summary of every daily a few
+//! minutes after it ends ([`SUMMARY_DELAY_MINUTES`]), so the speaking times
+//! recorded right after the meeting are included. A failed post is retried on the
+//! next runs up to [`MAX_ATTEMPTS`] times.
+//!
+//! Meetings can also be run from Slack with the slash commands of [`commands`].
+
+use std::collections::HashMap;
+
+use bson::{doc, oid::ObjectId, to_bson};
+use chrono::{Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{error::Error, Database};
+use rocket::serde::json::Value;
+
+use crate::models::{
+    absence::{absences_on, AbsenceKind},
+    attendance::{Attendance, AttendanceStatus},
+    chat::{ChatFormat, ChatSummary, SummaryStatus},
+    meeting::{Meeting, MeetingStatus},
+    meeting_config::{MeetingConfig, MeetingType},
+    team::Team,
+    user::User,
+    user_time::UserTime,
+};
+use crate::utils::outbound::client_for;
+
+pub mod commands;
+pub mod slack;
+mod teams;
+
+/// Minutes after the end of a daily its summary is posted
+pub const SUMMARY_DELAY_MINUTES: i64 = 5;
+
+/// Hours after the end of a daily its summary isn't posted anymore
+const SUMMARY_WINDOW_HOURS: i64 = 24;
+
+/// Attempts to post a summary before giving up
+pub const MAX_ATTEMPTS: i32 = 5;
+
+/// Seconds to wait for the webhook of the channel or the API of the chat
+const TIMEOUT_SECONDS: u64 = 10;
+
+/// Time spoken by a participant, adding up all of their turns
+#[derive(Debug, Clone)]
+pub struct Speaker {
+    pub name: String,
+    pub turns: u32,
+    pub time_ms: i64,
+    /// Whether they spoke longer than their share of the desired duration
+    pub overran: bool,
+}
+
+/// Member of the team that didn't attend
+#[derive(Debug, Clone)]
+pub struct AbsentMember {
+    pub name: String,
+    /// vacation, sick, out of office, absent or excused
+    pub reason: &'static str,
+}
+
+/// What the summary of a meeting tells
+#[derive(Debug, Clone)]
+pub struct MeetingSummary {
+    pub team_name: String,
+    pub config_name: String,
+    /// Local start of the meeting (`YYYY-MM-DD HH:MM`)
+    pub date: String,
+    pub timezone: String,
+    pub duration_ms: i64,
+    pub desired_duration_ms: i64,
+    /// Time each expected participant has to stay within the desired duration
+    pub share_ms: i64,
+    /// In the order they first spoke
+    pub speakers: Vec<Speaker>,
+    pub absent: Vec<AbsentMember>,
+}
+
+impl MeetingSummary {
+    /// Milliseconds the meeting lasted over the desired duration, negative if it
+    /// ended earlier
+    pub fn overrun_ms(&self) -> i64 {
+        self.duration_ms - self.desired_duration_ms
+    }
+}
+
+/// Duration as `Xm YYs`
+pub(crate) fn clock(ms: i64) -> String {
+    let seconds = ms.abs() / 1000;
+    format!("{}m {:02}s", seconds / 60, seconds % 60)
+}
+
+fn absence_reason(kind: &AbsenceKind) -> &'static str {
+    match kind {
+        AbsenceKind::VACATION => "vacation",
+        AbsenceKind::SICK => "sick",
+        AbsenceKind::OTHER => "out of office",
+    }
+}
+
+/// Summary of a meeting with the team it's posted to, `None` if the meeting has
+/// no active config or team
+pub async fn summarize(
+    db: &Database,
+    meeting: &Meeting,
+) -> Result<Option<(Team, MeetingSummary)>, Error> {
+    let config = match meeting.config_id {
+        Some(config_id) => {
+            db.collection::<MeetingConfig>("meeting_configs")
+                .find_one(doc! { "_id": config_id, "deleted_at": null }, None)
+                .await?
+        }
+        None => None,
+    };
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+    let team = match config.team_id {
+        Some(team_id) => {
+            db.collection::<Team>("teams")
+                .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+                .await?
+        }
+        None => None,
+    };
+    let team = match team {
+        Some(team) => team,
+        None => return Ok(None),
+    };
+
+    let meeting_id = meeting.id.unwrap();
+    let opts = FindOptions::builder()
+        .sort(doc! { "start_utc": 1, "_id": 1 })
+        .build();
+    let user_times = db
+        .collection::<UserTime>("user_times")
+        .find(doc! { "meeting_id": meeting_id, "deleted_at": null }, opts)
+        .await?
+        .try_collect::<Vec<UserTime>>()
+        .await?;
+
+    // members and speakers that left the team since are named too
+    let members_id = team.users.clone().unwrap_or_default();
+    let mut users_id: Vec<ObjectId> = user_times.iter().filter_map(|t| t.user_id).collect();
+    users_id.extend(&members_id);
+    let names: HashMap<ObjectId, String> = db
+        .collection::<User>("users")
+        .find(
+            doc! { "_id": { "$in": users_id }, "deleted_at": null },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<User>>()
+        .await?
+        .into_iter()
+        .map(|user| (user.id.unwrap(), user.name))
+        .collect();
+
+    // out of office on the local day of the meeting, or recorded as absent
+    let calendar = team.calendar();
+    let day = calendar.local(meeting.date_utc).date_naive();
+    let mut absent_id: Vec<ObjectId> = vec![];
+    let mut absent: Vec<AbsentMember> = vec![];
+    for absence in absences_on(db, &members_id, day).await? {
+        if let Some(name) = names.get(&absence.user_id) {
+            absent_id.push(absence.user_id);
+            absent.push(AbsentMember {
+                name: name.clone(),
+                reason: absence_reason(&absence.kind),
+            });
+        }
+    }
+    let attendance = db
+        .collection::<Attendance>("attendances")
+        .find(
+            doc! {
+                "meeting_id": meeting_id,
+                "status": { "$in": ["ABSENT", "EXCUSED"] },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<Attendance>>()
+        .await?;
+    for record in attendance {
+        if let (Some(name), false) = (
+            names.get(&record.user_id),
+            absent_id.contains(&record.user_id),
+        ) {
+            absent_id.push(record.user_id);
+            absent.push(AbsentMember {
+                name: name.clone(),
+                reason: match record.status {
+                    AttendanceStatus::EXCUSED => "excused",
+                    _ => "absent",
+                },
+            });
+        }
+    }
+
+    // add up the turns of every user, in the order they first spoke
+    let mut speakers: Vec<(ObjectId, Speaker)> = vec![];
+    for user_time in &user_times {
+        let user_id = user_time.user_id.unwrap();
+        let position = match speakers.iter().position(|(id, _)| *id == user_id) {
+            Some(position) => position,
+            None => {
+                speakers.push((
+                    user_id,
+                    Speaker {
+                        name: names.get(&user_id).cloned().unwrap_or_default(),
+                        turns: 0,
+                        time_ms: 0,
+                        overran: false,
+                    },
+                ));
+                speakers.len() - 1
+            }
+        };
+
+        let speaker = &mut speakers[position].1;
+        speaker.turns += 1;
+        speaker.time_ms += user_time.duration_ms();
+    }
+
+    // the desired duration is shared between the members expected to speak
+    let expected = members_id
+        .iter()
+        .filter(|id| names.contains_key(id) && !absent_id.contains(id))
+        .count()
+        .max(speakers.len())
+        .max(1);
+    let desired_duration_ms = config.desired_duration * 1000;
+    let share_ms = desired_duration_ms / expected as i64;
+    let speakers = speakers
+        .into_iter()
+        .map(|(_, mut speaker)| {
+            speaker.overran = speaker.time_ms > share_ms;
+            speaker
+        })
+        .collect();
+
+    let summary = MeetingSummary {
+        team_name: team.name.clone(),
+        config_name: config.config_name,
+        date: calendar
+            .local(meeting.date_utc)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        timezone: calendar.timezone().to_owned(),
+        duration_ms: meeting.duration_ms(),
+        desired_duration_ms,
+        share_ms,
+        speakers,
+        absent,
+    };
+
+    Ok(Some((team, summary)))
+}
+
+/// Message of the summary in the format of the chat
+pub fn render(format: ChatFormat, summary: &MeetingSummary) -> Value {
+    match format {
+        ChatFormat::SLACK => slack::message(summary),
+        ChatFormat::TEAMS => teams::message(summary),
+    }
+}
+
+/// Posts a message to an incoming webhook
+async fn send(url: &str, message: &Value) -> Result<(), String> {
+    let client = client_for(url, std::time::Duration::from_secs(TIMEOUT_SECONDS)).await?;
+
+    let response = client
+        .post(url)
+        .json(message)
+        .send()
+        .await
+        .map_err(|error| error.to_string())?;
+
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(format!("the webhook answered {}", status)),
+    }
+}
+
+/// Posts the summary of a meeting to the channel of its team and records the
+/// attempt. `None` if the meeting has no team or its team has no chat settings.
+pub async fn post_summary(db: &Database, meeting: &Meeting) -> Result<Option<ChatSummary>, Error> {
+    let (team, summary) = match summarize(db, meeting).await? {
+        Some(summary) => summary,
+        None => return Ok(None),
+    };
+    let settings = match &team.chat {
+        Some(settings) => settings,
+        None => return Ok(None),
+    };
+
+    let result = send(&settings.webhook_url, &render(settings.format, &summary)).await;
+    if let Err(error) = &result {
+        eprintln!("[CHAT][{}] ~ {}", team.name, error);
+    }
+
+    let now = Utc::now();
+    let mut changes = doc! {
+        "team_id": team.id.unwrap(),
+        "format": to_bson(&settings.format).unwrap(),
+        "updated_at": now.timestamp_millis()
+    };
+    match result {
+        Ok(()) => {
+            changes.insert("status", "POSTED");
+            changes.insert("last_error", bson::Bson::Null);
+            changes.insert("posted_at", now.timestamp_millis());
+        }
+        Err(error) => {
+            changes.insert("status", "FAILED");
+            changes.insert("last_error", error);
+        }
+    }
+
+    db.collection::<ChatSummary>("chat_summaries")
+        .find_one_and_update(
+            doc! { "meeting_id": meeting.id.unwrap() },
+            doc! { "$set": changes, "$inc": { "attempts": 1 } },
+            FindOneAndUpdateOptions::builder()
+                .upsert(true)
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+}
+
+/// Amount of summaries posted and failed by a run of [`post_due`]
+#[derive(Debug, Default)]
+pub struct SummaryReport {
+    pub posted: u64,
+    pub failed: u64,
+}
+
+/// Posts the summaries of the dailies that ended a few minutes ago and weren't
+/// posted yet
+pub async fn post_due(db: &Database) -> Result<SummaryReport, Error> {
+    let now = Utc::now();
+    let mut filter = doc! {
+        "end_utc": {
+            "$gte": (now - Duration::hours(SUMMARY_WINDOW_HOURS)).timestamp_millis(),
+            "$lte": (now - Duration::minutes(SUMMARY_DELAY_MINUTES)).timestamp_millis()
+        },
+        "deleted_at": null
+    };
+    filter.extend(MeetingStatus::HELD.filter());
+    let meetings = db
+        .collection::<Meeting>("meetings")
+        .find(filter, None)
+        .await?
+        .try_collect::<Vec<Meeting>>()
+        .await?;
+
+    let mut report = SummaryReport::default();
+    if meetings.is_empty() {
+        return Ok(report);
+    }
+
+    // posted already, or given up on
+    let meetings_id: Vec<ObjectId> = meetings.iter().map(|meeting| meeting.id.unwrap()).collect();
+    let done: Vec<ObjectId> = db
+        .collection::<ChatSummary>("chat_summaries")
+        .find(
+            doc! {
+                "meeting_id": { "$in": meetings_id },
+                "$or": [{ "status": "POSTED" }, { "attempts": { "$gte": MAX_ATTEMPTS } }]
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<ChatSummary>>()
+        .await?
+        .into_iter()
+        .map(|summary| summary.meeting_id)
+        .collect();
+
+    let configs_id: Vec<ObjectId> = meetings
+        .iter()
+        .filter_map(|meeting| meeting.config_id)
+        .collect();
+    let dailies: Vec<ObjectId> = db
+        .collection::<MeetingConfig>("meeting_configs")
+        .find(
+            doc! {
+                "_id": { "$in": configs_id },
+                "meeting_type": MeetingType::DAILY.value(),
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<MeetingConfig>>()
+        .await?
+        .into_iter()
+        .map(|config| config.id.unwrap())
+        .collect();
+
+    for meeting in meetings {
+        let is_daily = meeting.config_id.is_some_and(|id| dailies.contains(&id));
+        if !is_daily || done.contains(&meeting.id.unwrap()) {
+            continue;
+        }
+
+        match post_summary(db, &meeting).await? {
+            Some(summary) if summary.status == SummaryStatus::POSTED => report.posted += 1,
+            Some(_) => report.failed += 1,
+            None => {}
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,162 @@
+//! Summaries as Slack Block Kit messages
+//! (<https://api.slack.com/reference/block-kit/blocks>), and the requests of the
+//! slash commands
+
+use hmac::{Hmac, Mac};
+use rocket::serde::json::{json, Value};
+use sha2::Sha256;
+
+use super::{clock, MeetingSummary, TIMEOUT_SECONDS};
+
+/// Base URL of the Web API of Slack
+const API_URL: &str = "https://slack.com/api";
+
+/// Escapes the characters with a meaning in `mrkdwn`
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn duration(summary: &MeetingSummary) -> String {
+    let overrun_ms = summary.overrun_ms();
+    if overrun_ms > 0 {
+        format!(
+            "{} (:warning: {} over)",
+            clock(summary.duration_ms),
+            clock(overrun_ms)
+        )
+    } else {
+        clock(summary.duration_ms)
+    }
+}
+
+pub fn message(summary: &MeetingSummary) -> Value {
+    let title = format!("{} · {}", summary.config_name, summary.team_name);
+
+    let speakers: Vec<String> = summary
+        .speakers
+        .iter()
+        .map(|speaker| {
+            let overran = if speaker.overran {
+                format!(
+                    " :warning: {} over",
+                    clock(speaker.time_ms - summary.share_ms)
+                )
+            } else {
+                String::new()
+            };
+            format!(
+                "• *{}*: {} ({} turns){}",
+                escape(&speaker.name),
+                clock(speaker.time_ms),
+                speaker.turns,
+                overran
+            )
+        })
+        .collect();
+    let speakers = if speakers.is_empty() {
+        "_No speaking times recorded_".to_owned()
+    } else {
+        speakers.join("\n")
+    };
+
+    let absent: Vec<String> = summary
+        .absent
+        .iter()
+        .map(|member| format!("{} ({})", escape(&member.name), member.reason))
+        .collect();
+    let absent = if absent.is_empty() {
+        "Nobody was absent".to_owned()
+    } else {
+        format!("Absent: {}", absent.join(", "))
+    };
+
+    json!({
+        "text": format!("{}: {} of {}", title, clock(summary.duration_ms), clock(summary.desired_duration_ms)),
+        "blocks": [
+            {
+                "type": "header",
+                "text": { "type": "plain_text", "text": title }
+            },
+            {
+                "type": "context",
+                "elements": [
+                    { "type": "mrkdwn", "text": format!("{} ({})", summary.date, summary.timezone) }
+                ]
+            },
+            {
+                "type": "section",
+                "fields": [
+                    { "type": "mrkdwn", "text": format!("*Duration*\n{}", duration(summary)) },
+                    { "type": "mrkdwn", "text": format!("*Desired duration*\n{}", clock(summary.desired_duration_ms)) }
+                ]
+            },
+            {
+                "type": "section",
+                "text": {
+                    "type": "mrkdwn",
+                    "text": format!("*Speaking times* (share of {} each)\n{}", clock(summary.share_ms), speakers)
+                }
+            },
+            {
+                "type": "context",
+                "elements": [{ "type": "mrkdwn", "text": absent }]
+            }
+        ]
+    })
+}
+
+/// Whether `signature` (`v0=<hex>`) is the HMAC-SHA256 of `v0:<timestamp>:<body>`
+/// keyed with the signing secret of the Slack app
+pub fn verify(secret: &str, timestamp: &str, body: &str, signature: &str) -> bool {
+    let hex = match signature.strip_prefix("v0=") {
+        Some(hex) if hex.len() % 2 == 0 => hex,
+        _ => return false,
+    };
+    let expected: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return false,
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(b"v0:");
+    mac.update(timestamp.as_bytes());
+    mac.update(b":");
+    mac.update(body.as_bytes());
+
+    // compared in constant time
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Email of a Slack user, `None` if their profile doesn't show it. The bot token
+/// needs the `users:read.email` scope.
+pub async fn user_email(token: &str, user_id: &str) -> Result<Option<String>, String> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(TIMEOUT_SECONDS))
+        .build()
+        .unwrap();
+
+    let response: Value = client
+        .get(format!("{}/users.info", API_URL))
+        .bearer_auth(token)
+        .query(&[("user", user_id)])
+        .send()
+        .await
+        .map_err(|error| error.to_string())?
+        .json()
+        .await
+        .map_err(|error| error.to_string())?;
+
+    if response["ok"] != json!(true) {
+        return Err(format!("users.info failed: {}", response["error"]));
+    }
+
+    Ok(response["user"]["profile"]["email"]
+        .as_str()
+        .map(|email| email.to_owned()))
+}
@@ -0,0 +1,106 @@
+//! Summaries as Microsoft Teams messages with an Adaptive Card
+//! (<https://adaptivecards.io/explorer/>)
+
+use rocket::serde::json::{json, Value};
+
+use super::{clock, MeetingSummary};
+
+fn duration(summary: &MeetingSummary) -> String {
+    let overrun_ms = summary.overrun_ms();
+    if overrun_ms > 0 {
+        format!(
+            "{} (⚠️ {} over)",
+            clock(summary.duration_ms),
+            clock(overrun_ms)
+        )
+    } else {
+        clock(summary.duration_ms)
+    }
+}
+
+pub fn message(summary: &MeetingSummary) -> Value {
+    let speakers: Vec<Value> = summary
+        .speakers
+        .iter()
+        .map(|speaker| {
+            let overran = if speaker.overran {
+                format!(" ⚠️ {} over", clock(speaker.time_ms - summary.share_ms))
+            } else {
+                String::new()
+            };
+            json!({
+                "title": speaker.name,
+                "value": format!("{} ({} turns){}", clock(speaker.time_ms), speaker.turns, overran)
+            })
+        })
+        .collect();
+
+    let mut body = vec![
+        json!({
+            "type": "TextBlock",
+            "size": "Large",
+            "weight": "Bolder",
+            "wrap": true,
+            "text": format!("{} · {}", summary.config_name, summary.team_name)
+        }),
+        json!({
+            "type": "TextBlock",
+            "isSubtle": true,
+            "spacing": "None",
+            "text": format!("{} ({})", summary.date, summary.timezone)
+        }),
+        json!({
+            "type": "FactSet",
+            "facts": [
+                { "title": "Duration", "value": duration(summary) },
+                { "title": "Desired duration", "value": clock(summary.desired_duration_ms) }
+            ]
+        }),
+        json!({
+            "type": "TextBlock",
+            "weight": "Bolder",
+            "text": format!("Speaking times (share of {} each)", clock(summary.share_ms))
+        }),
+    ];
+
+    if speakers.is_empty() {
+        body.push(json!({
+            "type": "TextBlock",
+            "isSubtle": true,
+            "text": "No speaking times recorded"
+        }));
+    } else {
+        body.push(json!({ "type": "FactSet", "facts": speakers }));
+    }
+
+    let absent: Vec<String> = summary
+        .absent
+        .iter()
+        .map(|member| format!("{} ({})", member.name, member.reason))
+        .collect();
+    body.push(json!({
+        "type": "TextBlock",
+        "isSubtle": true,
+        "wrap": true,
+        "text": if absent.is_empty() {
+            "Nobody was absent".to_owned()
+        } else {
+            format!("Absent: {}", absent.join(", "))
+        }
+    }));
+
+    json!({
+        "type": "message",
+        "attachments": [
+            {
+                "contentType": "application/vnd.microsoft.card.adaptive",
+                "content": {
+                    "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                    "type": "AdaptiveCard",
+                    "version": "1.4",
+                    "body": body
+                }
+            }
+        ]
+    })
+}
@@ -2,6 +2,7 @@ use async_trait::async_trait;
 use deadpool::managed;
 use mongodb::options::ClientOptions;
 
+#[derive(Default)]
 pub struct PoolManager {}
 
 impl PoolManager {
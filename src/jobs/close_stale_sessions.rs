@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use bson::doc;
+use chrono::{Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::{error::Error, Database};
+
+use super::{Job, JobContext};
+use crate::models::{
+    attendance::Attendance,
+    chat::ChatSession,
+    meeting::{meeting_event, Meeting, MeetingStatus},
+    user_time::UserTime,
+    webhook::WebhookEvent,
+};
+
+/// Hours after the planned end a meeting still scheduled is considered abandoned
+const STALE_AFTER_HOURS: i64 = 2;
+
+/// Days back the abandoned meetings are looked for, older ones never took place
+const STALE_WINDOW_DAYS: i64 = 7;
+
+/// Closes the live sessions that were never finished: the scheduled meetings with
+/// speaking turns or attendance recorded are marked as held, with the dates of
+/// their first and last turns when they have any. The ones without anything
+/// recorded didn't take place and stay scheduled.
+pub struct CloseStaleSessions;
+
+async fn close(db: &Database) -> Result<u64, Error> {
+    let now = Utc::now();
+    let meetings = db
+        .collection::<Meeting>("meetings")
+        .find(
+            doc! {
+                "status": "SCHEDULED",
+                "end_utc": {
+                    "$gte": (now - Duration::days(STALE_WINDOW_DAYS)).timestamp_millis(),
+                    "$lt": (now - Duration::hours(STALE_AFTER_HOURS)).timestamp_millis()
+                },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<Meeting>>()
+        .await?;
+
+    let mut closed = 0;
+    for mut meeting in meetings {
+        let meeting_id = meeting.id.unwrap();
+
+        let user_times = db
+            .collection::<UserTime>("user_times")
+            .find(doc! { "meeting_id": meeting_id, "deleted_at": null }, None)
+            .await?
+            .try_collect::<Vec<UserTime>>()
+            .await?;
+        let has_attendance = db
+            .collection::<Attendance>("attendances")
+            .find_one(doc! { "meeting_id": meeting_id, "deleted_at": null }, None)
+            .await?
+            .is_some();
+
+        if user_times.is_empty() && !has_attendance {
+            continue;
+        }
+
+        let date_utc = user_times
+            .iter()
+            .map(|user_time| user_time.start_utc)
+            .min()
+            .unwrap_or(meeting.date_utc);
+        let end_utc = user_times
+            .iter()
+            .map(|user_time| user_time.end_utc)
+            .max()
+            .unwrap_or(meeting.end_utc);
+
+        // a meeting recorded meanwhile is left as it is
+        let result = db
+            .collection::<Meeting>("meetings")
+            .update_one(
+                doc! { "_id": meeting_id, "status": "SCHEDULED", "version": meeting.version },
+                doc! {
+                    "$set": {
+                        "status": "HELD",
+                        "date_utc": date_utc.timestamp_millis(),
+                        "end_utc": end_utc.timestamp_millis()
+                    },
+                    "$inc": { "version": 1 }
+                },
+                None,
+            )
+            .await?;
+        if result.modified_count == 0 {
+            continue;
+        }
+
+        // the meeting can't be finished from the chat anymore
+        db.collection::<ChatSession>("chat_sessions")
+            .delete_many(doc! { "meeting_id": meeting_id }, None)
+            .await?;
+
+        closed += 1;
+        meeting.status = MeetingStatus::HELD;
+        meeting.date_utc = date_utc;
+        meeting.end_utc = end_utc;
+        meeting.version += 1;
+        meeting_event(db, &meeting, WebhookEvent::MEETING_FINISHED).await;
+    }
+
+    Ok(closed)
+}
+
+#[async_trait]
+impl Job for CloseStaleSessions {
+    fn name(&self) -> &'static str {
+        "close_stale_sessions"
+    }
+
+    fn description(&self) -> &'static str {
+        "Marks as held the scheduled meetings whose session was started but never finished"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "*/15 * * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        match close(&ctx.db()).await {
+            Ok(closed) => Ok(format!("{} sessions closed", closed)),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, NaiveDate, TimeZone, Timelike, Utc};
+
+/// Years searched for the next run before giving up (`0 0 30 2 *` never runs)
+const MAX_SEARCH_YEARS: i64 = 5;
+
+/// Cron expression with the 5 usual fields, evaluated in UTC: minute (0-59), hour
+/// (0-23), day of the month (1-31), month (1-12) and day of the week (0-7, both 0
+/// and 7 are Sunday). Every field accepts `*`, values, ranges (`1-5`), lists
+/// (`1,15`) and steps (`*/15`, `0-30/10`). As in cron, when both days are
+/// restricted a day matching either of them matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    weekdays: BTreeSet<u32>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Values of a field between `min` and `max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>, String> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step in {}", part)),
+            },
+            None => (part, 1),
+        };
+
+        let parse = |value: &str| match value.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(format!("{} isn't between {} and {}", value, min, max)),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                // a single value with a step goes until the end (`5/15`)
+                None if step > 1 => (parse(range)?, max),
+                None => (parse(range)?, parse(range)?),
+            },
+        };
+
+        if start > end {
+            return Err(format!("the range {} is reversed", range));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    Ok(values)
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err("a cron expression has 5 fields".to_owned());
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        if weekdays.remove(&7) {
+            weekdays.insert(0);
+        }
+
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+impl Cron {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if !self.months.contains(&date.month()) {
+            return false;
+        }
+
+        let day = self.days.contains(&date.day());
+        let weekday = self.weekdays.contains(&date.weekday().num_days_from_sunday());
+
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// First minute strictly after `after` when the expression matches, `None` if it
+    /// doesn't match in the next years
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.duration_trunc(Duration::minutes(1)).ok()? + Duration::minutes(1);
+        let first_day = start.date_naive();
+
+        first_day
+            .iter_days()
+            .take_while(|date| (*date - first_day).num_days() < MAX_SEARCH_YEARS * 366)
+            .filter(|date| self.matches_day(*date))
+            .find_map(|date| {
+                // only the first day is restricted to the times after the start
+                let (from_hour, from_minute) = if date == first_day {
+                    (start.hour(), start.minute())
+                } else {
+                    (0, 0)
+                };
+
+                self.hours.range(from_hour..).find_map(|hour| {
+                    let from = if *hour == from_hour { from_minute } else { 0 };
+                    let minute = self.minutes.range(from..).next()?;
+                    Some(Utc.from_utc_datetime(&date.and_hms_opt(*hour, *minute, 0)?))
+                })
+            })
+    }
+}
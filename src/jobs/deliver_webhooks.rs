@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+
+use super::{Job, JobContext};
+use crate::webhooks::deliver_due;
+
+/// Posts the deliveries of the webhooks that are due, first attempts and retries
+pub struct DeliverWebhooks;
+
+#[async_trait]
+impl Job for DeliverWebhooks {
+    fn name(&self) -> &'static str {
+        "deliver_webhooks"
+    }
+
+    fn description(&self) -> &'static str {
+        "Posts the events waiting in the outbox of the webhooks, retrying the failed ones"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "* * * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        match deliver_due(&ctx.db()).await {
+            Ok(report) => Ok(format!(
+                "{} deliveries delivered, {} to retry, {} failed",
+                report.delivered, report.retried, report.failed
+            )),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
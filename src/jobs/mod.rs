@@ -0,0 +1,378 @@
+//! Background jobs run by the API on a cron schedule.
+//!
+//! Every instance of the API runs a [`Scheduler`], the state of the jobs is shared
+//! through the `jobs` collection: when a job is due, the first instance to lock it
+//! runs it and moves its next run forward, so it runs once whatever the amount of
+//! instances. A lock is renewed while the job runs and released when it finishes,
+//! so it only expires [`LOCK_LEASE_MINUTES`] after the instance died while running
+//! it. Every job runs in its own task, a slow one doesn't delay the others.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use bson::doc;
+use chrono::serde::ts_milliseconds_option;
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions};
+use mongodb::{error::Error, Client, Database};
+use rocket::tokio::{self, sync::watch, task::JoinHandle};
+use serde::{Deserialize, Serialize};
+
+use crate::mailer::SharedMailer;
+use crate::notifier::SharedNotifier;
+use crate::utils::token;
+
+pub mod cron;
+mod close_stale_sessions;
+mod deliver_webhooks;
+mod notify_overdue_action_items;
+mod post_meeting_summaries;
+mod purge_trash;
+mod schedule_meetings;
+mod send_reminders;
+
+use cron::Cron;
+
+/// Seconds between two checks of the due jobs
+const TICK_SECONDS: u64 = 30;
+
+/// Minutes a job stays locked by the instance running it without renewing the lock
+pub const LOCK_LEASE_MINUTES: i64 = 15;
+
+/// Times the lock is renewed during its lease, so a slow renewal doesn't lose it
+const RENEWALS_PER_LEASE: u64 = 3;
+
+/// What the jobs can use
+#[derive(Clone)]
+pub struct JobContext {
+    /// Client of the database, to start sessions
+    pub client: Client,
+    pub mailer: SharedMailer,
+    pub notifier: SharedNotifier,
+}
+
+impl JobContext {
+    pub fn db(&self) -> Database {
+        self.client.default_database().unwrap()
+    }
+}
+
+/// Task run without a request, on a schedule or when triggered by an admin
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// Unique name, used as the id of its state
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// When the job runs, as a cron expression in UTC (see [`Cron`])
+    fn schedule(&self) -> &'static str;
+    /// Runs the job, returning a summary of what it did
+    async fn run(&self, ctx: &JobContext) -> Result<String, String>;
+}
+
+/// Every job of the application
+pub fn all() -> Vec<Box<dyn Job>> {
+    vec![
+        Box::new(schedule_meetings::ScheduleMeetings),
+        Box::new(close_stale_sessions::CloseStaleSessions),
+        Box::new(send_reminders::SendReminders),
+        Box::new(notify_overdue_action_items::NotifyOverdueActionItems),
+        Box::new(deliver_webhooks::DeliverWebhooks),
+        Box::new(post_meeting_summaries::PostMeetingSummaries),
+        Box::new(purge_trash::PurgeTrash),
+    ]
+}
+
+/// Job with the `name`
+pub fn find(name: &str) -> Option<Box<dyn Job>> {
+    all().into_iter().find(|job| job.name() == name)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum JobStatus {
+    SUCCEEDED,
+    FAILED,
+}
+
+/// State of a job shared between the instances
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct JobState {
+    /// Name of the job
+    #[serde(rename = "_id")]
+    pub name: String,
+    /// Next time the job is due, missing if its schedule never matches again
+    #[serde(default, with = "ts_milliseconds_option")]
+    pub next_run_at: Option<DateTime<Utc>>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    pub last_run_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_status: Option<JobStatus>,
+    /// Summary or error of the last run
+    #[serde(default)]
+    pub last_message: Option<String>,
+    #[serde(default)]
+    pub last_duration_ms: Option<i64>,
+    /// Instance running the job
+    #[serde(default)]
+    pub locked_by: Option<String>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl JobState {
+    /// Whether an instance is running the job
+    pub fn is_running(&self) -> bool {
+        matches!(self.locked_until, Some(until) if until > Utc::now())
+    }
+}
+
+/// Next run of a job after `after`, `None` if its schedule is invalid or never
+/// matches again
+fn next_run(job: &dyn Job, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match Cron::from_str(job.schedule()) {
+        Ok(cron) => cron.next_after(after),
+        Err(error) => {
+            eprintln!("[JOBS][{}] ~ invalid schedule: {}", job.name(), error);
+            None
+        }
+    }
+}
+
+/// Creates the state of the jobs that don't have one yet
+async fn register(db: &Database, jobs: &[Arc<dyn Job>]) -> Result<(), Error> {
+    let collection = db.collection::<JobState>("jobs");
+    let now = Utc::now();
+
+    for job in jobs {
+        let next_run_at = next_run(job.as_ref(), now).map(|date| date.timestamp_millis());
+        collection
+            .update_one(
+                doc! { "_id": job.name() },
+                doc! { "$setOnInsert": { "next_run_at": next_run_at } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// States of every job, sorted by name
+pub async fn states(db: &Database) -> Result<Vec<JobState>, Error> {
+    let opts = mongodb::options::FindOptions::builder()
+        .sort(doc! { "_id": 1 })
+        .build();
+
+    db.collection::<JobState>("jobs")
+        .find(None, opts)
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Locks the job for `instance` unless another instance holds the lock. With
+/// `due_only` the job is only locked if it's due.
+async fn lock(
+    db: &Database,
+    name: &str,
+    instance: &str,
+    due_only: bool,
+) -> Result<Option<JobState>, Error> {
+    let now = Utc::now();
+
+    let mut filter = doc! {
+        "_id": name,
+        "$or": [
+            { "locked_until": null },
+            { "locked_until": { "$lte": now.timestamp_millis() } }
+        ]
+    };
+    if due_only {
+        filter.insert("next_run_at", doc! { "$lte": now.timestamp_millis() });
+    }
+
+    db.collection::<JobState>("jobs")
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": {
+                    "locked_by": instance,
+                    "locked_until": (now + Duration::minutes(LOCK_LEASE_MINUTES)).timestamp_millis()
+                }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+}
+
+/// Extends the lease of the lock held by `instance`
+async fn renew(db: &Database, name: &str, instance: &str) {
+    let locked_until = Utc::now() + Duration::minutes(LOCK_LEASE_MINUTES);
+    let result = db
+        .collection::<JobState>("jobs")
+        .update_one(
+            doc! { "_id": name, "locked_by": instance },
+            doc! { "$set": { "locked_until": locked_until.timestamp_millis() } },
+            None,
+        )
+        .await;
+
+    match result {
+        Ok(result) if result.matched_count == 0 => {
+            eprintln!("[JOBS][{}] ~ the lock was taken over by another instance", name)
+        }
+        Ok(_) => {}
+        Err(error) => eprintln!("[JOBS][{}] ~ {}", name, error),
+    }
+}
+
+/// Runs a job locked by `instance`, renewing the lock meanwhile, then records the
+/// result, schedules the next run and releases the lock. Returns `None` if the
+/// lease expired anyway and another instance took the job over.
+async fn execute(
+    ctx: &JobContext,
+    job: &dyn Job,
+    instance: &str,
+) -> Result<Option<JobState>, Error> {
+    let started_at = Utc::now();
+    let timer = Instant::now();
+
+    let db = ctx.db();
+    let run = job.run(ctx);
+    tokio::pin!(run);
+    let period = LOCK_LEASE_MINUTES as u64 * 60 / RENEWALS_PER_LEASE;
+    let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(period));
+    // the first tick is immediate, the lock was just taken
+    heartbeat.tick().await;
+
+    let result = loop {
+        tokio::select! {
+            result = &mut run => break result,
+            _ = heartbeat.tick() => renew(&db, job.name(), instance).await,
+        }
+    };
+
+    let (status, message) = match result {
+        Ok(summary) => (JobStatus::SUCCEEDED, summary),
+        Err(error) => {
+            eprintln!("[JOBS][{}] ~ {}", job.name(), error);
+            (JobStatus::FAILED, error)
+        }
+    };
+
+    let next_run_at = next_run(job, Utc::now()).map(|date| date.timestamp_millis());
+    ctx.db()
+        .collection::<JobState>("jobs")
+        .find_one_and_update(
+            doc! { "_id": job.name(), "locked_by": instance },
+            doc! {
+                "$set": {
+                    "next_run_at": next_run_at,
+                    "last_run_at": started_at.timestamp_millis(),
+                    "last_status": bson::to_bson(&status).unwrap(),
+                    "last_message": message,
+                    "last_duration_ms": timer.elapsed().as_millis() as i64
+                },
+                "$unset": { "locked_by": "", "locked_until": "" }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+}
+
+/// Runs a job if it's due and no other instance is running it
+async fn run_due(ctx: &JobContext, job: &dyn Job, instance: &str) {
+    match lock(&ctx.db(), job.name(), instance, true).await {
+        Ok(Some(_)) => match execute(ctx, job, instance).await {
+            Ok(Some(_)) => {}
+            Ok(None) => eprintln!(
+                "[JOBS][{}] ~ the lock expired before the job finished",
+                job.name()
+            ),
+            Err(error) => eprintln!("[JOBS][{}] ~ {}", job.name(), error),
+        },
+        Ok(None) => {}
+        Err(error) => eprintln!("[JOBS][{}] ~ {}", job.name(), error),
+    }
+}
+
+/// Runs a job right away, even if it isn't due. Returns `None` if another run
+/// of the job is in progress.
+pub async fn run_now(ctx: &JobContext, job: &dyn Job) -> Result<Option<JobState>, Error> {
+    let instance = token::generate();
+
+    match lock(&ctx.db(), job.name(), &instance, false).await? {
+        Some(_) => execute(ctx, job, &instance).await,
+        None => Ok(None),
+    }
+}
+
+/// Runs the due jobs in the background until it's stopped
+pub struct Scheduler {
+    shutdown: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl Scheduler {
+    /// Registers the jobs and starts checking every [`TICK_SECONDS`] if they're due
+    pub async fn start(ctx: JobContext) -> Result<Self, Error> {
+        let jobs: Vec<Arc<dyn Job>> = all().into_iter().map(Arc::from).collect();
+        let db = ctx.db();
+        register(&db, &jobs).await?;
+
+        let (shutdown, mut stopped) = watch::channel(false);
+        let instance = token::generate();
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECONDS));
+            let mut running: HashMap<&'static str, JoinHandle<()>> = HashMap::new();
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = stopped.changed() => break,
+                }
+
+                for job in &jobs {
+                    // the job is still running since a previous tick
+                    if matches!(running.get(job.name()), Some(run) if !run.is_finished()) {
+                        continue;
+                    }
+
+                    let name = job.name();
+                    let (ctx, job, instance) = (ctx.clone(), job.clone(), instance.clone());
+                    let run = tokio::spawn(async move {
+                        run_due(&ctx, job.as_ref(), &instance).await;
+                    });
+                    running.insert(name, run);
+                }
+            }
+
+            // the jobs running are finished, the next ones wait for the next start
+            for (name, run) in running {
+                if let Err(error) = run.await {
+                    eprintln!("[JOBS][{}] ~ {}", name, error);
+                }
+            }
+        });
+
+        println!("[JOBS] ~ Scheduler started");
+        Ok(Self { shutdown, handle })
+    }
+
+    /// Stops checking the jobs, waiting for the ones running to finish
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(true);
+        if let Err(error) = self.handle.await {
+            eprintln!("[JOBS] ~ {}", error);
+        }
+        println!("[JOBS] ~ Scheduler stopped");
+    }
+}
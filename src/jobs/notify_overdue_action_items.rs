@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use bson::doc;
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::{error::Error, Database};
+use std::collections::HashMap;
+
+use super::{Job, JobContext};
+use crate::models::{
+    action_item::ActionItem, meeting::Meeting, notification::NotificationKind,
+    team::calendars_by_config, user::User,
+};
+use crate::notifier::{Draft, Notifier};
+
+/// Tells the assignees of the action items not done yet that their due date is
+/// over, once per item. The due date is over once the day ended in the local
+/// time of the team of the retro.
+pub struct NotifyOverdueActionItems;
+
+async fn notify(db: &Database, notifier: &Notifier) -> Result<u64, Error> {
+    let items = db
+        .collection::<ActionItem>("action_items")
+        .find(
+            doc! {
+                "done": false,
+                "assignee_id": { "$ne": null },
+                "due_date": { "$ne": null },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<ActionItem>>()
+        .await?;
+
+    if items.is_empty() {
+        return Ok(0);
+    }
+
+    let meetings_id: Vec<_> = items.iter().map(|item| item.meeting_id).collect();
+    let configs: HashMap<_, _> = db
+        .collection::<Meeting>("meetings")
+        .find(doc! { "_id": { "$in": meetings_id } }, None)
+        .await?
+        .try_collect::<Vec<Meeting>>()
+        .await?
+        .into_iter()
+        .filter_map(|meeting| Some((meeting.id.unwrap(), meeting.config_id?)))
+        .collect();
+    let calendars = calendars_by_config(db, configs.values().copied().collect()).await?;
+
+    let now = Utc::now();
+    let mut sent = 0;
+    for item in items {
+        let today = match configs
+            .get(&item.meeting_id)
+            .and_then(|id| calendars.get(id))
+        {
+            Some(calendar) => calendar.local(now).date_naive(),
+            None => now.date_naive(),
+        };
+        let due_date = item.due_date.unwrap();
+        if due_date >= today {
+            continue;
+        }
+
+        let assignee = db
+            .collection::<User>("users")
+            .find_one(doc! { "_id": item.assignee_id, "deleted_at": null }, None)
+            .await?;
+        let assignee = match assignee {
+            Some(assignee) => assignee,
+            None => continue,
+        };
+
+        let draft = Draft {
+            kind: NotificationKind::ACTION_ITEM_OVERDUE,
+            title: format!("Action item overdue: {}", item.title),
+            body: format!(
+                "The action item \"{}\" was due on {}.",
+                item.title, due_date
+            ),
+            subject_id: item.id.unwrap(),
+            dedup_key: Some(format!("overdue:{}", item.id.unwrap().to_hex())),
+        };
+
+        if notifier.notify(db, &assignee, draft).await? {
+            sent += 1;
+        }
+    }
+
+    Ok(sent)
+}
+
+#[async_trait]
+impl Job for NotifyOverdueActionItems {
+    fn name(&self) -> &'static str {
+        "notify_overdue_action_items"
+    }
+
+    fn description(&self) -> &'static str {
+        "Tells the assignees of the action items not done that their due date is over"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "5 * * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        match notify(&ctx.db(), &ctx.notifier).await {
+            Ok(sent) => Ok(format!("{} overdue notices sent", sent)),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
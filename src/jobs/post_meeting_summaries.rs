@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+
+use super::{Job, JobContext};
+use crate::chat::post_due;
+
+/// Posts the summaries of the dailies to the channels of their teams
+pub struct PostMeetingSummaries;
+
+#[async_trait]
+impl Job for PostMeetingSummaries {
+    fn name(&self) -> &'static str {
+        "post_meeting_summaries"
+    }
+
+    fn description(&self) -> &'static str {
+        "Posts the summary of every daily to the chat channel of its team a few minutes after it ends"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "* * * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        match post_due(&ctx.db()).await {
+            Ok(report) => Ok(format!(
+                "{} summaries posted, {} failed",
+                report.posted, report.failed
+            )),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+
+use super::{Job, JobContext};
+use crate::models::trash::DEFAULT_PURGE_AFTER_DAYS;
+use crate::utils::integrity;
+
+/// Permanently deletes what has been in the trash for too long
+pub struct PurgeTrash;
+
+#[async_trait]
+impl Job for PurgeTrash {
+    fn name(&self) -> &'static str {
+        "purge_trash"
+    }
+
+    fn description(&self) -> &'static str {
+        "Permanently deletes the entities deleted more than 30 days ago"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "30 3 * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        let deleted_before = (Utc::now() - Duration::days(DEFAULT_PURGE_AFTER_DAYS)).timestamp_millis();
+
+        let db = ctx.db();
+        let mut session = ctx
+            .client
+            .start_session(None)
+            .await
+            .map_err(|error| error.to_string())?;
+        session
+            .start_transaction(None)
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let report = integrity::purge(&db, &mut session, deleted_before)
+            .await
+            .map_err(|status| format!("the purge failed with a {}", status))?;
+
+        session
+            .commit_transaction()
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let purged: Vec<String> = report
+            .into_iter()
+            .filter(|(_, deleted)| *deleted > 0)
+            .map(|(collection, deleted)| format!("{} {}", deleted, collection))
+            .collect();
+
+        if purged.is_empty() {
+            Ok("nothing to purge".to_owned())
+        } else {
+            Ok(format!("{} purged", purged.join(", ")))
+        }
+    }
+}
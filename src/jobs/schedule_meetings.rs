@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+
+use super::{Job, JobContext};
+use crate::models::meeting_config::create_scheduled_meetings;
+
+/// Creates the upcoming meetings of the scheduled configs
+pub struct ScheduleMeetings;
+
+#[async_trait]
+impl Job for ScheduleMeetings {
+    fn name(&self) -> &'static str {
+        "schedule_meetings"
+    }
+
+    fn description(&self) -> &'static str {
+        "Creates the meetings of the scheduled configs for the next days"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "0 * * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        match create_scheduled_meetings(&ctx.db()).await {
+            Ok(created) => Ok(format!("{} meetings created", created)),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
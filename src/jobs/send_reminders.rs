@@ -0,0 +1,148 @@
+use async_trait::async_trait;
+use bson::doc;
+use chrono::{Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::{error::Error, Database};
+
+use super::{Job, JobContext};
+use crate::models::{
+    absence::absences_on,
+    meeting::Meeting,
+    meeting_config::MeetingConfig,
+    notification::{NotificationKind, MAX_REMINDER_MINUTES},
+    team::Team,
+    user::User,
+};
+use crate::notifier::{Draft, Notifier};
+
+/// Reminds the members of the teams that their scheduled meetings are about to
+/// start, as many minutes before as each one chose. Members absent that day
+/// aren't reminded, and a member is only reminded once per meeting.
+pub struct SendReminders;
+
+async fn remind(db: &Database, notifier: &Notifier) -> Result<u64, Error> {
+    let now = Utc::now();
+    let meetings = db
+        .collection::<Meeting>("meetings")
+        .find(
+            doc! {
+                "status": "SCHEDULED",
+                "date_utc": {
+                    "$gt": now.timestamp_millis(),
+                    "$lte": (now + Duration::minutes(MAX_REMINDER_MINUTES)).timestamp_millis()
+                },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<Meeting>>()
+        .await?;
+
+    let mut sent = 0;
+    for meeting in meetings {
+        let config = match meeting.config_id {
+            Some(config_id) => {
+                db.collection::<MeetingConfig>("meeting_configs")
+                    .find_one(doc! { "_id": config_id, "deleted_at": null }, None)
+                    .await?
+            }
+            None => None,
+        };
+        let config = match config {
+            Some(config) => config,
+            None => continue,
+        };
+        let team = match config.team_id {
+            Some(team_id) => {
+                db.collection::<Team>("teams")
+                    .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+                    .await?
+            }
+            None => None,
+        };
+        let team = match team {
+            Some(team) => team,
+            None => continue,
+        };
+
+        let calendar = team.calendar();
+        let members_id = team.users.clone().unwrap_or_default();
+        let absent: Vec<_> = absences_on(
+            db,
+            &members_id,
+            calendar.local(meeting.date_utc).date_naive(),
+        )
+        .await?
+        .into_iter()
+        .map(|absence| absence.user_id)
+        .collect();
+        let members = db
+            .collection::<User>("users")
+            .find(
+                doc! { "_id": { "$in": &members_id }, "deleted_at": null },
+                None,
+            )
+            .await?
+            .try_collect::<Vec<User>>()
+            .await?;
+
+        let starts_in = meeting.date_utc - now;
+        let starts_at = calendar.local(meeting.date_utc).format("%H:%M");
+        for member in members {
+            if absent.contains(&member.id.unwrap()) {
+                continue;
+            }
+            match member.notifications.reminder_minutes {
+                Some(minutes) if starts_in <= Duration::minutes(minutes) => {}
+                _ => continue,
+            }
+
+            let draft = Draft {
+                kind: NotificationKind::MEETING_REMINDER,
+                title: format!(
+                    "{} starts in {} minutes",
+                    config.config_name,
+                    (starts_in.num_seconds() + 59) / 60
+                ),
+                body: format!(
+                    "{} of {} starts at {} ({}).",
+                    config.config_name,
+                    team.name,
+                    starts_at,
+                    calendar.timezone()
+                ),
+                subject_id: meeting.id.unwrap(),
+                dedup_key: Some(format!("reminder:{}", meeting.id.unwrap().to_hex())),
+            };
+
+            if notifier.notify(db, &member, draft).await? {
+                sent += 1;
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+#[async_trait]
+impl Job for SendReminders {
+    fn name(&self) -> &'static str {
+        "send_reminders"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reminds the members of the teams that their scheduled meetings are about to start"
+    }
+
+    fn schedule(&self) -> &'static str {
+        "* * * * *"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, String> {
+        match remind(&ctx.db(), &ctx.notifier).await {
+            Ok(sent) => Ok(format!("{} reminders sent", sent)),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
@@ -1,8 +1,14 @@
 use rocket_cors::{CorsOptions, AllowedOrigins, Cors};
 
-mod config;
-mod models;
-mod utils;
+pub mod chat;
+pub mod config;
+pub mod jobs;
+pub mod mailer;
+pub mod migrations;
+pub mod models;
+pub mod notifier;
+pub mod utils;
+pub mod webhooks;
 
 #[allow(unused)]
 pub async fn run_api() -> Result<(), rocket::Error> {
@@ -15,7 +21,25 @@ pub async fn run_api() -> Result<(), rocket::Error> {
         .max_size(16)
         .build()
         .unwrap();
-    
+
+    let db = pool.get().await.unwrap().default_database().unwrap();
+    if let Err(error) = migrations::run(&db, false).await {
+        panic!("[MIGRATIONS] ~ {}", error);
+    }
+    utils::schema::bootstrap(&db).await;
+
+    // background jobs (scheduled meetings, reminders, purge, ...) share the mailer
+    // and notifier of the routes
+    let mailer = mailer::from_env();
+    let notifier = notifier::from_env(mailer.clone());
+    let scheduler = jobs::Scheduler::start(jobs::JobContext {
+        client: pool.get().await.unwrap().clone(),
+        mailer: mailer.clone(),
+        notifier: notifier.clone(),
+    })
+    .await
+    .unwrap_or_else(|error| panic!("[JOBS] ~ {}", error));
+
     // configure CORS
     let cors: Cors = CorsOptions::default()
         .allowed_origins(AllowedOrigins::all())
@@ -23,7 +47,29 @@ pub async fn run_api() -> Result<(), rocket::Error> {
         .to_cors()
         .unwrap();
 
-    rocket.attach(cors).manage(pool).launch().await?;
+    let result = rocket
+        .attach(cors)
+        .manage(pool)
+        .manage(mailer)
+        .manage(notifier)
+        .launch()
+        .await;
+
+    // rocket returns once it was shut down, the job running is let finish
+    scheduler.stop().await;
+    result?;
 
     Ok(())
 }
+
+/// Applies the pending migrations without starting the API (`smt_backend migrate [--dry-run]`)
+pub async fn run_migrations(dry_run: bool) -> Result<(), migrations::MigrationError> {
+    utils::db::check_db_working().await;
+    let pool = config::Pool::builder(config::PoolManager::new())
+        .max_size(1)
+        .build()
+        .unwrap();
+
+    let db = pool.get().await.unwrap().default_database().unwrap();
+    migrations::run(&db, dry_run).await
+}
@@ -0,0 +1,47 @@
+use super::{Mail, Mailer, MailerError};
+use async_trait::async_trait;
+use rocket::tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use std::path::PathBuf;
+
+/// Mailer for local development, prints the emails or appends them to a file
+pub struct LogMailer {
+    /// File where the emails are appended (`MAIL_LOG_FILE`), stdout if not provided
+    file: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn from_env() -> Self {
+        Self {
+            file: std::env::var("MAIL_LOG_FILE").ok().map(PathBuf::from),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), MailerError> {
+        let entry = format!(
+            "To: {}\nSubject: {}\n\n{}\n---\n",
+            mail.to, mail.subject, mail.body
+        );
+
+        match &self.file {
+            Some(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|error| MailerError(error.to_string()))?;
+
+                file.write_all(entry.as_bytes())
+                    .await
+                    .map_err(|error| MailerError(error.to_string()))
+            }
+            None => {
+                println!("[MAILER] ~ {}", entry);
+                Ok(())
+            }
+        }
+    }
+}
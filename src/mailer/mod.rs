@@ -0,0 +1,46 @@
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+
+pub mod log;
+pub mod smtp;
+
+/// Email to be delivered by a [`Mailer`]
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct MailerError(pub String);
+
+impl fmt::Display for MailerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Delivers emails to the users of the application
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), MailerError>;
+}
+
+/// Mailer shared between the routes (managed by rocket) and the background tasks
+pub type SharedMailer = Arc<dyn Mailer>;
+
+/// Builds the mailer selected with the `MAILER` variable (`smtp` or `log`, default `log`)
+pub fn from_env() -> SharedMailer {
+    match std::env::var("MAILER").as_deref() {
+        Ok("smtp") => {
+            println!("[MAILER] ~ Sending emails through SMTP");
+            Arc::new(smtp::SmtpMailer::from_env())
+        }
+        _ => {
+            println!("[MAILER] ~ Logging emails instead of sending them");
+            Arc::new(log::LogMailer::from_env())
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::{Mail, Mailer, MailerError};
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+/// Mailer that delivers the emails through an SMTP relay
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Configured with `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD` and `MAIL_FROM`
+    pub fn from_env() -> Self {
+        let host = std::env::var("SMTP_HOST").expect("[MAILER] ~ SMTP_HOST hasn't been provided");
+        let from = std::env::var("MAIL_FROM").expect("[MAILER] ~ MAIL_FROM hasn't been provided");
+
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&host)
+            .expect("[MAILER] ~ Invalid SMTP host");
+
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            builder = builder.port(port.parse().expect("[MAILER] ~ Invalid SMTP port"));
+        }
+
+        if let (Ok(username), Ok(password)) = (
+            std::env::var("SMTP_USERNAME"),
+            std::env::var("SMTP_PASSWORD"),
+        ) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Self {
+            transport: builder.build(),
+            from: from.parse().expect("[MAILER] ~ Invalid MAIL_FROM address"),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), MailerError> {
+        let to: Mailbox = mail
+            .to
+            .parse()
+            .map_err(|_| MailerError(format!("Invalid address {}", mail.to)))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&mail.subject)
+            .body(mail.body.clone())
+            .map_err(|error| MailerError(error.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|error| MailerError(error.to_string()))
+    }
+}
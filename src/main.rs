@@ -1,9 +1,5 @@
 use dotenv::dotenv;
 
-pub mod config;
-pub mod models;
-pub mod utils;
-
 #[rocket::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
@@ -11,7 +7,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     std::env::var("MONGODB_ROOT_USERNAME").expect("[DB] ~ Username variable hasn't been provided");
     std::env::var("MONGODB_ROOT_PASSWORD").expect("[DB] ~ Password variable hasn't been provided");
 
-    smt_backend::run_api().await?;
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("migrate") => {
+            let dry_run = args.iter().any(|arg| arg == "--dry-run");
+            smt_backend::run_migrations(dry_run).await?;
+        }
+        _ => smt_backend::run_api().await?,
+    }
 
     Ok(())
 }
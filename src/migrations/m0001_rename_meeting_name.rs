@@ -0,0 +1,54 @@
+use super::Migration;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use mongodb::{error::Error, Database};
+
+/// Meeting configs used to have a `meeting_name`, renamed to `config_name` in 0.1.8
+pub struct RenameMeetingName;
+
+fn filter() -> Document {
+    doc! { "meeting_name": { "$exists": true } }
+}
+
+#[async_trait]
+impl Migration for RenameMeetingName {
+    fn id(&self) -> &'static str {
+        "0001_rename_meeting_name"
+    }
+
+    fn description(&self) -> &'static str {
+        "Rename meeting_configs.meeting_name to config_name"
+    }
+
+    async fn pending_changes(&self, db: &Database) -> Result<u64, Error> {
+        db.collection::<Document>("meeting_configs")
+            .count_documents(filter(), None)
+            .await
+    }
+
+    async fn up(&self, db: &Database) -> Result<u64, Error> {
+        let collection = db.collection::<Document>("meeting_configs");
+
+        // configs that already got a config_name keep it
+        let mut renamed = filter();
+        renamed.insert("config_name", doc! { "$exists": false });
+
+        let result = collection
+            .update_many(
+                renamed,
+                doc! { "$rename": { "meeting_name": "config_name" } },
+                None,
+            )
+            .await?;
+
+        let leftovers = collection
+            .update_many(
+                filter(),
+                doc! { "$unset": { "meeting_name": "" } },
+                None,
+            )
+            .await?;
+
+        Ok(result.modified_count + leftovers.modified_count)
+    }
+}
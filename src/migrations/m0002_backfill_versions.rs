@@ -0,0 +1,50 @@
+use super::Migration;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use mongodb::{error::Error, Database};
+
+/// Documents created before optimistic concurrency have no `version`, so an
+/// `If-Match: "0"` would never match them
+pub struct BackfillVersions;
+
+const COLLECTIONS: [&str; 5] = ["users", "teams", "meeting_configs", "meetings", "user_times"];
+
+fn filter() -> Document {
+    doc! { "version": { "$exists": false } }
+}
+
+#[async_trait]
+impl Migration for BackfillVersions {
+    fn id(&self) -> &'static str {
+        "0002_backfill_versions"
+    }
+
+    fn description(&self) -> &'static str {
+        "Set version 0 on the documents without a version"
+    }
+
+    async fn pending_changes(&self, db: &Database) -> Result<u64, Error> {
+        let mut pending = 0;
+        for collection in COLLECTIONS {
+            pending += db
+                .collection::<Document>(collection)
+                .count_documents(filter(), None)
+                .await?;
+        }
+
+        Ok(pending)
+    }
+
+    async fn up(&self, db: &Database) -> Result<u64, Error> {
+        let mut changed = 0;
+        for collection in COLLECTIONS {
+            let result = db
+                .collection::<Document>(collection)
+                .update_many(filter(), doc! { "$set": { "version": 0_i64 } }, None)
+                .await?;
+            changed += result.modified_count;
+        }
+
+        Ok(changed)
+    }
+}
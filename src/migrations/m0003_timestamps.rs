@@ -0,0 +1,138 @@
+use super::Migration;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneOptions, FindOptions, UpdateOptions};
+use mongodb::{error::Error, Collection, Database};
+
+/// Meetings used to have a `duration` and user times a `time`, both in seconds.
+/// They are replaced by start and end dates. As the moment the users spoke wasn't
+/// stored, the turns of a meeting are laid out one after another from its start,
+/// in the order they were stored.
+pub struct Timestamps;
+
+fn meeting_filter() -> Document {
+    doc! { "duration": { "$exists": true } }
+}
+
+fn user_time_filter() -> Document {
+    doc! { "time": { "$exists": true } }
+}
+
+/// The documents still match the previous validators until they are migrated
+fn bypass_validation() -> UpdateOptions {
+    UpdateOptions::builder()
+        .bypass_document_validation(true)
+        .build()
+}
+
+/// Sets the dates of the legacy turns matching `filter`, one after another from
+/// `start` in the order they were stored
+async fn lay_out(
+    user_times: &Collection<Document>,
+    mut filter: Document,
+    mut start: i64,
+) -> Result<u64, Error> {
+    filter.extend(user_time_filter());
+    let opts = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+
+    let mut changed = 0;
+    let mut cursor = user_times.find(filter, opts).await?;
+    while let Some(user_time) = cursor.try_next().await? {
+        let time = match user_time.get("time") {
+            Some(bson::Bson::Int32(time)) => *time as i64,
+            Some(bson::Bson::Int64(time)) => *time,
+            _ => 0,
+        };
+        let end = start + time * 1000;
+
+        let result = user_times
+            .update_one(
+                doc! { "_id": user_time.get_object_id("_id").unwrap() },
+                doc! {
+                    "$set": { "start_utc": start, "end_utc": end },
+                    "$unset": { "time": "" }
+                },
+                bypass_validation(),
+            )
+            .await?;
+        changed += result.modified_count;
+        start = end;
+    }
+
+    Ok(changed)
+}
+
+#[async_trait]
+impl Migration for Timestamps {
+    fn id(&self) -> &'static str {
+        "0003_timestamps"
+    }
+
+    fn description(&self) -> &'static str {
+        "Replace meetings.duration and user_times.time with start and end dates"
+    }
+
+    async fn pending_changes(&self, db: &Database) -> Result<u64, Error> {
+        let meetings = db
+            .collection::<Document>("meetings")
+            .count_documents(meeting_filter(), None)
+            .await?;
+        let user_times = db
+            .collection::<Document>("user_times")
+            .count_documents(user_time_filter(), None)
+            .await?;
+
+        Ok(meetings + user_times)
+    }
+
+    async fn up(&self, db: &Database) -> Result<u64, Error> {
+        let meetings = db.collection::<Document>("meetings");
+        let user_times = db.collection::<Document>("user_times");
+
+        let mut changed = 0;
+        let meetings_id = user_times
+            .distinct("meeting_id", user_time_filter(), None)
+            .await?;
+        for meeting_id in meetings_id {
+            let meeting_start = meetings
+                .find_one(doc! { "_id": &meeting_id }, None)
+                .await?
+                .and_then(|meeting| meeting.get_i64("date_utc").ok());
+
+            // a run that didn't finish already laid out the first turns
+            let last_migrated = user_times
+                .find_one(
+                    doc! { "meeting_id": &meeting_id, "time": { "$exists": false } },
+                    FindOneOptions::builder().sort(doc! { "end_utc": -1 }).build(),
+                )
+                .await?
+                .and_then(|user_time| user_time.get_i64("end_utc").ok());
+
+            // without a meeting there's no better start than the epoch
+            let start = meeting_start.into_iter().chain(last_migrated).max().unwrap_or(0);
+            changed += lay_out(&user_times, doc! { "meeting_id": meeting_id }, start).await?;
+        }
+        changed += lay_out(&user_times, doc! { "meeting_id": null }, 0).await?;
+
+        let result = meetings
+            .update_many(
+                meeting_filter(),
+                vec![
+                    doc! {
+                        "$set": {
+                            "end_utc": {
+                                "$add": ["$date_utc", { "$multiply": ["$duration", 1000_i64] }]
+                            }
+                        }
+                    },
+                    doc! { "$unset": "duration" },
+                ],
+                bypass_validation(),
+            )
+            .await?;
+        changed += result.modified_count;
+
+        Ok(changed)
+    }
+}
@@ -0,0 +1,71 @@
+use super::Migration;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use futures::TryStreamExt;
+use mongodb::{error::Error, Database};
+
+use crate::models::user::normalize_email;
+use crate::utils::db::is_duplicate_key;
+
+/// Emails used to be stored as typed, so the invitations (sent to the lowercase
+/// email) and the lookups by email missed the users who signed up with capitals
+pub struct NormalizeEmails;
+
+fn filter() -> Document {
+    doc! {
+        "$expr": {
+            "$ne": ["$email", { "$toLower": { "$trim": { "input": "$email" } } }]
+        }
+    }
+}
+
+#[async_trait]
+impl Migration for NormalizeEmails {
+    fn id(&self) -> &'static str {
+        "0004_normalize_emails"
+    }
+
+    fn description(&self) -> &'static str {
+        "Trim and lowercase users.email"
+    }
+
+    async fn pending_changes(&self, db: &Database) -> Result<u64, Error> {
+        db.collection::<Document>("users")
+            .count_documents(filter(), None)
+            .await
+    }
+
+    async fn up(&self, db: &Database) -> Result<u64, Error> {
+        let collection = db.collection::<Document>("users");
+        let users: Vec<Document> = collection.find(filter(), None).await?.try_collect().await?;
+
+        let mut changed = 0;
+        for user in users {
+            let (id, email) = match (user.get_object_id("_id"), user.get_str("email")) {
+                (Ok(id), Ok(email)) => (id, email),
+                _ => continue,
+            };
+
+            // two accounts differing only in case can't both keep their email,
+            // they have to be merged by hand
+            match collection
+                .update_one(
+                    doc! { "_id": id },
+                    doc! { "$set": { "email": normalize_email(email) } },
+                    None,
+                )
+                .await
+            {
+                Ok(result) => changed += result.modified_count,
+                Err(error) if is_duplicate_key(&error) => eprintln!(
+                    "[MIGRATIONS] ~ {} is kept as it is, another user has the email {}",
+                    email,
+                    normalize_email(email)
+                ),
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(changed)
+    }
+}
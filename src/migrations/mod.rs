@@ -0,0 +1,204 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use bson::doc;
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Duration, Utc};
+use mongodb::{error::Error, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::db::is_duplicate_key;
+
+mod m0001_rename_meeting_name;
+mod m0002_backfill_versions;
+mod m0003_timestamps;
+mod m0004_normalize_emails;
+
+/// Minutes an instance holds the lock of a migration it's applying. An unfinished
+/// migration whose lock expired is taken over by the next run, so the migrations
+/// have to be safe to apply again on documents partly migrated.
+const LOCK_LEASE_MINUTES: i64 = 30;
+
+/// Why the migrations couldn't be applied
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(Error),
+    /// Another instance is applying the migration, the next ones have to wait for it
+    Locked(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(error) => error.fmt(f),
+            MigrationError::Locked(id) => write!(
+                f,
+                "{} is still being applied by another instance, try again once it finishes",
+                id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<Error> for MigrationError {
+    fn from(error: Error) -> Self {
+        MigrationError::Database(error)
+    }
+}
+
+/// Change of the stored documents that has to be applied once per database
+#[async_trait]
+pub trait Migration: Send + Sync {
+    /// Unique id, migrations are applied in the order of their ids
+    fn id(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// Amount of documents the migration would change, used on dry runs
+    async fn pending_changes(&self, db: &Database) -> Result<u64, Error>;
+    /// Applies the migration, returning the amount of documents changed
+    async fn up(&self, db: &Database) -> Result<u64, Error>;
+}
+
+/// Every migration of the application, new ones are appended at the end
+fn all() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(m0001_rename_meeting_name::RenameMeetingName),
+        Box::new(m0002_backfill_versions::BackfillVersions),
+        Box::new(m0003_timestamps::Timestamps),
+        Box::new(m0004_normalize_emails::NormalizeEmails),
+    ]
+}
+
+/// Record of a migration applied to the database
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AppliedMigration {
+    #[serde(rename = "_id")]
+    id: String,
+    description: String,
+    /// Whether the migration finished or is still being applied
+    finished: bool,
+    /// Amount of documents changed
+    changed: i64,
+    #[serde(with = "ts_milliseconds")]
+    applied_at: DateTime<Utc>,
+    /// End of the lock of the instance applying it, none once it's finished. Records
+    /// without it were left by older versions and are stale.
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// Applies the migrations that haven't been applied yet, in order. With `dry_run`
+/// nothing is changed, the pending migrations are only reported. Fails without
+/// going further when a migration is being applied by another instance.
+pub async fn run(db: &Database, dry_run: bool) -> Result<(), MigrationError> {
+    let collection = db.collection::<AppliedMigration>("migrations");
+
+    let mut migrations = all();
+    migrations.sort_by_key(|migration| migration.id());
+
+    for migration in migrations {
+        let applied = collection
+            .find_one(doc! { "_id": migration.id() }, None)
+            .await?;
+
+        let stale = match applied {
+            Some(applied) if applied.finished => continue,
+            Some(applied) => match applied.locked_until {
+                Some(locked_until) if locked_until > Utc::now() => {
+                    return Err(MigrationError::Locked(migration.id().to_owned()))
+                }
+                // the instance applying it stopped before finishing
+                locked_until => Some(locked_until),
+            },
+            None => None,
+        };
+
+        if dry_run {
+            let changes = migration.pending_changes(db).await?;
+            println!(
+                "[MIGRATIONS] ~ {} is pending, {} documents would change ({})",
+                migration.id(),
+                changes,
+                migration.description()
+            );
+            continue;
+        }
+
+        // the record works as a lock, only one instance can insert it or take
+        // over a stale one
+        let now = Utc::now();
+        let mut record = AppliedMigration {
+            id: migration.id().to_owned(),
+            description: migration.description().to_owned(),
+            finished: false,
+            changed: 0,
+            applied_at: now,
+            locked_until: Some(now + Duration::minutes(LOCK_LEASE_MINUTES)),
+        };
+
+        let locked = match stale {
+            None => match collection.insert_one(&record, None).await {
+                Ok(_) => true,
+                Err(error) if is_duplicate_key(&error) => false,
+                Err(error) => return Err(error.into()),
+            },
+            Some(locked_until) => {
+                let result = collection
+                    .replace_one(
+                        doc! {
+                            "_id": migration.id(),
+                            "finished": false,
+                            "locked_until": locked_until.map(|date| date.timestamp_millis())
+                        },
+                        &record,
+                        None,
+                    )
+                    .await?;
+                result.modified_count == 1
+            }
+        };
+        if !locked {
+            return Err(MigrationError::Locked(migration.id().to_owned()));
+        }
+
+        match stale {
+            Some(_) => println!(
+                "[MIGRATIONS] ~ Applying {} again, its last run didn't finish...",
+                migration.id()
+            ),
+            None => println!("[MIGRATIONS] ~ Applying {}...", migration.id()),
+        }
+
+        let changed = match migration.up(db).await {
+            Ok(changed) => changed,
+            Err(error) => {
+                // release the lock so the migration is retried on the next run
+                collection
+                    .delete_one(doc! { "_id": migration.id() }, None)
+                    .await?;
+                return Err(error.into());
+            }
+        };
+
+        record.finished = true;
+        record.changed = changed as i64;
+        record.applied_at = Utc::now();
+        record.locked_until = None;
+        collection
+            .replace_one(doc! { "_id": migration.id() }, &record, None)
+            .await?;
+
+        println!(
+            "[MIGRATIONS] ~ {} applied, {} documents changed",
+            migration.id(),
+            changed
+        );
+    }
+
+    Ok(())
+}
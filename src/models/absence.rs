@@ -0,0 +1,443 @@
+use crate::config::Pool;
+use crate::utils::{
+    concurrency::{ETag, IfMatch},
+    db::{commit_transaction, get_collection, parse_id, start_transaction},
+    integrity::{is_active, is_trashed, restore_one, soft_delete_one},
+    pagination::{paginate, Page, PageOptions},
+    patch::{check_reference, nullable, patch_one},
+    responders::{ApiError, Response},
+    validation::{object_id, validate, validate_date_range},
+};
+use bson::{doc, oid::ObjectId, to_bson, Document};
+use chrono::serde::ts_milliseconds_option;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::user::User;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AbsenceKind {
+    VACATION,
+    SICK,
+    OTHER,
+}
+
+/// Period a user is out of office, the user is left out of the speaking order and
+/// the expected attendance of the meetings held during it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Absence {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) user_id: ObjectId,
+    /// VACATION | SICK | OTHER
+    pub(crate) kind: AbsenceKind,
+    /// First day of the absence, in the local time of each team of the user
+    pub(crate) start_date: NaiveDate,
+    /// Last day of the absence (included)
+    pub(crate) end_date: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) note: Option<String>,
+    /// Date and time when the absence was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the absence
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+/// Absence as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct AbsenceView {
+    id: String,
+    user_id: String,
+    kind: AbsenceKind,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    note: Option<String>,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<Absence> for AbsenceView {
+    fn from(absence: Absence) -> Self {
+        Self {
+            id: absence.id.unwrap().to_hex(),
+            user_id: absence.user_id.to_hex(),
+            kind: absence.kind,
+            start_date: absence.start_date,
+            end_date: absence.end_date,
+            note: absence.note,
+            deleted_at: absence.deleted_at,
+            version: absence.version,
+        }
+    }
+}
+
+/// Body of the creation and the update of an absence
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct AbsenceRequestBody {
+    #[validate(custom = "object_id")]
+    user_id: String,
+    /// VACATION | SICK | OTHER, anything else is rejected when parsing the body
+    kind: AbsenceKind,
+    /// First day of the absence (ex: `2023-08-01`)
+    start_date: NaiveDate,
+    /// Last day of the absence (included), not before `start_date`
+    end_date: NaiveDate,
+    #[validate(length(max = 500))]
+    note: Option<String>,
+}
+
+impl TryFrom<AbsenceRequestBody> for Absence {
+    type Error = Status;
+
+    fn try_from(body: AbsenceRequestBody) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: None,
+            user_id: parse_id(&body.user_id)?,
+            kind: body.kind,
+            start_date: body.start_date,
+            end_date: body.end_date,
+            note: body.note,
+            deleted_at: None,
+            version: 0,
+        })
+    }
+}
+
+/// Active absences of the users that include the local `date`
+pub(crate) async fn absences_on(
+    db: &Database,
+    users_id: &[ObjectId],
+    date: NaiveDate,
+) -> Result<Vec<Absence>, mongodb::error::Error> {
+    let date = date.to_string();
+
+    db.collection::<Absence>("absences")
+        .find(
+            doc! {
+                "user_id": { "$in": users_id },
+                "start_date": { "$lte": &date },
+                "end_date": { "$gte": &date },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Fails with a 409 if another active absence of the user overlaps the period
+async fn check_overlap(
+    collection: &Collection<Absence>,
+    absence: &Absence,
+    id: Option<ObjectId>,
+) -> Result<(), Status> {
+    let mut filter = doc! {
+        "user_id": absence.user_id,
+        "start_date": { "$lte": absence.end_date.to_string() },
+        "end_date": { "$gte": absence.start_date.to_string() },
+        "deleted_at": null
+    };
+    if let Some(id) = id {
+        filter.insert("_id", doc! { "$ne": id });
+    }
+
+    match collection.find_one(filter, None).await {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err(Status::Conflict),
+        Err(error) => {
+            eprintln!("[ABSENCE] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[rocket::post("/", format = "json", data = "<absence>")]
+pub async fn create(
+    db_pool: &State<Pool>,
+    absence: Json<AbsenceRequestBody>,
+) -> Result<Response<AbsenceView>, ApiError> {
+    validate(&absence.0)?;
+    validate_date_range(absence.0.start_date, absence.0.end_date, "end_date")?;
+
+    let collection = get_collection::<Absence>(db_pool, "absences").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let mut new_absence = Absence::try_from(absence.0)?;
+
+    check_reference(&user_collection, new_absence.user_id).await?;
+    check_overlap(&collection, &new_absence, None).await?;
+
+    match collection.insert_one(&new_absence, None).await {
+        Ok(result) => {
+            new_absence.id = Some(result.inserted_id.as_object_id().unwrap());
+            Ok(Response::Created(Json(new_absence.into())))
+        }
+        Err(error) => {
+            eprintln!("[INSERT][ABSENCE] ~ {}", error);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+#[rocket::get("/<absence_id>")]
+pub async fn get(
+    db_pool: &State<Pool>,
+    absence_id: String,
+) -> Result<Response<AbsenceView>, Status> {
+    let collection = get_collection::<Absence>(db_pool, "absences").await;
+    let absence_id = parse_id(&absence_id)?;
+
+    let absence = collection
+        .find_one(doc! { "_id": absence_id, "deleted_at": null }, None)
+        .await
+        .unwrap();
+
+    match absence {
+        Some(absence) => {
+            let etag = ETag(absence.version);
+            Ok(Response::Tagged(Json(absence.into()), etag))
+        }
+        None => Err(Status::NotFound),
+    }
+}
+
+#[rocket::put("/<absence_id>", format = "json", data = "<absence>")]
+pub async fn update(
+    db_pool: &State<Pool>,
+    absence_id: String,
+    if_match: IfMatch,
+    absence: Json<AbsenceRequestBody>,
+) -> Result<Response<AbsenceView>, ApiError> {
+    validate(&absence.0)?;
+    validate_date_range(absence.0.start_date, absence.0.end_date, "end_date")?;
+
+    let collection = get_collection::<Absence>(db_pool, "absences").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let absence_id = parse_id(&absence_id)?;
+    let new_absence = Absence::try_from(absence.0)?;
+
+    check_reference(&user_collection, new_absence.user_id).await?;
+    check_overlap(&collection, &new_absence, Some(absence_id)).await?;
+
+    let mut filter = doc! { "_id": absence_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": {
+                    "user_id": new_absence.user_id,
+                    "kind": to_bson(&new_absence.kind).unwrap(),
+                    "start_date": new_absence.start_date.to_string(),
+                    "end_date": new_absence.end_date.to_string(),
+                    "note": new_absence.note
+                },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    match result {
+        Ok(Some(absence)) => {
+            let etag = ETag(absence.version);
+            Ok(Response::Tagged(Json(absence.into()), etag))
+        }
+        Ok(None) => {
+            let exists = collection
+                .find_one(doc! { "_id": absence_id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
+        Err(error) => {
+            eprintln!("[UPDATE][ABSENCE] ~ {}", error);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+/// Fields of an absence that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AbsencePatch {
+    kind: Option<AbsenceKind>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    /// `null` removes the note
+    #[validate(length(max = 500))]
+    #[serde(default, deserialize_with = "nullable")]
+    note: Option<Option<String>>,
+}
+
+/// Partial update of an absence, only the fields sent are changed
+#[rocket::patch("/<absence_id>", format = "json", data = "<absence>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    absence_id: String,
+    if_match: IfMatch,
+    absence: Json<AbsencePatch>,
+) -> Result<Response<AbsenceView>, ApiError> {
+    validate(&absence.0)?;
+
+    let collection = get_collection::<Absence>(db_pool, "absences").await;
+    let absence_id = parse_id(&absence_id)?;
+    let fields = absence.0;
+
+    // the new period has to be checked against the day that is kept
+    if fields.start_date.is_some() || fields.end_date.is_some() {
+        let mut current = match collection
+            .find_one(doc! { "_id": absence_id, "deleted_at": null }, None)
+            .await
+            .unwrap()
+        {
+            Some(absence) => absence,
+            None => return Err(Status::NotFound.into()),
+        };
+
+        current.start_date = fields.start_date.unwrap_or(current.start_date);
+        current.end_date = fields.end_date.unwrap_or(current.end_date);
+        validate_date_range(current.start_date, current.end_date, "end_date")?;
+        check_overlap(&collection, &current, Some(absence_id)).await?;
+    }
+
+    let mut changes = Document::new();
+    if let Some(kind) = fields.kind {
+        changes.insert("kind", to_bson(&kind).unwrap());
+    }
+    if let Some(start_date) = fields.start_date {
+        changes.insert("start_date", start_date.to_string());
+    }
+    if let Some(end_date) = fields.end_date {
+        changes.insert("end_date", end_date.to_string());
+    }
+    if let Some(note) = fields.note {
+        changes.insert("note", note);
+    }
+
+    let absence = patch_one(&collection, absence_id, if_match, changes).await?;
+
+    let etag = ETag(absence.version);
+    Ok(Response::Tagged(Json(absence.into()), etag))
+}
+
+#[rocket::delete("/<absence_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    absence_id: String,
+    if_match: IfMatch,
+) -> Result<Response<AbsenceView>, Status> {
+    let absence_id = parse_id(&absence_id)?;
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let result = soft_delete_one::<Absence>(
+        &db,
+        &mut session,
+        "absences",
+        absence_id,
+        if_match.0,
+        Utc::now().timestamp_millis(),
+    )
+    .await?;
+
+    let absence = match result {
+        Some(absence) => absence,
+        None => {
+            let exists = is_active(&db, &mut session, "absences", absence_id).await?;
+            return Err(if_match.failed_write_status(exists));
+        }
+    };
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(absence.into())))
+}
+
+#[rocket::put("/<absence_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    absence_id: String,
+    if_match: IfMatch,
+) -> Result<Response<AbsenceView>, Status> {
+    let absence_id = parse_id(&absence_id)?;
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored =
+        restore_one::<Absence>(&db, &mut session, "absences", absence_id, if_match.0).await?;
+    let mut absence = match restored {
+        Some(absence) => absence,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "absences", absence_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // the user has to be restored first
+    if !is_active(&db, &mut session, "users", absence.user_id).await? {
+        return Err(Status::Conflict);
+    }
+
+    // another absence of the user may have been created over the same days
+    let collection = get_collection::<Absence>(db_pool, "absences").await;
+    check_overlap(&collection, &absence, absence.id).await?;
+
+    commit_transaction(&mut session).await?;
+
+    absence.deleted_at = None;
+    absence.version += 1;
+    Ok(Response::Success(Json(absence.into())))
+}
+
+#[rocket::get("/all?<user_id>&<from>&<to>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    user_id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<AbsenceView>>, Status> {
+    let collection = get_collection::<Absence>(db_pool, "absences").await;
+    let opts = PageOptions::new(page, limit, sort, &["start_date", "end_date", "_id"])?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(user_id) = user_id {
+        filter.insert("user_id", parse_id(&user_id)?);
+    }
+
+    // absences overlapping the period, dates as `YYYY-MM-DD`
+    let parse_date = |date: &str| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| Status::UnprocessableEntity)
+    };
+    if let Some(from) = from {
+        filter.insert("end_date", doc! { "$gte": parse_date(&from)?.to_string() });
+    }
+    if let Some(to) = to {
+        filter.insert("start_date", doc! { "$lte": parse_date(&to)?.to_string() });
+    }
+
+    let absences = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(absences.map(AbsenceView::from))))
+}
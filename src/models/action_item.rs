@@ -0,0 +1,409 @@
+use crate::config::Pool;
+use crate::notifier::{Draft, SharedNotifier};
+use crate::utils::{
+    concurrency::{ETag, IfMatch},
+    db::{commit_transaction, get_collection, parse_id, start_transaction},
+    integrity::{is_active, is_trashed, restore_one, soft_delete_one},
+    pagination::{paginate, Page, PageOptions},
+    patch::{check_reference, nullable, patch_one},
+    responders::{ApiError, Response},
+    validation::{not_blank, object_id, validate},
+};
+use crate::webhooks;
+use bson::{doc, oid::ObjectId, Document};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, NaiveDate, Utc};
+use mongodb::Database;
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::{
+    meeting::Meeting,
+    meeting_config::{MeetingConfig, MeetingType},
+    notification::NotificationKind,
+    user::User,
+    webhook::WebhookEvent,
+};
+
+/// Task agreed on in a retro, optionally assigned to a user with a due date
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionItem {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    /// Retro where the item was agreed on
+    pub(crate) meeting_id: ObjectId,
+    pub(crate) title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) assignee_id: Option<ObjectId>,
+    /// Last day to get it done, in the local time of the team
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) due_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub(crate) done: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) created_at: DateTime<Utc>,
+    /// Date and time when the item was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the item
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+/// Action item as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct ActionItemView {
+    id: String,
+    meeting_id: String,
+    title: String,
+    assignee_id: Option<String>,
+    due_date: Option<NaiveDate>,
+    done: bool,
+    #[serde(with = "ts_milliseconds")]
+    created_at: DateTime<Utc>,
+    #[serde(
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<ActionItem> for ActionItemView {
+    fn from(item: ActionItem) -> Self {
+        Self {
+            id: item.id.unwrap().to_hex(),
+            meeting_id: item.meeting_id.to_hex(),
+            title: item.title,
+            assignee_id: item.assignee_id.map(|id| id.to_hex()),
+            due_date: item.due_date,
+            done: item.done,
+            created_at: item.created_at,
+            deleted_at: item.deleted_at,
+            version: item.version,
+        }
+    }
+}
+
+/// Body of the creation of an action item
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct ActionItemRequestBody {
+    /// Id of a meeting of a RETRO config
+    #[validate(custom = "object_id")]
+    meeting_id: String,
+    #[validate(length(min = 1, max = 200), custom = "not_blank")]
+    title: String,
+    #[validate(custom = "object_id")]
+    assignee_id: Option<String>,
+    due_date: Option<NaiveDate>,
+}
+
+/// Fields of an action item that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ActionItemPatch {
+    #[validate(length(min = 1, max = 200), custom = "not_blank")]
+    title: Option<String>,
+    /// `null` unassigns the item
+    #[serde(default, deserialize_with = "nullable")]
+    assignee_id: Option<Option<String>>,
+    /// `null` removes the due date
+    #[serde(default, deserialize_with = "nullable")]
+    due_date: Option<Option<NaiveDate>>,
+    done: Option<bool>,
+}
+
+/// Tells the assignee of the item it was assigned to them
+async fn notify_assignee(db: &Database, notifier: &SharedNotifier, item: &ActionItem) {
+    let assignee_id = match item.assignee_id {
+        Some(assignee_id) => assignee_id,
+        None => return,
+    };
+
+    let assignee = match db
+        .collection::<User>("users")
+        .find_one(doc! { "_id": assignee_id, "deleted_at": null }, None)
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return,
+        Err(error) => {
+            eprintln!("[NOTIFIER][ACTION_ITEM] ~ {}", error);
+            return;
+        }
+    };
+
+    let due = match item.due_date {
+        Some(due_date) => format!(" It's due on {}.", due_date),
+        None => String::new(),
+    };
+    let draft = Draft {
+        kind: NotificationKind::ACTION_ITEM_ASSIGNED,
+        title: format!("Action item assigned: {}", item.title),
+        body: format!(
+            "The action item \"{}\" was assigned to you.{}",
+            item.title, due
+        ),
+        subject_id: item.id.unwrap(),
+        dedup_key: None,
+    };
+
+    if let Err(error) = notifier.notify(db, &assignee, draft).await {
+        eprintln!("[NOTIFIER][ACTION_ITEM] ~ {}", error);
+    }
+}
+
+#[rocket::post("/", format = "json", data = "<item>")]
+pub async fn create(
+    db_pool: &State<Pool>,
+    notifier: &State<SharedNotifier>,
+    item: Json<ActionItemRequestBody>,
+) -> Result<Response<ActionItemView>, ApiError> {
+    validate(&item.0)?;
+
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+    let collection = db.collection::<ActionItem>("action_items");
+    let user_collection = db.collection::<User>("users");
+
+    let body = item.0;
+    let meeting_id = parse_id(&body.meeting_id)?;
+    let assignee_id = body.assignee_id.as_deref().map(parse_id).transpose()?;
+
+    let meeting = match db
+        .collection::<Meeting>("meetings")
+        .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(meeting) => meeting,
+        None => return Err(Status::NotFound.into()),
+    };
+
+    // action items come out of retros
+    let config = match meeting.config_id {
+        Some(config_id) => db
+            .collection::<MeetingConfig>("meeting_configs")
+            .find_one(doc! { "_id": config_id }, None)
+            .await
+            .unwrap(),
+        None => None,
+    };
+    let is_retro = config
+        .as_ref()
+        .is_some_and(|config| config.meeting_type == MeetingType::RETRO.value());
+    if !is_retro {
+        return Err(Status::Conflict.into());
+    }
+
+    if let Some(assignee_id) = assignee_id {
+        check_reference(&user_collection, assignee_id).await?;
+    }
+
+    let mut new_item = ActionItem {
+        id: None,
+        meeting_id,
+        title: body.title,
+        assignee_id,
+        due_date: body.due_date,
+        done: false,
+        created_at: Utc::now(),
+        deleted_at: None,
+        version: 0,
+    };
+
+    match collection.insert_one(&new_item, None).await {
+        Ok(result) => new_item.id = Some(result.inserted_id.as_object_id().unwrap()),
+        Err(error) => {
+            eprintln!("[INSERT][ACTION_ITEM] ~ {}", error);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    notify_assignee(&db, notifier, &new_item).await;
+
+    let view = ActionItemView::from(new_item);
+    if let Some(team_id) = config.and_then(|config| config.team_id) {
+        webhooks::emit(&db, team_id, WebhookEvent::ACTION_ITEM_CREATED, None, &view).await;
+    }
+
+    Ok(Response::Created(Json(view)))
+}
+
+#[rocket::get("/<item_id>")]
+pub async fn get(
+    db_pool: &State<Pool>,
+    item_id: String,
+) -> Result<Response<ActionItemView>, Status> {
+    let collection = get_collection::<ActionItem>(db_pool, "action_items").await;
+
+    let item = collection
+        .find_one(
+            doc! { "_id": parse_id(&item_id)?, "deleted_at": null },
+            None,
+        )
+        .await
+        .unwrap();
+
+    match item {
+        Some(item) => {
+            let etag = ETag(item.version);
+            Ok(Response::Tagged(Json(item.into()), etag))
+        }
+        None => Err(Status::NotFound),
+    }
+}
+
+/// Partial update of an action item, only the fields sent are changed. A new
+/// assignee is notified.
+#[rocket::patch("/<item_id>", format = "json", data = "<item>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    notifier: &State<SharedNotifier>,
+    item_id: String,
+    if_match: IfMatch,
+    item: Json<ActionItemPatch>,
+) -> Result<Response<ActionItemView>, ApiError> {
+    validate(&item.0)?;
+
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+    let collection = db.collection::<ActionItem>("action_items");
+    let user_collection = db.collection::<User>("users");
+
+    let item_id = parse_id(&item_id)?;
+    let fields = item.0;
+
+    let previous = match collection
+        .find_one(doc! { "_id": item_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(item) => item,
+        None => return Err(Status::NotFound.into()),
+    };
+
+    let mut changes = Document::new();
+    if let Some(title) = fields.title {
+        changes.insert("title", title);
+    }
+    if let Some(assignee_id) = fields.assignee_id {
+        let assignee_id = assignee_id.as_deref().map(parse_id).transpose()?;
+        if let Some(assignee_id) = assignee_id {
+            check_reference(&user_collection, assignee_id).await?;
+        }
+        changes.insert("assignee_id", assignee_id);
+    }
+    if let Some(due_date) = fields.due_date {
+        changes.insert("due_date", due_date.map(|date| date.to_string()));
+    }
+    if let Some(done) = fields.done {
+        changes.insert("done", done);
+    }
+
+    let item = patch_one(&collection, item_id, if_match, changes).await?;
+
+    if item.assignee_id.is_some() && item.assignee_id != previous.assignee_id {
+        notify_assignee(&db, notifier, &item).await;
+    }
+
+    let etag = ETag(item.version);
+    Ok(Response::Tagged(Json(item.into()), etag))
+}
+
+#[rocket::delete("/<item_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    item_id: String,
+    if_match: IfMatch,
+) -> Result<Response<ActionItemView>, Status> {
+    let item_id = parse_id(&item_id)?;
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let result = soft_delete_one::<ActionItem>(
+        &db,
+        &mut session,
+        "action_items",
+        item_id,
+        if_match.0,
+        Utc::now().timestamp_millis(),
+    )
+    .await?;
+
+    let item = match result {
+        Some(item) => item,
+        None => {
+            let exists = is_active(&db, &mut session, "action_items", item_id).await?;
+            return Err(if_match.failed_write_status(exists));
+        }
+    };
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(item.into())))
+}
+
+#[rocket::put("/<item_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    item_id: String,
+    if_match: IfMatch,
+) -> Result<Response<ActionItemView>, Status> {
+    let item_id = parse_id(&item_id)?;
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored =
+        restore_one::<ActionItem>(&db, &mut session, "action_items", item_id, if_match.0).await?;
+    let mut item = match restored {
+        Some(item) => item,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "action_items", item_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // the meeting has to be restored first
+    if !is_active(&db, &mut session, "meetings", item.meeting_id).await? {
+        return Err(Status::Conflict);
+    }
+
+    commit_transaction(&mut session).await?;
+
+    item.deleted_at = None;
+    item.version += 1;
+    Ok(Response::Success(Json(item.into())))
+}
+
+#[rocket::get("/all?<meeting_id>&<assignee_id>&<done>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    meeting_id: Option<String>,
+    assignee_id: Option<String>,
+    done: Option<bool>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<ActionItemView>>, Status> {
+    let collection = get_collection::<ActionItem>(db_pool, "action_items").await;
+    let opts = PageOptions::new(page, limit, sort, &["created_at", "due_date", "_id"])?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(meeting_id) = meeting_id {
+        filter.insert("meeting_id", parse_id(&meeting_id)?);
+    }
+    if let Some(assignee_id) = assignee_id {
+        filter.insert("assignee_id", parse_id(&assignee_id)?);
+    }
+    if let Some(done) = done {
+        filter.insert("done", done);
+    }
+
+    let items = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(items.map(ActionItemView::from))))
+}
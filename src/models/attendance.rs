@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use crate::config::Pool;
+use crate::utils::{
+    concurrency::IfMatch,
+    db::{get_collection, parse_id},
+    responders::{ApiError, Response},
+    validation::{invalid_field, validate},
+};
+use bson::{doc, oid::ObjectId, to_bson};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::{
+    meeting::{meeting_event, Meeting, MeetingStatus},
+    meeting_config::MeetingConfig,
+    team::Team,
+    user::User,
+    webhook::WebhookEvent,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AttendanceStatus {
+    PRESENT,
+    /// Joined after the start, with the minutes of delay
+    LATE,
+    ABSENT,
+    /// Absent with a reason, doesn't lower the attendance rate
+    EXCUSED,
+}
+
+/// Whether a user attended a meeting, there's at most one per meeting and user
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attendance {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) meeting_id: ObjectId,
+    pub(crate) user_id: ObjectId,
+    /// PRESENT | LATE | ABSENT | EXCUSED
+    pub(crate) status: AttendanceStatus,
+    /// Minutes the user joined after the start, only when LATE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) late_minutes: Option<i64>,
+    /// Date and time of the last change of the status
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) recorded_at: DateTime<Utc>,
+    /// Date and time when the attendance was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+/// Attendance as returned by the API, with the name of the user resolved
+#[derive(Serialize, Clone, Debug)]
+pub struct AttendanceView {
+    meeting_id: String,
+    user_id: String,
+    /// Name of the user (empty if the user doesn't exist anymore)
+    user_name: String,
+    status: AttendanceStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    late_minutes: Option<i64>,
+    #[serde(with = "ts_milliseconds")]
+    recorded_at: DateTime<Utc>,
+    version: i64,
+}
+
+impl AttendanceView {
+    pub(crate) fn new(attendance: Attendance, user_name: String) -> Self {
+        Self {
+            meeting_id: attendance.meeting_id.to_hex(),
+            user_id: attendance.user_id.to_hex(),
+            user_name,
+            status: attendance.status,
+            late_minutes: attendance.late_minutes,
+            recorded_at: attendance.recorded_at,
+            version: attendance.version,
+        }
+    }
+}
+
+/// Body of the recording of the attendance of a user
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct AttendanceRequestBody {
+    /// PRESENT | LATE | ABSENT | EXCUSED
+    status: AttendanceStatus,
+    /// Required when LATE, rejected otherwise
+    #[validate(range(min = 1, max = 600))]
+    late_minutes: Option<i64>,
+}
+
+/// Active attendances of a meeting in the order they were recorded, with the
+/// names of the users
+pub(crate) async fn meeting_attendance(
+    db_pool: &State<Pool>,
+    meeting_id: ObjectId,
+) -> Result<Vec<AttendanceView>, mongodb::error::Error> {
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    let opts = FindOptions::builder()
+        .sort(doc! { "recorded_at": 1, "_id": 1 })
+        .build();
+    let attendances = db
+        .collection::<Attendance>("attendances")
+        .find(doc! { "meeting_id": meeting_id, "deleted_at": null }, opts)
+        .await?
+        .try_collect::<Vec<Attendance>>()
+        .await?;
+
+    let users_id: Vec<ObjectId> = attendances.iter().map(|a| a.user_id).collect();
+    let users: HashMap<ObjectId, String> = db
+        .collection::<User>("users")
+        .find(doc! { "_id": { "$in": users_id }, "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<User>>()
+        .await?
+        .into_iter()
+        .map(|user| (user.id.unwrap(), user.name))
+        .collect();
+
+    Ok(attendances
+        .into_iter()
+        .map(|attendance| {
+            let user_name = users.get(&attendance.user_id).cloned().unwrap_or_default();
+            AttendanceView::new(attendance, user_name)
+        })
+        .collect())
+}
+
+/// Records the attendance of a user to a meeting, replacing the previous one. It can
+/// be recorded once the meeting started, during the live session or afterwards, and
+/// only for members of the team of the meeting.
+#[rocket::put("/meeting/<meeting_id>/user/<user_id>", format = "json", data = "<attendance>")]
+pub async fn record(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    user_id: String,
+    if_match: IfMatch,
+    attendance: Json<AttendanceRequestBody>,
+) -> Result<Response<AttendanceView>, ApiError> {
+    validate(&attendance.0)?;
+
+    let body = attendance.0;
+    match (body.status, body.late_minutes) {
+        (AttendanceStatus::LATE, None) => {
+            return Err(invalid_field("late_minutes", "required", "is required when LATE"))
+        }
+        (AttendanceStatus::LATE, Some(_)) | (_, None) => {}
+        (_, Some(_)) => {
+            return Err(invalid_field("late_minutes", "late", "is only allowed when LATE"))
+        }
+    }
+
+    let meeting_id = parse_id(&meeting_id)?;
+    let user_id = parse_id(&user_id)?;
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    let meeting = match db
+        .collection::<Meeting>("meetings")
+        .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(meeting) => meeting,
+        None => return Err(Status::NotFound.into()),
+    };
+
+    let user = match db
+        .collection::<User>("users")
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(user) => user,
+        None => return Err(Status::NotFound.into()),
+    };
+
+    if meeting.date_utc > Utc::now() {
+        return Err(Status::Conflict.into());
+    }
+
+    // the attendance of a meeting with a team is only tracked for its members
+    let config = match meeting.config_id {
+        Some(config_id) => db
+            .collection::<MeetingConfig>("meeting_configs")
+            .find_one(doc! { "_id": config_id }, None)
+            .await
+            .unwrap(),
+        None => None,
+    };
+    if let Some(team_id) = config.and_then(|config| config.team_id) {
+        let is_member = db
+            .collection::<Team>("teams")
+            .find_one(doc! { "_id": team_id, "users": user_id }, None)
+            .await
+            .unwrap()
+            .is_some();
+
+        if !is_member {
+            return Err(Status::Conflict.into());
+        }
+    }
+
+    // with a version the attendance has to be recorded already, `*` records it anyway
+    let mut filter = doc! { "meeting_id": meeting_id, "user_id": user_id };
+    if_match.apply(&mut filter);
+
+    let collection = db.collection::<Attendance>("attendances");
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": {
+                    "status": to_bson(&body.status).unwrap(),
+                    "late_minutes": body.late_minutes,
+                    "recorded_at": Utc::now().timestamp_millis()
+                },
+                "$unset": { "deleted_at": "" },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .upsert(if_match.0.is_none())
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    // the first attendance recorded in a scheduled meeting starts its session
+    if let Ok(Some(_)) = result {
+        if meeting.status == MeetingStatus::SCHEDULED {
+            meeting_event(&db, &meeting, WebhookEvent::MEETING_STARTED).await;
+        }
+    }
+
+    match result {
+        Ok(Some(attendance)) => Ok(Response::Success(Json(AttendanceView::new(
+            attendance, user.name,
+        )))),
+        Ok(None) => {
+            let exists = collection
+                .find_one(doc! { "meeting_id": meeting_id, "user_id": user_id }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
+        Err(error) => {
+            eprintln!("[UPSERT][ATTENDANCE] ~ {}", error);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+#[rocket::get("/meeting/<meeting_id>")]
+pub async fn get_meeting(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<Response<Vec<AttendanceView>>, Status> {
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let meeting_id = parse_id(&meeting_id)?;
+
+    let exists = collection
+        .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .is_some();
+    if !exists {
+        return Err(Status::NotFound);
+    }
+
+    match meeting_attendance(db_pool, meeting_id).await {
+        Ok(attendance) => Ok(Response::Success(Json(attendance))),
+        Err(error) => {
+            eprintln!("[ATTENDANCE] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Forgets the attendance of a user to a meeting, moving it to the trash
+#[rocket::delete("/meeting/<meeting_id>/user/<user_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    user_id: String,
+    if_match: IfMatch,
+) -> Result<Response<AttendanceView>, Status> {
+    let collection = get_collection::<Attendance>(db_pool, "attendances").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let meeting_id = parse_id(&meeting_id)?;
+    let user_id = parse_id(&user_id)?;
+
+    let recorded = doc! { "meeting_id": meeting_id, "user_id": user_id, "deleted_at": null };
+    let mut filter = recorded.clone();
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": { "deleted_at": Utc::now().timestamp_millis() },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    let attendance = match result {
+        Ok(Some(attendance)) => attendance,
+        Ok(None) => {
+            let exists = collection.find_one(recorded, None).await.unwrap().is_some();
+            return Err(if_match.failed_write_status(exists));
+        }
+        Err(error) => {
+            eprintln!("[DELETE][ATTENDANCE] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let user_name = user_collection
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .map(|user| user.name)
+        .unwrap_or_default();
+
+    Ok(Response::Success(Json(AttendanceView::new(attendance, user_name))))
+}
@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::config::Pool;
+use crate::utils::{
+    db::{get_collection, parse_id},
+    ical::{self, Calendar},
+};
+use bson::{doc, oid::ObjectId};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use rocket::http::{ContentType, Status};
+use rocket::State;
+
+use super::{
+    meeting::Meeting,
+    meeting_config::MeetingConfig,
+    team::{calendars_by_config, Team},
+    user::User,
+};
+
+/// Days of past meetings included in the feeds
+const FEED_HISTORY_DAYS: i64 = 90;
+
+/// Days ahead the occurrences on days off are excluded from the recurring events
+const FEED_AHEAD_DAYS: i64 = 366;
+
+/// Active user owning the calendar token
+async fn token_owner(db_pool: &State<Pool>, token: &str) -> Result<User, Status> {
+    let collection = get_collection::<User>(db_pool, "users").await;
+
+    match collection
+        .find_one(doc! { "calendar_token": token, "deleted_at": null }, None)
+        .await
+    {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(Status::NotFound),
+        Err(error) => {
+            eprintln!("[CALENDAR][USER] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Adds the meetings of the configs to the calendar. A scheduled config is a
+/// recurring event: its cancelled occurrences are excluded and the ones held at
+/// other dates are overridden. The meetings created by hand are single events.
+async fn add_meetings(
+    db_pool: &State<Pool>,
+    calendar: &mut Calendar,
+    configs: Vec<MeetingConfig>,
+) -> Result<(), Status> {
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+
+    let since = Utc::now() - Duration::days(FEED_HISTORY_DAYS);
+    let configs_id: Vec<ObjectId> = configs.iter().filter_map(|config| config.id).collect();
+
+    // deleted meetings are needed too, they are the cancelled occurrences
+    let meetings = match collection
+        .find(
+            doc! {
+                "config_id": { "$in": &configs_id },
+                "date_utc": { "$gte": since.timestamp_millis() }
+            },
+            None,
+        )
+        .await
+    {
+        Ok(cursor) => cursor.try_collect::<Vec<Meeting>>().await,
+        Err(error) => Err(error),
+    };
+
+    let meetings = match meetings {
+        Ok(meetings) => meetings,
+        Err(error) => {
+            eprintln!("[CALENDAR][MEETING] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+    let calendars = match calendars_by_config(&db, configs_id).await {
+        Ok(calendars) => calendars,
+        Err(error) => {
+            eprintln!("[CALENDAR][TEAM] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let mut meetings_by_config: HashMap<ObjectId, Vec<Meeting>> = HashMap::new();
+    for meeting in meetings {
+        if let Some(config_id) = meeting.config_id {
+            meetings_by_config.entry(config_id).or_default().push(meeting);
+        }
+    }
+
+    // the recurring events start at their first occurrence, in the timezone of
+    // the schedule, which has to be defined from then on
+    let until = Utc::now() + Duration::days(FEED_AHEAD_DAYS);
+    let mut first_occurrences: HashMap<ObjectId, DateTime<Tz>> = HashMap::new();
+    let mut timezones: BTreeMap<&str, (Tz, DateTime<Utc>)> = BTreeMap::new();
+    for config in &configs {
+        let schedule = match &config.schedule {
+            Some(schedule) => schedule,
+            None => continue,
+        };
+        let tz = match schedule.timezone.parse::<Tz>() {
+            Ok(tz) => tz,
+            Err(_) => continue,
+        };
+        if let Some(first) = schedule.first_occurrence() {
+            first_occurrences.insert(config.id.unwrap(), first.with_timezone(&tz));
+            let (_, from) = timezones.entry(tz.name()).or_insert((tz, first));
+            *from = first.min(*from);
+        }
+    }
+    for (tz, from) in timezones.into_values() {
+        calendar.timezone(tz, from, until);
+    }
+
+    let now = ical::utc(Utc::now());
+    for config in configs {
+        let config_id = config.id.unwrap();
+        let series = ical::uid("config", &config_id.to_hex());
+        let meetings = meetings_by_config.remove(&config_id).unwrap_or_default();
+
+        // occurrences that are still part of the current schedule, the ones of a
+        // previous schedule are shown as single events
+        let in_series = |meeting: &Meeting| match (&config.schedule, meeting.occurrence_utc) {
+            (Some(schedule), Some(occurrence)) => schedule
+                .occurrences(occurrence)
+                .map(|mut occurrences| occurrences.next() == Some(occurrence))
+                .unwrap_or(false),
+            _ => false,
+        };
+
+        if let (Some(schedule), Some(first)) =
+            (&config.schedule, first_occurrences.get(&config_id))
+        {
+            calendar
+                .property("BEGIN", "VEVENT")
+                .property("UID", &series)
+                .property("DTSTAMP", &now)
+                .property(
+                    &format!("DTSTART;TZID={}", schedule.timezone),
+                    &ical::local(first.date_naive().and_time(schedule.start_time)),
+                )
+                .property("DURATION", &format!("PT{}S", config.desired_duration))
+                .property("RRULE", schedule.rrule.trim_start_matches("RRULE:"))
+                .text("SUMMARY", &config.config_name)
+                .text("DESCRIPTION", &config.description);
+
+            // cancelled occurrences and the ones on days off of the team
+            let mut excluded: BTreeSet<DateTime<Utc>> = meetings
+                .iter()
+                .filter(|meeting| meeting.deleted_at.is_some() && in_series(meeting))
+                .filter_map(|meeting| meeting.occurrence_utc)
+                .collect();
+            if let (Some(work_calendar), Ok(occurrences)) =
+                (calendars.get(&config_id), schedule.occurrences(since))
+            {
+                excluded.extend(
+                    occurrences
+                        .take_while(|start_utc| *start_utc < until)
+                        .filter(|start_utc| {
+                            !work_calendar
+                                .is_working_day(work_calendar.local(*start_utc).date_naive())
+                        }),
+                );
+            }
+
+            for occurrence in excluded {
+                calendar.property("EXDATE", &ical::utc(occurrence));
+            }
+
+            calendar.property("END", "VEVENT");
+        }
+
+        for meeting in meetings.iter().filter(|meeting| meeting.deleted_at.is_none()) {
+            let uid = if in_series(meeting) {
+                let planned_end =
+                    meeting.occurrence_utc.unwrap() + Duration::seconds(config.desired_duration);
+
+                // the occurrence as planned is already part of the recurring event
+                if meeting.occurrence_utc == Some(meeting.date_utc)
+                    && meeting.end_utc == planned_end
+                {
+                    continue;
+                }
+                series.clone()
+            } else {
+                ical::uid("meeting", &meeting.id.unwrap().to_hex())
+            };
+
+            calendar
+                .property("BEGIN", "VEVENT")
+                .property("UID", &uid)
+                .property("DTSTAMP", &now);
+            if uid == series {
+                calendar.property("RECURRENCE-ID", &ical::utc(meeting.occurrence_utc.unwrap()));
+            }
+            calendar
+                .property("DTSTART", &ical::utc(meeting.date_utc))
+                .property("DTEND", &ical::utc(meeting.end_utc))
+                .text("SUMMARY", &config.config_name)
+                .text("DESCRIPTION", &config.description)
+                .property("END", "VEVENT");
+        }
+    }
+
+    Ok(())
+}
+
+/// Active configs of the teams
+async fn team_configs(
+    db_pool: &State<Pool>,
+    teams_id: Vec<ObjectId>,
+) -> Result<Vec<MeetingConfig>, Status> {
+    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+
+    let configs = match collection
+        .find(doc! { "team_id": { "$in": teams_id }, "deleted_at": null }, None)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect::<Vec<MeetingConfig>>().await,
+        Err(error) => Err(error),
+    };
+
+    match configs {
+        Ok(configs) => Ok(configs),
+        Err(error) => {
+            eprintln!("[CALENDAR][MEETING_CONFIG] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Feed with the meetings of every team of the owner of the token
+#[rocket::get("/<token>/meetings.ics")]
+pub async fn user_feed(
+    db_pool: &State<Pool>,
+    token: String,
+) -> Result<(ContentType, String), Status> {
+    let user = token_owner(db_pool, &token).await?;
+    let team_collection = get_collection::<Team>(db_pool, "teams").await;
+    let user_id = user.id.unwrap();
+
+    let teams = match team_collection
+        .find(
+            doc! {
+                "$or": [{ "users": user_id }, { "owner": user_id }],
+                "deleted_at": null
+            },
+            None,
+        )
+        .await
+    {
+        Ok(cursor) => cursor.try_collect::<Vec<Team>>().await,
+        Err(error) => Err(error),
+    };
+
+    let teams_id = match teams {
+        Ok(teams) => teams.into_iter().filter_map(|team| team.id).collect(),
+        Err(error) => {
+            eprintln!("[CALENDAR][TEAM] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let configs = team_configs(db_pool, teams_id).await?;
+    let mut calendar = Calendar::new(&format!("Meetings of {}", user.name));
+    add_meetings(db_pool, &mut calendar, configs).await?;
+
+    Ok((ContentType::Calendar, calendar.build()))
+}
+
+/// Feed with the meetings of a team, only available to its members
+#[rocket::get("/<token>/team/<team_id>/meetings.ics")]
+pub async fn team_feed(
+    db_pool: &State<Pool>,
+    token: String,
+    team_id: String,
+) -> Result<(ContentType, String), Status> {
+    let user = token_owner(db_pool, &token).await?;
+    let team_collection = get_collection::<Team>(db_pool, "teams").await;
+    let team_id = parse_id(&team_id)?;
+    let user_id = user.id.unwrap();
+
+    // the feed of a team the user isn't part of doesn't exist for them
+    let team = match team_collection
+        .find_one(
+            doc! {
+                "_id": team_id,
+                "$or": [{ "users": user_id }, { "owner": user_id }],
+                "deleted_at": null
+            },
+            None,
+        )
+        .await
+        .unwrap()
+    {
+        Some(team) => team,
+        None => return Err(Status::NotFound),
+    };
+
+    let configs = team_configs(db_pool, vec![team_id]).await?;
+    let mut calendar = Calendar::new(&team.name);
+    add_meetings(db_pool, &mut calendar, configs).await?;
+
+    Ok((ContentType::Calendar, calendar.build()))
+}
@@ -0,0 +1,395 @@
+use crate::chat;
+use crate::config::Pool;
+use crate::utils::{
+    concurrency::IfMatch,
+    db::{get_collection, parse_id},
+    responders::{ApiError, Response},
+    validation::{public_url, validate},
+};
+use bson::{doc, oid::ObjectId, to_bson};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Utc};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use rocket::data::{self, Data, FromData, ToByteUnit};
+use rocket::http::RawStr;
+use rocket::outcome::Outcome;
+use rocket::request::Request;
+use rocket::serde::json::Value;
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::{meeting::Meeting, team::Team};
+
+/// Format of the messages posted to the channel of a team
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ChatFormat {
+    /// Slack Block Kit
+    SLACK,
+    /// Microsoft Teams Adaptive Card
+    TEAMS,
+}
+
+/// Incoming webhook of the channel of a team, where the summaries of its dailies
+/// are posted
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatSettings {
+    pub(crate) format: ChatFormat,
+    pub(crate) webhook_url: String,
+}
+
+/// Body of the update of the chat settings of a team
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct ChatSettingsRequestBody {
+    /// SLACK | TEAMS
+    format: ChatFormat,
+    #[validate(custom = "public_url", length(max = 2048))]
+    webhook_url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SummaryStatus {
+    POSTED,
+    /// The last attempt failed, it's retried until it's posted or runs out of attempts
+    FAILED,
+}
+
+/// Summary of a meeting posted to the channel of its team, one per meeting
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatSummary {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) meeting_id: ObjectId,
+    pub(crate) team_id: ObjectId,
+    pub(crate) format: ChatFormat,
+    pub(crate) status: SummaryStatus,
+    pub(crate) attempts: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_error: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) updated_at: DateTime<Utc>,
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) posted_at: Option<DateTime<Utc>>,
+}
+
+/// Meeting run from the chat with the slash commands, there's at most one per team
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatSession {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) team_id: ObjectId,
+    /// Scheduled meeting being held
+    pub(crate) meeting_id: ObjectId,
+    /// User who started it
+    pub(crate) started_by: ObjectId,
+    /// Members expected to speak, in order
+    pub(crate) speaking_order: Vec<ObjectId>,
+    /// Position in `speaking_order` of who's speaking, past the end once everyone spoke
+    pub(crate) current: i32,
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) started_at: DateTime<Utc>,
+    /// Date and time when whoever's speaking started
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) turn_started_at: DateTime<Utc>,
+}
+
+/// Summary as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct ChatSummaryView {
+    meeting_id: String,
+    team_id: String,
+    format: ChatFormat,
+    status: SummaryStatus,
+    attempts: i32,
+    last_error: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    updated_at: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds_option")]
+    posted_at: Option<DateTime<Utc>>,
+}
+
+impl From<ChatSummary> for ChatSummaryView {
+    fn from(summary: ChatSummary) -> Self {
+        Self {
+            meeting_id: summary.meeting_id.to_hex(),
+            team_id: summary.team_id.to_hex(),
+            format: summary.format,
+            status: summary.status,
+            attempts: summary.attempts,
+            last_error: summary.last_error,
+            updated_at: summary.updated_at,
+            posted_at: summary.posted_at,
+        }
+    }
+}
+
+async fn active_team(db_pool: &State<Pool>, team_id: ObjectId) -> Result<Team, Status> {
+    let collection = get_collection::<Team>(db_pool, "teams").await;
+
+    match collection
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(team) => Ok(team),
+        None => Err(Status::NotFound),
+    }
+}
+
+async fn active_meeting(db_pool: &State<Pool>, meeting_id: ObjectId) -> Result<Meeting, Status> {
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+
+    match collection
+        .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(meeting) => Ok(meeting),
+        None => Err(Status::NotFound),
+    }
+}
+
+#[rocket::get("/team/<team_id>")]
+pub async fn get_settings(
+    db_pool: &State<Pool>,
+    team_id: String,
+) -> Result<Response<ChatSettings>, Status> {
+    let team = active_team(db_pool, parse_id(&team_id)?).await?;
+
+    match team.chat {
+        Some(settings) => Ok(Response::Success(Json(settings))),
+        None => Err(Status::NotFound),
+    }
+}
+
+#[rocket::put("/team/<team_id>", format = "json", data = "<settings>")]
+pub async fn update_settings(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+    settings: Json<ChatSettingsRequestBody>,
+) -> Result<Response<ChatSettings>, ApiError> {
+    validate(&settings.0)?;
+
+    let settings = ChatSettings {
+        format: settings.0.format,
+        webhook_url: settings.0.webhook_url,
+    };
+
+    let collection = get_collection::<Team>(db_pool, "teams").await;
+    let team_id = parse_id(&team_id)?;
+    let mut filter = doc! { "_id": team_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": { "chat": to_bson(&settings).unwrap() },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    match result {
+        Ok(Some(_)) => Ok(Response::Success(Json(settings))),
+        Ok(None) => {
+            let exists = collection
+                .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
+        Err(error) => {
+            eprintln!("[UPDATE][CHAT_SETTINGS] ~ {}", error);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+/// Stops posting the summaries of the team, returning the settings removed
+#[rocket::delete("/team/<team_id>")]
+pub async fn delete_settings(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+) -> Result<Response<ChatSettings>, Status> {
+    let collection = get_collection::<Team>(db_pool, "teams").await;
+    let team_id = parse_id(&team_id)?;
+    let configured = doc! { "_id": team_id, "chat": { "$ne": null }, "deleted_at": null };
+    let mut filter = configured.clone();
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! { "$unset": { "chat": "" }, "$inc": { "version": 1 } },
+            None,
+        )
+        .await;
+
+    match result {
+        Ok(Some(team)) => Ok(Response::Success(Json(team.chat.unwrap()))),
+        Ok(None) => {
+            let exists = collection.find_one(configured, None).await.unwrap().is_some();
+            Err(if_match.failed_write_status(exists))
+        }
+        Err(error) => {
+            eprintln!("[UPDATE][CHAT_SETTINGS] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Message summing up a meeting as it's posted to the channel of its team, in the
+/// `format` asked or the one of the team (Slack by default)
+#[rocket::get("/meeting/<meeting_id>/summary?<format>")]
+pub async fn preview(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    format: Option<String>,
+) -> Result<Response<Value>, Status> {
+    let format = match format.as_deref() {
+        Some("SLACK") => Some(ChatFormat::SLACK),
+        Some("TEAMS") => Some(ChatFormat::TEAMS),
+        Some(_) => return Err(Status::UnprocessableEntity),
+        None => None,
+    };
+
+    let meeting = active_meeting(db_pool, parse_id(&meeting_id)?).await?;
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    let (team, summary) = match chat::summarize(&db, &meeting).await {
+        Ok(Some(summary)) => summary,
+        Ok(None) => return Err(Status::Conflict),
+        Err(error) => {
+            eprintln!("[CHAT][SUMMARY] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let format = format
+        .or(team.chat.map(|settings| settings.format))
+        .unwrap_or(ChatFormat::SLACK);
+    Ok(Response::Success(Json(chat::render(format, &summary))))
+}
+
+/// Posts the summary of a meeting to the channel of its team right away, even if
+/// it was already posted. A team without chat settings returns a 409.
+#[rocket::post("/meeting/<meeting_id>/summary")]
+pub async fn post(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<Response<ChatSummaryView>, Status> {
+    let meeting = active_meeting(db_pool, parse_id(&meeting_id)?).await?;
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    match chat::post_summary(&db, &meeting).await {
+        Ok(Some(summary)) => Ok(Response::Success(Json(summary.into()))),
+        Ok(None) => Err(Status::Conflict),
+        Err(error) => {
+            eprintln!("[CHAT][SUMMARY] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Seconds a slash command is accepted after Slack signed it, older ones could be
+/// replayed
+const SLASH_COMMAND_MAX_AGE_SECONDS: i64 = 5 * 60;
+
+/// Slash command posted by Slack (`application/x-www-form-urlencoded`), only the
+/// fields used are kept
+#[derive(Debug)]
+pub struct SlashCommand {
+    /// Id of the Slack user who typed it
+    user_id: String,
+    /// What follows the command (`start`, `stats Team A`...)
+    text: String,
+}
+
+impl SlashCommand {
+    /// Reads the fields of the form, `None` without user or with a value that
+    /// isn't UTF-8
+    fn parse(body: &str) -> Option<Self> {
+        let mut user_id = None;
+        let mut text = String::new();
+
+        for field in body.split('&') {
+            let (name, value) = field.split_once('=').unwrap_or((field, ""));
+            let value = RawStr::new(value).url_decode().ok()?.into_owned();
+            match name {
+                "user_id" => user_id = Some(value),
+                "text" => text = value,
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            user_id: user_id?,
+            text,
+        })
+    }
+}
+
+/// The request has to be signed with the `SLACK_SIGNING_SECRET` variable, without
+/// it the slash commands are disabled and fail with a 403
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SlashCommand {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let secret = match std::env::var("SLACK_SIGNING_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret,
+            _ => return Outcome::Failure((Status::Forbidden, ())),
+        };
+
+        // the signature is computed over the raw body
+        let body = match data.open(16.kibibytes()).into_string().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, ())),
+            Err(_) => return Outcome::Failure((Status::BadRequest, ())),
+        };
+
+        let headers = request.headers();
+        let timestamp = headers.get_one("X-Slack-Request-Timestamp").unwrap_or_default();
+        let signature = headers.get_one("X-Slack-Signature").unwrap_or_default();
+        let is_recent = timestamp.parse::<i64>().is_ok_and(|timestamp| {
+            (Utc::now().timestamp() - timestamp).abs() <= SLASH_COMMAND_MAX_AGE_SECONDS
+        });
+        if !is_recent || !chat::slack::verify(&secret, timestamp, &body, signature) {
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
+
+        match SlashCommand::parse(&body) {
+            Some(command) => Outcome::Success(command),
+            None => Outcome::Failure((Status::UnprocessableEntity, ())),
+        }
+    }
+}
+
+/// Slash command of Slack (`/standup start|next|finish|stats|help [team]`),
+/// answered with the message to show in the channel
+#[rocket::post("/slack/command", data = "<command>")]
+pub async fn slash_command(
+    db_pool: &State<Pool>,
+    command: SlashCommand,
+) -> Result<Response<Value>, Status> {
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    match chat::commands::run(&db, &command.user_id, &command.text).await {
+        Ok(reply) => Ok(Response::Success(Json(reply))),
+        Err(error) => {
+            eprintln!("[CHAT][COMMAND] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
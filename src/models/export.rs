@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use crate::config::Pool;
+use crate::utils::{csv::to_row, db::get_collection};
+use bson::{doc, oid::ObjectId, Document};
+use futures::TryStreamExt;
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Cursor};
+use rocket::http::{ContentType, Status};
+use rocket::response::stream::TextStream;
+use rocket::State;
+
+use super::{
+    meeting::{Meeting, MeetingStatus},
+    meeting_config::MeetingConfig,
+    team::Team,
+    user::User,
+    user_time::{SegmentMarker, UserTime},
+};
+
+/// Filters accepted by every export endpoint
+#[derive(Debug)]
+pub struct ExportFilter {
+    /// Only meetings started at or after this date (ms since epoch)
+    from: Option<i64>,
+    /// Only meetings started at or before this date (ms since epoch)
+    to: Option<i64>,
+    /// Only meetings created from this meeting config
+    config_id: Option<String>,
+}
+
+impl ExportFilter {
+    pub fn new(from: Option<i64>, to: Option<i64>, config_id: Option<String>) -> Self {
+        Self {
+            from,
+            to,
+            config_id,
+        }
+    }
+}
+
+/// Meetings of a team matching the filter, sorted by date, together with the
+/// configs of the team indexed by their id
+pub(crate) async fn team_meetings(
+    db_pool: &State<Pool>,
+    team_id: String,
+    filter: &ExportFilter,
+) -> Result<(Cursor<Meeting>, HashMap<ObjectId, MeetingConfig>), Status> {
+    let team_collection = get_collection::<Team>(db_pool, "teams").await;
+    let config_collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+    let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
+
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let team_exists = team_collection
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .is_some();
+
+    if !team_exists {
+        return Err(Status::NotFound);
+    }
+
+    let mut config_query = doc! { "team_id": team_id, "deleted_at": null };
+    if let Some(config_id) = &filter.config_id {
+        match ObjectId::parse_str(config_id) {
+            Ok(id) => config_query.insert("_id", id),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+    }
+
+    let configs = match config_collection.find(config_query, None).await {
+        Ok(cursor) => cursor.try_collect::<Vec<MeetingConfig>>().await,
+        Err(error) => Err(error),
+    };
+
+    let configs: HashMap<ObjectId, MeetingConfig> = match configs {
+        Ok(configs) => configs
+            .into_iter()
+            .map(|config| (config.id.unwrap(), config))
+            .collect(),
+        Err(error) => {
+            eprintln!("[EXPORT][MEETING_CONFIG] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let mut date_query = Document::new();
+    if let Some(from) = filter.from {
+        date_query.insert("$gte", from);
+    }
+    if let Some(to) = filter.to {
+        date_query.insert("$lte", to);
+    }
+
+    // the scheduled meetings didn't take place yet
+    let config_ids: Vec<ObjectId> = configs.keys().cloned().collect();
+    let mut meeting_query = doc! { "config_id": { "$in": config_ids }, "deleted_at": null };
+    meeting_query.extend(MeetingStatus::HELD.filter());
+    if !date_query.is_empty() {
+        meeting_query.insert("date_utc", date_query);
+    }
+
+    let opts = FindOptions::builder().sort(doc! { "date_utc": 1 }).build();
+
+    match meeting_collection.find(meeting_query, opts).await {
+        Ok(cursor) => Ok((cursor, configs)),
+        Err(error) => {
+            eprintln!("[EXPORT][MEETING] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Looks a user up, caching the result so every user is only fetched once per export
+async fn cached_user(
+    collection: &Collection<User>,
+    cache: &mut HashMap<ObjectId, Option<User>>,
+    user_id: ObjectId,
+) -> Option<User> {
+    if let Some(user) = cache.get(&user_id) {
+        return user.clone();
+    }
+
+    let user = collection
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+        .await
+        .unwrap_or(None);
+    cache.insert(user_id, user.clone());
+
+    user
+}
+
+#[rocket::get("/team/<team_id>/meetings.csv?<from>&<to>&<config_id>")]
+pub async fn meetings(
+    db_pool: &State<Pool>,
+    team_id: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    config_id: Option<String>,
+) -> Result<(ContentType, TextStream![String]), Status> {
+    let (mut cursor, configs) =
+        team_meetings(db_pool, team_id, &ExportFilter::new(from, to, config_id)).await?;
+
+    let stream = TextStream! {
+        yield to_row((
+            "meeting_id",
+            "date_utc",
+            "end_utc",
+            "config_id",
+            "config_name",
+            "meeting_type",
+            "desired_duration",
+            "duration_ms",
+        ));
+
+        loop {
+            let meeting = match cursor.try_next().await {
+                Ok(Some(meeting)) => meeting,
+                Ok(None) => break,
+                Err(error) => {
+                    eprintln!("[EXPORT][MEETING] ~ {}", error);
+                    break;
+                }
+            };
+            let config = &configs[&meeting.config_id.unwrap()];
+
+            yield to_row((
+                meeting.id.unwrap().to_hex(),
+                meeting.date_utc.to_rfc3339(),
+                meeting.end_utc.to_rfc3339(),
+                config.id.unwrap().to_hex(),
+                &config.config_name,
+                &config.meeting_type,
+                config.desired_duration,
+                meeting.duration_ms(),
+            ));
+        }
+    };
+
+    Ok((ContentType::CSV, stream))
+}
+
+#[rocket::get("/team/<team_id>/user_times.csv?<from>&<to>&<config_id>")]
+pub async fn user_times(
+    db_pool: &State<Pool>,
+    team_id: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    config_id: Option<String>,
+) -> Result<(ContentType, TextStream![String]), Status> {
+    let (mut cursor, configs) =
+        team_meetings(db_pool, team_id, &ExportFilter::new(from, to, config_id)).await?;
+    let user_time_collection = get_collection::<UserTime>(db_pool, "user_times").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let stream = TextStream! {
+        let mut users = HashMap::<ObjectId, Option<User>>::new();
+
+        yield to_row((
+            "meeting_id",
+            "date_utc",
+            "config_name",
+            "user_id",
+            "user_name",
+            "user_email",
+            "turns",
+            "interruptions",
+            "crosstalks",
+            "time_ms",
+        ));
+
+        'meetings: loop {
+            let meeting = match cursor.try_next().await {
+                Ok(Some(meeting)) => meeting,
+                Ok(None) => break,
+                Err(error) => {
+                    eprintln!("[EXPORT][MEETING] ~ {}", error);
+                    break;
+                }
+            };
+            let config = &configs[&meeting.config_id.unwrap()];
+
+            let opts = FindOptions::builder().sort(doc! { "start_utc": 1 }).build();
+            let mut times = match user_time_collection
+                .find(
+                    doc! { "meeting_id": meeting.id.unwrap(), "deleted_at": null },
+                    opts,
+                )
+                .await
+            {
+                Ok(cursor) => cursor,
+                Err(error) => {
+                    eprintln!("[EXPORT][USER_TIME] ~ {}", error);
+                    break;
+                }
+            };
+
+            // one row per user adding up all of their turns, in the order they first spoke
+            let mut totals: Vec<(ObjectId, [u32; 3], i64)> = vec![];
+            loop {
+                // the rows are already being sent so the status can't change anymore,
+                // stop the export rather than yield partial totals for the meeting
+                let user_time = match times.try_next().await {
+                    Ok(Some(user_time)) => user_time,
+                    Ok(None) => break,
+                    Err(error) => {
+                        eprintln!("[EXPORT][USER_TIME] ~ {}", error);
+                        break 'meetings;
+                    }
+                };
+                let user_id = user_time.user_id.unwrap();
+                let position = match totals.iter().position(|(id, _, _)| *id == user_id) {
+                    Some(position) => position,
+                    None => {
+                        totals.push((user_id, [0; 3], 0));
+                        totals.len() - 1
+                    }
+                };
+
+                // turns, interruptions and crosstalks
+                let (_, counts, time_ms) = &mut totals[position];
+                counts[0] += 1;
+                match user_time.marker {
+                    Some(SegmentMarker::INTERRUPTION) => counts[1] += 1,
+                    Some(SegmentMarker::CROSSTALK) => counts[2] += 1,
+                    None => {}
+                }
+                *time_ms += user_time.duration_ms();
+            }
+
+            for (user_id, [turns, interruptions, crosstalks], time_ms) in totals {
+                let user = cached_user(&user_collection, &mut users, user_id).await;
+                let (name, email) = match user {
+                    Some(user) => (user.name, user.email),
+                    None => (String::new(), String::new()),
+                };
+
+                yield to_row((
+                    meeting.id.unwrap().to_hex(),
+                    meeting.date_utc.to_rfc3339(),
+                    &config.config_name,
+                    user_id.to_hex(),
+                    name,
+                    email,
+                    turns,
+                    interruptions,
+                    crosstalks,
+                    time_ms,
+                ));
+            }
+        }
+    };
+
+    Ok((ContentType::CSV, stream))
+}
+
+#[rocket::get("/team/<team_id>/overruns.csv?<from>&<to>&<config_id>")]
+pub async fn overruns(
+    db_pool: &State<Pool>,
+    team_id: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    config_id: Option<String>,
+) -> Result<(ContentType, TextStream![String]), Status> {
+    let (mut cursor, configs) =
+        team_meetings(db_pool, team_id, &ExportFilter::new(from, to, config_id)).await?;
+
+    let stream = TextStream! {
+        yield to_row((
+            "meeting_id",
+            "date_utc",
+            "config_name",
+            "desired_duration",
+            "duration_ms",
+            "overrun_ms",
+        ));
+
+        loop {
+            let meeting = match cursor.try_next().await {
+                Ok(Some(meeting)) => meeting,
+                Ok(None) => break,
+                Err(error) => {
+                    eprintln!("[EXPORT][MEETING] ~ {}", error);
+                    break;
+                }
+            };
+            let config = &configs[&meeting.config_id.unwrap()];
+            let duration_ms = meeting.duration_ms();
+            let overrun_ms = duration_ms - config.desired_duration * 1000;
+
+            // only the meetings that lasted longer than desired are reported
+            if overrun_ms > 0 {
+                yield to_row((
+                    meeting.id.unwrap().to_hex(),
+                    meeting.date_utc.to_rfc3339(),
+                    &config.config_name,
+                    config.desired_duration,
+                    duration_ms,
+                    overrun_ms,
+                ));
+            }
+        }
+    };
+
+    Ok((ContentType::CSV, stream))
+}
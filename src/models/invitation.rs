@@ -0,0 +1,382 @@
+use crate::config::Pool;
+use crate::mailer::{Mail, SharedMailer};
+use crate::utils::{
+    concurrency::IfMatch,
+    db::{commit_transaction, get_collection, start_transaction},
+    responders::{ApiError, Response},
+    token,
+    validation::{object_id, validate},
+};
+use bson::{doc, oid::ObjectId};
+use chrono::serde::ts_milliseconds;
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::{
+    team::{add_users, Team},
+    user::{normalize_email, User},
+};
+
+/// Days until an invitation can't be accepted anymore
+const INVITATION_TTL_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum InvitationStatus {
+    PENDING,
+    ACCEPTED,
+    DECLINED,
+    REVOKED,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Invitation {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    /// Team the user is invited to
+    pub(crate) team_id: ObjectId,
+    /// Email of the invited user
+    pub(crate) email: String,
+    /// Scrum master that sent the invitation
+    pub(crate) invited_by: ObjectId,
+    /// Secret sent by email that allows to answer the invitation
+    pub(crate) token: String,
+    /// Status of the invitation (PENDING | ACCEPTED | DECLINED | REVOKED)
+    pub(crate) status: InvitationStatus,
+    /// Date and time when the invitation was sent
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) created_at: DateTime<Utc>,
+    /// Date and time when the invitation expires
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) expires_at: DateTime<Utc>,
+}
+
+/// Invitation as returned by the API, without the token
+#[derive(Serialize, Clone, Debug)]
+pub struct InvitationView {
+    id: String,
+    team_id: String,
+    email: String,
+    invited_by: String,
+    status: InvitationStatus,
+    #[serde(with = "ts_milliseconds")]
+    created_at: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    expires_at: DateTime<Utc>,
+}
+
+impl From<Invitation> for InvitationView {
+    fn from(invitation: Invitation) -> Self {
+        Self {
+            id: invitation.id.unwrap().to_hex(),
+            team_id: invitation.team_id.to_hex(),
+            email: invitation.email,
+            invited_by: invitation.invited_by.to_hex(),
+            status: invitation.status,
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct InvitationRequestBody {
+    #[validate(custom = "object_id")]
+    team_id: String,
+    #[validate(email, length(max = 254))]
+    email: String,
+    /// Id of the scrum master (owner) of the team
+    #[validate(custom = "object_id")]
+    invited_by: String,
+}
+
+#[rocket::post("/", format = "json", data = "<invitation>")]
+pub async fn create(
+    db_pool: &State<Pool>,
+    mailer: &State<SharedMailer>,
+    invitation: Json<InvitationRequestBody>,
+) -> Result<Response<InvitationView>, ApiError> {
+    validate(&invitation.0)?;
+
+    let collection = get_collection::<Invitation>(db_pool, "invitations").await;
+    let team_collection = get_collection::<Team>(db_pool, "teams").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let body = invitation.0;
+    let email = normalize_email(&body.email);
+
+    let team_id = match ObjectId::parse_str(&body.team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+    let invited_by = match ObjectId::parse_str(&body.invited_by) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let team = match team_collection
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(team) => team,
+        None => return Err(Status::NotFound.into()),
+    };
+
+    // only the scrum master of the team can invite people
+    if team.owner != Some(invited_by) {
+        return Err(Status::Forbidden.into());
+    }
+
+    // the user may already be a member of the team
+    let invited_user = user_collection
+        .find_one(doc! { "email": &email, "deleted_at": null }, None)
+        .await
+        .unwrap();
+
+    if let Some(user) = invited_user {
+        if team.users.unwrap_or_default().contains(&user.get_id()) {
+            return Err(Status::Conflict.into());
+        }
+    }
+
+    let now = Utc::now();
+
+    // or have been invited already
+    let already_invited = collection
+        .find_one(
+            doc! {
+                "team_id": team_id,
+                "email": &email,
+                "status": "PENDING",
+                "expires_at": { "$gt": now.timestamp_millis() }
+            },
+            None,
+        )
+        .await
+        .unwrap()
+        .is_some();
+
+    if already_invited {
+        return Err(Status::Conflict.into());
+    }
+
+    let mut new_invitation = Invitation {
+        id: None,
+        team_id,
+        email,
+        invited_by,
+        token: token::generate(),
+        status: InvitationStatus::PENDING,
+        created_at: now,
+        expires_at: now + Duration::days(INVITATION_TTL_DAYS),
+    };
+
+    match collection.insert_one(&new_invitation, None).await {
+        Ok(result) => new_invitation.id = Some(result.inserted_id.as_object_id().unwrap()),
+        Err(error) => {
+            eprintln!("[INSERT][INVITATION] ~ {}", error);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    let app_url = std::env::var("APP_URL").unwrap_or_default();
+    let mail = Mail {
+        to: new_invitation.email.clone(),
+        subject: format!("You have been invited to join {}", team.name),
+        body: format!(
+            "You have been invited to join the team {} in Scrum Master Tools.\n\n\
+             Accept or decline the invitation here: {}/invitations/{}\n\n\
+             The invitation expires in {} days.",
+            team.name, app_url, new_invitation.token, INVITATION_TTL_DAYS
+        ),
+    };
+
+    // the invitation is kept even if the email can't be delivered, it can be sent again later
+    if let Err(error) = mailer.send(&mail).await {
+        eprintln!("[MAILER][INVITATION] ~ {}", error);
+    }
+
+    Ok(Response::Created(Json(new_invitation.into())))
+}
+
+#[rocket::get("/?<team_id>")]
+pub async fn pending(
+    db_pool: &State<Pool>,
+    team_id: String,
+) -> Result<Response<Vec<InvitationView>>, Status> {
+    let collection = get_collection::<Invitation>(db_pool, "invitations").await;
+
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let invitations = collection
+        .find(
+            doc! {
+                "team_id": team_id,
+                "status": "PENDING",
+                "expires_at": { "$gt": Utc::now().timestamp_millis() }
+            },
+            None,
+        )
+        .await
+        .unwrap()
+        .try_collect::<Vec<Invitation>>()
+        .await;
+
+    match invitations {
+        Ok(invitations) => Ok(Response::Success(Json(
+            invitations.into_iter().map(InvitationView::from).collect(),
+        ))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[rocket::get("/<token>")]
+pub async fn get(db_pool: &State<Pool>, token: String) -> Result<Response<InvitationView>, Status> {
+    let collection = get_collection::<Invitation>(db_pool, "invitations").await;
+
+    let invitation = collection
+        .find_one(doc! { "token": token }, None)
+        .await
+        .unwrap();
+
+    match invitation {
+        Some(invitation) => Ok(Response::Success(Json(invitation.into()))),
+        None => Err(Status::NotFound),
+    }
+}
+
+/// Marks a pending invitation as answered, failing if it isn't pending or already expired.
+/// The invitee of an accepted one is added to the team in the same transaction.
+async fn answer(
+    db_pool: &State<Pool>,
+    token: String,
+    status: InvitationStatus,
+) -> Result<Invitation, Status> {
+    let collection = get_collection::<Invitation>(db_pool, "invitations").await;
+
+    let invitation = match collection
+        .find_one(doc! { "token": &token }, None)
+        .await
+        .unwrap()
+    {
+        Some(invitation) => invitation,
+        None => return Err(Status::NotFound),
+    };
+
+    if invitation.status != InvitationStatus::PENDING {
+        return Err(Status::Conflict);
+    }
+
+    if invitation.expires_at < Utc::now() {
+        return Err(Status::Gone);
+    }
+
+    // the invitee has to be signed up before accepting
+    let user = if status == InvitationStatus::ACCEPTED {
+        let user_collection = get_collection::<User>(db_pool, "users").await;
+        match user_collection
+            .find_one(
+                doc! { "email": &invitation.email, "deleted_at": null },
+                None,
+            )
+            .await
+            .unwrap()
+        {
+            Some(user) => Some(user),
+            None => return Err(Status::NotFound),
+        }
+    } else {
+        None
+    };
+
+    // the invitation stays pending if the user can't be added to the team
+    let (db, mut session) = start_transaction(db_pool).await?;
+    let result = db
+        .collection::<Invitation>("invitations")
+        .find_one_and_update_with_session(
+            doc! { "_id": invitation.id, "status": "PENDING" },
+            doc! { "$set": { "status": bson::to_bson(&status).unwrap() } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+            &mut session,
+        )
+        .await;
+
+    // someone else answered the invitation in the meantime
+    let invitation = match result {
+        Ok(Some(invitation)) => invitation,
+        Ok(None) => return Err(Status::Conflict),
+        Err(error) => {
+            eprintln!("[UPDATE][INVITATION] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    if let Some(user) = user {
+        let users_id = vec![user.get_id()];
+        let added = add_users(&db, &mut session, invitation.team_id, IfMatch(None), users_id);
+        if added.await?.is_none() {
+            return Err(Status::NotFound);
+        }
+    }
+    commit_transaction(&mut session).await?;
+
+    Ok(invitation)
+}
+
+#[rocket::post("/<token>/accept")]
+pub async fn accept(
+    db_pool: &State<Pool>,
+    token: String,
+) -> Result<Response<InvitationView>, Status> {
+    let invitation = answer(db_pool, token, InvitationStatus::ACCEPTED).await?;
+
+    Ok(Response::Success(Json(invitation.into())))
+}
+
+#[rocket::post("/<token>/decline")]
+pub async fn decline(
+    db_pool: &State<Pool>,
+    token: String,
+) -> Result<Response<InvitationView>, Status> {
+    let invitation = answer(db_pool, token, InvitationStatus::DECLINED).await?;
+
+    Ok(Response::Success(Json(invitation.into())))
+}
+
+#[rocket::delete("/<invitation_id>")]
+pub async fn revoke(
+    db_pool: &State<Pool>,
+    invitation_id: String,
+) -> Result<Response<InvitationView>, Status> {
+    let collection = get_collection::<Invitation>(db_pool, "invitations").await;
+
+    let invitation_id = match ObjectId::parse_str(invitation_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let result = collection
+        .find_one_and_update(
+            doc! { "_id": invitation_id, "status": "PENDING" },
+            doc! { "$set": { "status": "REVOKED" } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .unwrap();
+
+    match result {
+        Some(invitation) => Ok(Response::Success(Json(invitation.into()))),
+        None => Err(Status::NotFound),
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::Pool;
+use crate::jobs::{self, JobContext, JobState, JobStatus};
+use crate::mailer::SharedMailer;
+use crate::notifier::SharedNotifier;
+use crate::utils::{admin::Admin, responders::Response};
+use chrono::serde::ts_milliseconds_option;
+use chrono::{DateTime, Utc};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::Serialize;
+
+/// Background job with the state of its runs
+#[derive(Serialize, Clone, Debug)]
+pub struct JobView {
+    name: String,
+    description: String,
+    /// Cron expression in UTC
+    schedule: String,
+    #[serde(with = "ts_milliseconds_option")]
+    next_run_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_milliseconds_option")]
+    last_run_at: Option<DateTime<Utc>>,
+    last_status: Option<JobStatus>,
+    last_message: Option<String>,
+    last_duration_ms: Option<i64>,
+    /// Whether an instance is running the job right now
+    running: bool,
+}
+
+impl JobView {
+    fn new(job: &dyn jobs::Job, state: Option<JobState>) -> Self {
+        let running = state.as_ref().is_some_and(JobState::is_running);
+        let state = state.unwrap_or_default();
+
+        Self {
+            name: job.name().to_owned(),
+            description: job.description().to_owned(),
+            schedule: job.schedule().to_owned(),
+            next_run_at: state.next_run_at,
+            last_run_at: state.last_run_at,
+            last_status: state.last_status,
+            last_message: state.last_message,
+            last_duration_ms: state.last_duration_ms,
+            running,
+        }
+    }
+}
+
+#[rocket::get("/")]
+pub async fn all(_admin: Admin, db_pool: &State<Pool>) -> Result<Response<Vec<JobView>>, Status> {
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    let mut states = match jobs::states(&db).await {
+        Ok(states) => states,
+        Err(error) => {
+            eprintln!("[JOBS] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let views = jobs::all()
+        .iter()
+        .map(|job| {
+            let position = states.iter().position(|state| state.name == job.name());
+            JobView::new(job.as_ref(), position.map(|position| states.remove(position)))
+        })
+        .collect();
+
+    Ok(Response::Success(Json(views)))
+}
+
+/// Runs a job right away and waits for it to finish. A job already running
+/// (here or in another instance) returns a 409.
+#[rocket::post("/<name>/run")]
+pub async fn run(
+    _admin: Admin,
+    db_pool: &State<Pool>,
+    mailer: &State<SharedMailer>,
+    notifier: &State<SharedNotifier>,
+    name: String,
+) -> Result<Response<JobView>, Status> {
+    let job = match jobs::find(&name) {
+        Some(job) => job,
+        None => return Err(Status::NotFound),
+    };
+
+    let ctx = JobContext {
+        client: db_pool.get().await.unwrap().clone(),
+        mailer: mailer.inner().clone(),
+        notifier: notifier.inner().clone(),
+    };
+
+    match jobs::run_now(&ctx, job.as_ref()).await {
+        Ok(Some(state)) => Ok(Response::Success(Json(JobView::new(job.as_ref(), Some(state))))),
+        Ok(None) => Err(Status::Conflict),
+        Err(error) => {
+            eprintln!("[JOBS][{}] ~ {}", name, error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
@@ -1,33 +1,325 @@
-use crate::utils::db::get_collection;
-use crate::{config::Pool, utils::responders::Response};
-use bson::doc;
-use chrono::serde::ts_milliseconds;
-use chrono::{DateTime, Utc};
+use super::{
+    absence::{absences_on, AbsenceKind},
+    attendance::{meeting_attendance, AttendanceView},
+    meeting_config::MeetingConfig,
+    team::{calendars_by_config, Team},
+    user::User,
+    user_time::{SegmentMarker, UserTime},
+    webhook::WebhookEvent,
+};
+use crate::utils::concurrency::{ETag, IfMatch};
+use crate::utils::db::{commit_transaction, get_collection, parse_id, start_transaction};
+use crate::utils::integrity::{
+    is_active, is_trashed, restore_many, restore_one, soft_delete_many, soft_delete_one,
+};
+use crate::utils::pagination::{paginate, Page, PageOptions};
+use crate::utils::patch::{check_reference, nullable, patch_one};
+use crate::utils::validation::{object_id, validate, validate_period};
+use crate::utils::work_calendar::WorkCalendar;
+use crate::webhooks;
+use crate::{
+    config::Pool,
+    utils::responders::{ApiError, Response},
+};
+use bson::{doc, Bson, Document};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::TryStreamExt;
 use mongodb::bson::oid::ObjectId;
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::Database;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
 use rocket::{self, http::Status, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// Whether a meeting already took place
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum MeetingStatus {
+    /// Created from the schedule of its config, the dates are the planned ones
+    SCHEDULED,
+    /// Took place, the dates are the real ones
+    #[default]
+    HELD,
+}
+
+impl MeetingStatus {
+    /// Filter of the meetings with the status. Meetings created before the
+    /// schedules have no status and are held.
+    pub(crate) fn filter(&self) -> Document {
+        match self {
+            MeetingStatus::SCHEDULED => doc! { "status": "SCHEDULED" },
+            MeetingStatus::HELD => doc! { "status": { "$ne": "SCHEDULED" } },
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Meeting {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    id: Option<ObjectId>,
-    /// Real duration of the meeting in seconds (max 65535)
-    duration: u16,
+    pub(crate) id: Option<ObjectId>,
     /// Id of the Meeting Configuration associated
-    config_id: Option<ObjectId>,
+    pub(crate) config_id: Option<ObjectId>,
     /// Date and time when the meeting started
     #[serde(with = "ts_milliseconds")]
+    pub(crate) date_utc: DateTime<Utc>,
+    /// Date and time when the meeting ended
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) end_utc: DateTime<Utc>,
+    /// SCHEDULED | HELD
+    #[serde(default)]
+    pub(crate) status: MeetingStatus,
+    /// Occurrence of the schedule of the config the meeting was created for
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) occurrence_utc: Option<DateTime<Utc>>,
+    /// Date and time when the meeting was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the meeting
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+impl Meeting {
+    /// Real duration of the meeting in milliseconds
+    pub(crate) fn duration_ms(&self) -> i64 {
+        (self.end_utc - self.date_utc).num_milliseconds()
+    }
+}
+
+/// Meeting as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct MeetingView {
+    id: String,
+    config_id: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    date_utc: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end_utc: DateTime<Utc>,
+    /// Real duration of the meeting in milliseconds (planned one if scheduled)
+    duration_ms: i64,
+    /// Timezone of the team of the meeting, the local dates are missing without team
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    /// `date_utc` in the local time of the team (RFC 3339, ex: `2023-05-02T09:30:00+02:00`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_local: Option<String>,
+    /// `end_utc` in the local time of the team
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_local: Option<String>,
+    status: MeetingStatus,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    occurrence_utc: Option<DateTime<Utc>>,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<Meeting> for MeetingView {
+    fn from(meeting: Meeting) -> Self {
+        Self {
+            id: meeting.id.unwrap().to_hex(),
+            duration_ms: meeting.duration_ms(),
+            timezone: None,
+            date_local: None,
+            end_local: None,
+            config_id: meeting.config_id.map(|id| id.to_hex()),
+            date_utc: meeting.date_utc,
+            end_utc: meeting.end_utc,
+            status: meeting.status,
+            occurrence_utc: meeting.occurrence_utc,
+            deleted_at: meeting.deleted_at,
+            version: meeting.version,
+        }
+    }
+}
+
+impl MeetingView {
+    /// Meeting with its dates in the local time of its team
+    fn localized(meeting: Meeting, calendars: &HashMap<ObjectId, WorkCalendar>) -> Self {
+        let calendar = meeting.config_id.and_then(|id| calendars.get(&id));
+        let mut view = MeetingView::from(meeting);
+
+        if let Some(calendar) = calendar {
+            view.timezone = Some(calendar.timezone().to_owned());
+            view.date_local = Some(calendar.local(view.date_utc).to_rfc3339());
+            view.end_local = Some(calendar.local(view.end_utc).to_rfc3339());
+        }
+
+        view
+    }
+}
+
+/// Work calendars of the teams of the meetings, indexed by config id. Without
+/// them the dates are only returned in UTC.
+async fn calendars(
+    db_pool: &State<Pool>,
+    meetings: &[Meeting],
+) -> HashMap<ObjectId, WorkCalendar> {
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+    let configs_id = meetings.iter().filter_map(|meeting| meeting.config_id).collect();
+
+    match calendars_by_config(&db, configs_id).await {
+        Ok(calendars) => calendars,
+        Err(error) => {
+            eprintln!("[MEETING][TEAM] ~ {}", error);
+            HashMap::new()
+        }
+    }
+}
+
+/// Meeting as returned by the API, with its dates in the local time of its team
+async fn localized(db_pool: &State<Pool>, meeting: Meeting) -> MeetingView {
+    let calendars = calendars(db_pool, std::slice::from_ref(&meeting)).await;
+    MeetingView::localized(meeting, &calendars)
+}
+
+/// Tells the webhooks of the team of the meeting that it started or finished,
+/// only once per meeting
+pub(crate) async fn meeting_event(db: &Database, meeting: &Meeting, event: WebhookEvent) {
+    let calendars = match calendars_by_config(db, meeting.config_id.into_iter().collect()).await {
+        Ok(calendars) => calendars,
+        Err(error) => {
+            eprintln!("[MEETING][TEAM] ~ {}", error);
+            HashMap::new()
+        }
+    };
+    let view = MeetingView::localized(meeting.clone(), &calendars);
+    let dedup_key = format!("{:?}:{}", event, meeting.id.unwrap().to_hex());
+
+    webhooks::emit_for_meeting(db, meeting, event, Some(dedup_key), &view).await;
+}
+
+/// Body of the creation and the update of a meeting
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct MeetingRequestBody {
+    #[validate(custom = "object_id")]
+    config_id: Option<String>,
+    /// Date and time when the meeting started (ms since epoch)
+    #[serde(with = "ts_milliseconds")]
     date_utc: DateTime<Utc>,
+    /// Date and time when the meeting ended (ms since epoch), after `date_utc`
+    #[serde(with = "ts_milliseconds")]
+    end_utc: DateTime<Utc>,
+}
+
+impl TryFrom<MeetingRequestBody> for Meeting {
+    type Error = Status;
+
+    fn try_from(body: MeetingRequestBody) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: None,
+            config_id: body.config_id.as_deref().map(parse_id).transpose()?,
+            date_utc: body.date_utc,
+            end_utc: body.end_utc,
+            status: MeetingStatus::HELD,
+            occurrence_utc: None,
+            deleted_at: None,
+            version: 0,
+        })
+    }
+}
+
+/// Time spoken by a user in a meeting adding up all of their turns, with the
+/// name of the user resolved
+#[derive(Serialize, Clone, Debug)]
+pub struct MeetingUserTime {
+    /// User Id
+    user_id: String,
+    /// Name of the user (empty if the user doesn't exist anymore)
+    user_name: String,
+    /// Amount of times the user spoke
+    turns: u32,
+    /// Amount of turns where the user cut off whoever was speaking
+    interruptions: u32,
+    /// Amount of turns where the user spoke at the same time as someone else
+    crosstalks: u32,
+    /// Total time in milliseconds
+    time_ms: i64,
+}
+
+/// Speaking segment of a meeting timeline, with the name of the user resolved
+#[derive(Serialize, Clone, Debug)]
+pub struct TimelineSegment {
+    /// Id of the UserTime document
+    user_time_id: String,
+    user_id: String,
+    /// Name of the user (empty if the user doesn't exist anymore)
+    user_name: String,
+    #[serde(with = "ts_milliseconds")]
+    start_utc: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end_utc: DateTime<Utc>,
+    /// Milliseconds between the start of the meeting and the start of the segment
+    offset_ms: i64,
+    duration_ms: i64,
+    marker: Option<SegmentMarker>,
+}
+
+/// Every speaking segment of a meeting in chronological order, to replay it
+#[derive(Serialize, Clone, Debug)]
+pub struct MeetingTimeline {
+    meeting: MeetingView,
+    segments: Vec<TimelineSegment>,
+}
+
+/// Member of the team expected in a meeting
+#[derive(Serialize, Clone, Debug)]
+pub struct Participant {
+    pub(crate) user_id: String,
+    pub(crate) name: String,
+}
+
+/// Member of the team out of office on the day of a meeting
+#[derive(Serialize, Clone, Debug)]
+pub struct AbsentMember {
+    user_id: String,
+    pub(crate) name: String,
+    kind: AbsenceKind,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+}
+
+/// Meeting as run by the scrum master: the members expected to attend, in the
+/// order they speak, and the ones who are out. Meetings without team have neither.
+#[derive(Serialize, Clone, Debug)]
+pub struct MeetingSession {
+    meeting: MeetingView,
+    /// Members of the team that aren't absent on the local day of the meeting,
+    /// shuffled but always in the same order for the same meeting
+    speaking_order: Vec<Participant>,
+    absent: Vec<AbsentMember>,
+    /// Attendance recorded so far
+    attendance: Vec<AttendanceView>,
 }
 
 #[rocket::post("/", format = "json", data = "<meeting>")]
 pub async fn create(
     db_pool: &State<Pool>,
-    meeting: Json<Meeting>,
-) -> Result<Response<Meeting>, Status> {
+    meeting: Json<MeetingRequestBody>,
+) -> Result<Response<MeetingView>, ApiError> {
+    validate(&meeting.0)?;
+    validate_period(meeting.0.date_utc, meeting.0.end_utc, "end_utc")?;
+
     let collection = get_collection::<Meeting>(db_pool, "meetings").await;
 
-    let mut new_meeting = meeting.0.clone();
+    let mut new_meeting = Meeting::try_from(meeting.0)?;
+
+    // the config has to exist and not be in the trash
+    if let Some(config_id) = new_meeting.config_id {
+        let config_collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+        check_reference(&config_collection, config_id).await?;
+    }
 
     let result = collection.insert_one(&new_meeting, None).await;
 
@@ -35,17 +327,22 @@ pub async fn create(
         Ok(result) => {
             let id = result.inserted_id;
             new_meeting.id = Some(id.as_object_id().unwrap());
-            Ok(Response::Created(Json(new_meeting)))
+            let db = db_pool.get().await.unwrap().default_database().unwrap();
+            meeting_event(&db, &new_meeting, WebhookEvent::MEETING_FINISHED).await;
+            Ok(Response::Created(Json(localized(db_pool, new_meeting).await)))
         }
         Err(error) => {
             eprintln!("[INSERT][MEETING] ~ {}", error);
-            Err(Status::InternalServerError)
+            Err(Status::InternalServerError.into())
         }
     }
 }
 
 #[rocket::get("/<meeting_id>")]
-pub async fn get(db_pool: &State<Pool>, meeting_id: String) -> Result<Response<Meeting>, Status> {
+pub async fn get(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<Response<MeetingView>, Status> {
     let collection = get_collection::<Meeting>(db_pool, "meetings").await;
 
     let meeting_id = match ObjectId::parse_str(meeting_id) {
@@ -56,7 +353,8 @@ pub async fn get(db_pool: &State<Pool>, meeting_id: String) -> Result<Response<M
     let result = collection
         .find_one(
             doc! {
-                "_id": meeting_id
+                "_id": meeting_id,
+                "deleted_at": null
             },
             None,
         )
@@ -64,7 +362,602 @@ pub async fn get(db_pool: &State<Pool>, meeting_id: String) -> Result<Response<M
         .unwrap();
 
     match result {
-        Some(meeting) => Ok(Response::Success(Json(meeting))),
+        Some(meeting) => {
+            let etag = ETag(meeting.version);
+            Ok(Response::Tagged(Json(localized(db_pool, meeting).await), etag))
+        }
         None => Err(Status::NotFound),
     }
 }
+
+#[rocket::put("/<meeting_id>", format = "json", data = "<meeting>")]
+pub async fn update(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    if_match: IfMatch,
+    meeting: Json<MeetingRequestBody>,
+) -> Result<Response<MeetingView>, ApiError> {
+    validate(&meeting.0)?;
+
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let config_collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+
+    validate_period(meeting.0.date_utc, meeting.0.end_utc, "end_utc")?;
+    let new_meeting = Meeting::try_from(meeting.0)?;
+
+    let meeting_id = match ObjectId::parse_str(meeting_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    // check if the config exists when one is provided
+    if let Some(config_id) = new_meeting.config_id {
+        let config_exists = config_collection
+            .find_one(doc! { "_id": config_id, "deleted_at": null }, None)
+            .await
+            .unwrap()
+            .is_some();
+
+        if !config_exists {
+            return Err(Status::NotFound.into());
+        }
+    }
+
+    let mut filter = doc! { "_id": meeting_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": {
+                    "config_id": new_meeting.config_id,
+                    "date_utc": new_meeting.date_utc.timestamp_millis(),
+                    "end_utc": new_meeting.end_utc.timestamp_millis(),
+                    // the real dates of a scheduled meeting are recorded once it's held
+                    "status": "HELD"
+                },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    match result {
+        Ok(result) => match result {
+            Some(meeting) => {
+                let db = db_pool.get().await.unwrap().default_database().unwrap();
+                meeting_event(&db, &meeting, WebhookEvent::MEETING_FINISHED).await;
+                let etag = ETag(meeting.version);
+                Ok(Response::Tagged(Json(localized(db_pool, meeting).await), etag))
+            }
+            None => {
+                let exists = collection
+                    .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+                    .await
+                    .unwrap()
+                    .is_some();
+                Err(if_match.failed_write_status(exists).into())
+            }
+        },
+        Err(error) => {
+            eprintln!("[UPDATE][MEETING] ~ {}", error);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+/// Fields of a meeting that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MeetingPatch {
+    /// `null` detaches the meeting from its config
+    #[validate(custom = "object_id")]
+    #[serde(default, deserialize_with = "nullable")]
+    config_id: Option<Option<String>>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    date_utc: Option<DateTime<Utc>>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    end_utc: Option<DateTime<Utc>>,
+}
+
+/// Partial update of a meeting, only the fields sent are changed
+#[rocket::patch("/<meeting_id>", format = "json", data = "<meeting>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    if_match: IfMatch,
+    meeting: Json<MeetingPatch>,
+) -> Result<Response<MeetingView>, ApiError> {
+    validate(&meeting.0)?;
+
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let meeting_id = parse_id(&meeting_id)?;
+    let fields = meeting.0;
+
+    // the new start or end has to be checked against the one that is kept
+    if fields.date_utc.is_some() || fields.end_utc.is_some() {
+        let current = match collection
+            .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+            .await
+            .unwrap()
+        {
+            Some(meeting) => meeting,
+            None => return Err(Status::NotFound.into()),
+        };
+
+        validate_period(
+            fields.date_utc.unwrap_or(current.date_utc),
+            fields.end_utc.unwrap_or(current.end_utc),
+            "end_utc",
+        )?;
+    }
+
+    let mut changes = Document::new();
+    match fields.config_id {
+        Some(Some(config_id)) => {
+            let config_id = parse_id(&config_id)?;
+            let config_collection =
+                get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+            check_reference(&config_collection, config_id).await?;
+            changes.insert("config_id", config_id);
+        }
+        Some(None) => {
+            changes.insert("config_id", Bson::Null);
+        }
+        None => {}
+    }
+    if let Some(date_utc) = fields.date_utc {
+        changes.insert("date_utc", date_utc.timestamp_millis());
+    }
+    if let Some(end_utc) = fields.end_utc {
+        changes.insert("end_utc", end_utc.timestamp_millis());
+    }
+    // the real dates of a scheduled meeting are recorded once it's held
+    let held = fields.date_utc.is_some() || fields.end_utc.is_some();
+    if held {
+        changes.insert("status", "HELD");
+    }
+
+    let meeting = patch_one(&collection, meeting_id, if_match, changes).await?;
+    if held {
+        let db = db_pool.get().await.unwrap().default_database().unwrap();
+        meeting_event(&db, &meeting, WebhookEvent::MEETING_FINISHED).await;
+    }
+
+    let etag = ETag(meeting.version);
+    Ok(Response::Tagged(Json(localized(db_pool, meeting).await), etag))
+}
+
+#[rocket::delete("/<meeting_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    if_match: IfMatch,
+) -> Result<Response<MeetingView>, ApiError> {
+    let meeting_id = match ObjectId::parse_str(meeting_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+    let deleted_at = Utc::now().timestamp_millis();
+
+    let result = soft_delete_one::<Meeting>(
+        &db,
+        &mut session,
+        "meetings",
+        meeting_id,
+        if_match.0,
+        deleted_at,
+    )
+    .await?;
+
+    let meeting = match result {
+        Some(meeting) => meeting,
+        None => {
+            let exists = is_active(&db, &mut session, "meetings", meeting_id).await?;
+            return Err(if_match.failed_write_status(exists).into());
+        }
+    };
+
+    // the times spoken in the meeting, its attendance and its action items go
+    // away with it
+    soft_delete_many(
+        &db,
+        &mut session,
+        "user_times",
+        doc! { "meeting_id": meeting_id },
+        deleted_at,
+    )
+    .await?;
+    soft_delete_many(
+        &db,
+        &mut session,
+        "attendances",
+        doc! { "meeting_id": meeting_id },
+        deleted_at,
+    )
+    .await?;
+    soft_delete_many(
+        &db,
+        &mut session,
+        "action_items",
+        doc! { "meeting_id": meeting_id },
+        deleted_at,
+    )
+    .await?;
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(meeting.into())))
+}
+
+#[rocket::put("/<meeting_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+    if_match: IfMatch,
+) -> Result<Response<MeetingView>, Status> {
+    let meeting_id = match ObjectId::parse_str(meeting_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored =
+        restore_one::<Meeting>(&db, &mut session, "meetings", meeting_id, if_match.0).await?;
+    let mut meeting = match restored {
+        Some(meeting) => meeting,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "meetings", meeting_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // the config has to be restored first
+    if let Some(config_id) = meeting.config_id {
+        if !is_active(&db, &mut session, "meeting_configs", config_id).await? {
+            return Err(Status::Conflict);
+        }
+    }
+
+    // times, attendance and action items deleted together with the meeting
+    let deleted_at = meeting.deleted_at.take().unwrap().timestamp_millis();
+    meeting.version += 1;
+    restore_many(
+        &db,
+        &mut session,
+        "user_times",
+        doc! { "meeting_id": meeting_id },
+        deleted_at,
+    )
+    .await?;
+    restore_many(
+        &db,
+        &mut session,
+        "attendances",
+        doc! { "meeting_id": meeting_id },
+        deleted_at,
+    )
+    .await?;
+    restore_many(
+        &db,
+        &mut session,
+        "action_items",
+        doc! { "meeting_id": meeting_id },
+        deleted_at,
+    )
+    .await?;
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(meeting.into())))
+}
+
+/// Active meeting with its speaking segments sorted by start, and the names of
+/// the users that spoke
+async fn segments(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<(Meeting, Vec<UserTime>, HashMap<ObjectId, String>), Status> {
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let user_time_collection = get_collection::<UserTime>(db_pool, "user_times").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let meeting_id = match ObjectId::parse_str(meeting_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let meeting = match collection
+        .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(meeting) => meeting,
+        None => return Err(Status::NotFound),
+    };
+
+    let opts = FindOptions::builder()
+        .sort(doc! { "start_utc": 1, "_id": 1 })
+        .build();
+    let user_times = user_time_collection
+        .find(doc! { "meeting_id": meeting_id, "deleted_at": null }, opts)
+        .await
+        .unwrap()
+        .try_collect::<Vec<UserTime>>()
+        .await
+        .unwrap();
+
+    // get the users of every time in a single query
+    let users_id: Vec<ObjectId> = user_times.iter().filter_map(|t| t.user_id).collect();
+    let users: HashMap<ObjectId, String> = user_collection
+        .find(doc! { "_id": { "$in": users_id }, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .try_collect::<Vec<User>>()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| (user.id.unwrap(), user.name))
+        .collect();
+
+    Ok((meeting, user_times, users))
+}
+
+#[rocket::get("/<meeting_id>/user_times")]
+pub async fn get_user_times(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<Response<Vec<MeetingUserTime>>, Status> {
+    let (_, user_times, users) = segments(db_pool, meeting_id).await?;
+
+    // add up the turns of every user, in the order they first spoke
+    let mut result: Vec<MeetingUserTime> = vec![];
+    let mut positions: HashMap<ObjectId, usize> = HashMap::new();
+    for user_time in user_times {
+        let user_id = user_time.user_id.unwrap();
+        let position = *positions.entry(user_id).or_insert_with(|| {
+            result.push(MeetingUserTime {
+                user_id: user_id.to_hex(),
+                user_name: users.get(&user_id).cloned().unwrap_or_default(),
+                turns: 0,
+                interruptions: 0,
+                crosstalks: 0,
+                time_ms: 0,
+            });
+            result.len() - 1
+        });
+
+        let total = &mut result[position];
+        total.turns += 1;
+        total.time_ms += user_time.duration_ms();
+        match user_time.marker {
+            Some(SegmentMarker::INTERRUPTION) => total.interruptions += 1,
+            Some(SegmentMarker::CROSSTALK) => total.crosstalks += 1,
+            None => {}
+        }
+    }
+
+    Ok(Response::Success(Json(result)))
+}
+
+#[rocket::get("/<meeting_id>/timeline")]
+pub async fn timeline(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<Response<MeetingTimeline>, Status> {
+    let (meeting, user_times, users) = segments(db_pool, meeting_id).await?;
+
+    let segments = user_times
+        .into_iter()
+        .map(|user_time| {
+            let user_id = user_time.user_id.unwrap();
+            TimelineSegment {
+                user_time_id: user_time.id.unwrap().to_hex(),
+                user_id: user_id.to_hex(),
+                user_name: users.get(&user_id).cloned().unwrap_or_default(),
+                offset_ms: (user_time.start_utc - meeting.date_utc).num_milliseconds(),
+                duration_ms: user_time.duration_ms(),
+                start_utc: user_time.start_utc,
+                end_utc: user_time.end_utc,
+                marker: user_time.marker,
+            }
+        })
+        .collect();
+
+    Ok(Response::Success(Json(MeetingTimeline {
+        meeting: localized(db_pool, meeting).await,
+        segments,
+    })))
+}
+
+/// Members of the team expected in a meeting, in the order they speak, and the
+/// ones out of office on its local day
+pub(crate) async fn session_members(
+    db: &Database,
+    meeting: &Meeting,
+    team: &Team,
+) -> Result<(Vec<Participant>, Vec<AbsentMember>), mongodb::error::Error> {
+    let members_id = team.users.clone().unwrap_or_default();
+    let names: HashMap<ObjectId, String> = db
+        .collection::<User>("users")
+        .find(doc! { "_id": { "$in": &members_id }, "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<User>>()
+        .await?
+        .into_iter()
+        .map(|user| (user.id.unwrap(), user.name))
+        .collect();
+
+    let day = team.calendar().local(meeting.date_utc).date_naive();
+    let mut absent = vec![];
+    for absence in absences_on(db, &members_id, day).await? {
+        if let Some(name) = names.get(&absence.user_id) {
+            absent.push(AbsentMember {
+                user_id: absence.user_id.to_hex(),
+                name: name.clone(),
+                kind: absence.kind,
+                start_date: absence.start_date,
+                end_date: absence.end_date,
+            });
+        }
+    }
+
+    let mut speaking_order = vec![];
+    for user_id in members_id {
+        let is_absent = absent.iter().any(|member| member.user_id == user_id.to_hex());
+        if let (Some(name), false) = (names.get(&user_id), is_absent) {
+            speaking_order.push(Participant {
+                user_id: user_id.to_hex(),
+                name: name.clone(),
+            });
+        }
+    }
+
+    // seeded with the meeting id so reloading the session keeps the order, ChaCha8
+    // gives the same stream on every platform and release unlike StdRng
+    let mut seed = [0; 32];
+    seed[..12].copy_from_slice(&meeting.id.unwrap().bytes());
+    speaking_order.shuffle(&mut ChaCha8Rng::from_seed(seed));
+
+    Ok((speaking_order, absent))
+}
+
+#[rocket::get("/<meeting_id>/session")]
+pub async fn session(
+    db_pool: &State<Pool>,
+    meeting_id: String,
+) -> Result<Response<MeetingSession>, Status> {
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let meeting_id = parse_id(&meeting_id)?;
+
+    let meeting = match collection
+        .find_one(doc! { "_id": meeting_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(meeting) => meeting,
+        None => return Err(Status::NotFound),
+    };
+
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+    let config = match meeting.config_id {
+        Some(config_id) => db
+            .collection::<MeetingConfig>("meeting_configs")
+            .find_one(doc! { "_id": config_id }, None)
+            .await
+            .unwrap(),
+        None => None,
+    };
+    let team = match config.and_then(|config| config.team_id) {
+        Some(team_id) => db
+            .collection::<Team>("teams")
+            .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+            .await
+            .unwrap(),
+        None => None,
+    };
+
+    let (speaking_order, absent) = match team {
+        Some(team) => match session_members(&db, &meeting, &team).await {
+            Ok(members) => members,
+            Err(error) => {
+                eprintln!("[MEETING][ABSENCE] ~ {}", error);
+                return Err(Status::InternalServerError);
+            }
+        },
+        None => (vec![], vec![]),
+    };
+
+    let attendance = match meeting_attendance(db_pool, meeting_id).await {
+        Ok(attendance) => attendance,
+        Err(error) => {
+            eprintln!("[MEETING][ATTENDANCE] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    Ok(Response::Success(Json(MeetingSession {
+        meeting: localized(db_pool, meeting).await,
+        speaking_order,
+        absent,
+        attendance,
+    })))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/all?<config_id>&<team_id>&<status>&<from>&<to>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    config_id: Option<String>,
+    team_id: Option<String>,
+    status: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<MeetingView>>, Status> {
+    let collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let opts = PageOptions::new(page, limit, sort, &["-date_utc", "end_utc"])?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(config_id) = config_id {
+        match ObjectId::parse_str(config_id) {
+            Ok(id) => filter.insert("config_id", id),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+    }
+
+    // meetings of a team are the ones created from any of its configs
+    if let Some(team_id) = team_id {
+        let team_id = match ObjectId::parse_str(team_id) {
+            Ok(id) => id,
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+
+        let config_collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+        let configs_id: Vec<ObjectId> = config_collection
+            .find(doc! { "team_id": team_id, "deleted_at": null }, None)
+            .await
+            .unwrap()
+            .try_collect::<Vec<MeetingConfig>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|config| config.id)
+            .collect();
+
+        filter = doc! { "$and": [filter, { "config_id": { "$in": configs_id } }] };
+    }
+
+    let mut date_filter = doc! {};
+    if let Some(from) = from {
+        date_filter.insert("$gte", from);
+    }
+    if let Some(to) = to {
+        date_filter.insert("$lte", to);
+    }
+    if !date_filter.is_empty() {
+        filter.insert("date_utc", date_filter);
+    }
+
+    match status.as_deref() {
+        Some("SCHEDULED") => filter.extend(MeetingStatus::SCHEDULED.filter()),
+        Some("HELD") => filter.extend(MeetingStatus::HELD.filter()),
+        Some(_) => return Err(Status::UnprocessableEntity),
+        None => {}
+    }
+
+    let meetings = paginate(&collection, filter, &opts).await?;
+    let calendars = calendars(db_pool, meetings.items()).await;
+
+    Ok(Response::Success(Json(
+        meetings.map(|meeting| MeetingView::localized(meeting, &calendars)),
+    )))
+}
@@ -1,12 +1,43 @@
-use super::team::Team;
-use crate::utils::db::get_collection;
-use crate::{config::Pool, utils::responders::Response};
-use bson::{doc, oid::ObjectId};
-use futures::TryStreamExt;
+use super::meeting::{Meeting, MeetingStatus};
+use super::team::{calendars_by_config, Team};
+use super::webhook::WebhookEvent;
+use crate::utils::concurrency::{ETag, IfMatch};
+use crate::utils::db::{
+    commit_transaction, get_collection, is_duplicate_key, is_validation_failure, parse_id,
+    start_transaction,
+};
+use crate::utils::integrity::{
+    find_blockers, find_ids, is_active, is_trashed, restore_many, restore_one,
+    soft_delete_many, soft_delete_one,
+};
+use crate::utils::pagination::{paginate, Page, PageOptions};
+use crate::utils::patch::{check_reference, nullable, patch_one};
+use crate::utils::recurrence::Schedule;
+use crate::utils::validation::{not_blank, object_id, validate};
+use crate::webhooks;
+use crate::{
+    config::Pool,
+    utils::responders::{ApiError, Response},
+};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use mongodb::bson::to_bson;
+use futures::TryStreamExt;
 use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::Database;
 use rocket::{http::Status, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// Days ahead the meetings of the scheduled configs are created
+const SCHEDULE_HORIZON_DAYS: i64 = 14;
+
+/// Most occurrences returned at once
+const MAX_OCCURRENCES: usize = 100;
+
+/// Days ahead the occurrences are looked for, as the days off may skip all of them
+const OCCURRENCES_AHEAD_DAYS: i64 = 366;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum MeetingType {
@@ -15,8 +46,7 @@ pub enum MeetingType {
 }
 
 impl MeetingType {
-    #[allow(dead_code)]
-    fn value(&self) -> &str {
+    pub(crate) fn value(&self) -> &str {
         match self {
             MeetingType::DAILY => "DAILY",
             MeetingType::RETRO => "RETRO",
@@ -28,55 +58,425 @@ impl MeetingType {
 pub struct MeetingConfig {
     /// Meeting DB Id
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    id: Option<ObjectId>,
-    /// Team Id as ObjectId
-    team_id: Option<ObjectId>,
-    /// Team Id as String
-    #[serde(skip_serializing)]
-    team_id_str: Option<String>,
+    pub(crate) id: Option<ObjectId>,
+    /// Team Id
+    pub(crate) team_id: Option<ObjectId>,
     /// Time in seconds that the meeting should last at maximum
-    desired_duration: i64,
+    pub(crate) desired_duration: i64,
     /// Name of the meeting (ex: Pandora Daily)
-    config_name: String,
+    pub(crate) config_name: String,
     /// Description of the meeting
-    description: String,
+    pub(crate) description: String,
     /// Type of the meeting (RETRO | DAILY)
+    pub(crate) meeting_type: String,
+    /// Recurring schedule of the meetings, none if they are created by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) schedule: Option<Schedule>,
+    /// Date and time when the config was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the config
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+/// Meeting config as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct MeetingConfigView {
+    id: String,
+    team_id: Option<String>,
+    desired_duration: i64,
+    config_name: String,
+    description: String,
     meeting_type: String,
+    schedule: Option<Schedule>,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<MeetingConfig> for MeetingConfigView {
+    fn from(config: MeetingConfig) -> Self {
+        Self {
+            id: config.id.unwrap().to_hex(),
+            team_id: config.team_id.map(|id| id.to_hex()),
+            desired_duration: config.desired_duration,
+            config_name: config.config_name,
+            description: config.description,
+            meeting_type: config.meeting_type,
+            schedule: config.schedule,
+            deleted_at: config.deleted_at,
+            version: config.version,
+        }
+    }
+}
+
+/// What happened to a config, sent with the `CONFIG_CHANGED` event of the webhooks
+#[derive(Serialize, Clone, Copy, Debug)]
+pub enum ConfigChange {
+    CREATED,
+    UPDATED,
+    DELETED,
+    RESTORED,
+}
+
+/// Data of the `CONFIG_CHANGED` event
+#[derive(Serialize, Clone, Debug)]
+struct ConfigChanged {
+    change: ConfigChange,
+    config: MeetingConfigView,
+}
+
+/// Tells the webhooks of the team of the config that it changed
+async fn config_changed(db_pool: &State<Pool>, change: ConfigChange, config: &MeetingConfig) {
+    if let Some(team_id) = config.team_id {
+        let db = db_pool.get().await.unwrap().default_database().unwrap();
+        let data = ConfigChanged {
+            change,
+            config: config.clone().into(),
+        };
+        webhooks::emit(&db, team_id, WebhookEvent::CONFIG_CHANGED, None, &data).await;
+    }
+}
+
+/// Body of the creation and the update of a meeting config
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct MeetingConfigRequestBody {
+    #[validate(custom = "object_id")]
+    team_id: String,
+    /// Time in seconds that the meeting should last at maximum (up to a day)
+    #[validate(range(min = 1, max = 86400))]
+    desired_duration: i64,
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    config_name: String,
+    #[validate(length(max = 1000))]
+    description: String,
+    /// RETRO | DAILY, anything else is rejected when parsing the body
+    meeting_type: MeetingType,
+    /// Recurring schedule, meetings are created from the config on every occurrence
+    #[validate]
+    #[serde(default)]
+    schedule: Option<Schedule>,
+}
+
+impl TryFrom<MeetingConfigRequestBody> for MeetingConfig {
+    type Error = Status;
+
+    fn try_from(body: MeetingConfigRequestBody) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: None,
+            team_id: Some(parse_id(&body.team_id)?),
+            desired_duration: body.desired_duration,
+            config_name: body.config_name,
+            description: body.description,
+            meeting_type: body.meeting_type.value().to_owned(),
+            schedule: body.schedule,
+            deleted_at: None,
+            version: 0,
+        })
+    }
+}
+
+/// Upcoming meeting of a scheduled config
+#[derive(Serialize, Clone, Debug)]
+pub struct Occurrence {
+    #[serde(with = "ts_milliseconds")]
+    start_utc: DateTime<Utc>,
+    /// Start plus the desired duration
+    #[serde(with = "ts_milliseconds")]
+    end_utc: DateTime<Utc>,
+    /// Meeting created for the occurrence, none if it's too far ahead to be created yet
+    meeting_id: Option<String>,
+}
+
+/// Predicate telling if the team of the config works on the local day of a start
+/// date. Configs without team have no days off.
+async fn is_working_day(
+    db: &Database,
+    config: &MeetingConfig,
+) -> Result<impl Fn(&DateTime<Utc>) -> bool, mongodb::error::Error> {
+    let calendar = calendars_by_config(db, vec![config.id.unwrap()])
+        .await?
+        .into_values()
+        .next();
+
+    Ok(move |start_utc: &DateTime<Utc>| match &calendar {
+        Some(calendar) => calendar.is_working_day(calendar.local(*start_utc).date_naive()),
+        None => true,
+    })
+}
+
+/// Starts of the occurrences of the config in the next `SCHEDULE_HORIZON_DAYS`,
+/// skipping the days off of the team. Empty without schedule, `None` if the
+/// schedule can't be expanded.
+async fn upcoming_occurrences(
+    db: &Database,
+    config: &MeetingConfig,
+) -> Result<Option<Vec<DateTime<Utc>>>, mongodb::error::Error> {
+    let schedule = match &config.schedule {
+        Some(schedule) => schedule,
+        None => return Ok(Some(vec![])),
+    };
+
+    let now = Utc::now();
+    let until = now + Duration::days(SCHEDULE_HORIZON_DAYS);
+    let occurrences = match schedule.occurrences(now) {
+        Ok(occurrences) => occurrences,
+        Err(error) => {
+            eprintln!("[SCHEDULE][MEETING_CONFIG] ~ {}", error);
+            return Ok(None);
+        }
+    };
+
+    let is_working_day = is_working_day(db, config).await?;
+    Ok(Some(
+        occurrences
+            .take_while(|start_utc| *start_utc < until)
+            .filter(is_working_day)
+            .collect(),
+    ))
+}
+
+/// Creates the meetings of the scheduled config starting in the next
+/// `SCHEDULE_HORIZON_DAYS`, returning the amount created. Occurrences on days
+/// off of the team or that already have a meeting, even in the trash, are skipped.
+async fn schedule_meetings(
+    db: &Database,
+    config: &MeetingConfig,
+) -> Result<u64, mongodb::error::Error> {
+    let occurrences = match upcoming_occurrences(db, config).await? {
+        Some(occurrences) => occurrences,
+        None => return Ok(0),
+    };
+
+    let collection = db.collection::<Meeting>("meetings");
+    let mut created = 0;
+    for start_utc in occurrences {
+        let meeting = Meeting {
+            id: None,
+            config_id: config.id,
+            date_utc: start_utc,
+            end_utc: start_utc + Duration::seconds(config.desired_duration),
+            status: MeetingStatus::SCHEDULED,
+            occurrence_utc: Some(start_utc),
+            deleted_at: None,
+            version: 0,
+        };
+
+        // the unique index on the occurrence keeps concurrent runs from duplicating it
+        match collection.insert_one(&meeting, None).await {
+            Ok(_) => created += 1,
+            Err(error) if is_duplicate_key(&error) => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(created)
+}
+
+/// Upcoming scheduled meetings of a config that aren't in its schedule anymore,
+/// updating the duration of the others
+async fn unscheduled_meetings(
+    db: &Database,
+    config: &MeetingConfig,
+    occurrences: &[DateTime<Utc>],
+) -> Result<Vec<ObjectId>, mongodb::error::Error> {
+    let collection = db.collection::<Meeting>("meetings");
+    let meetings = collection
+        .find(
+            doc! {
+                "config_id": config.id,
+                "status": "SCHEDULED",
+                "date_utc": { "$gt": Utc::now().timestamp_millis() },
+                "deleted_at": null
+            },
+            None,
+        )
+        .await?
+        .try_collect::<Vec<Meeting>>()
+        .await?;
+
+    let mut unscheduled = vec![];
+    for meeting in meetings {
+        let occurrence_utc = match meeting.occurrence_utc {
+            Some(occurrence_utc) => occurrence_utc,
+            None => continue,
+        };
+        if !occurrences.contains(&occurrence_utc) {
+            unscheduled.push(meeting.id.unwrap());
+            continue;
+        }
+
+        // meetings moved to other dates keep them
+        let end_utc = occurrence_utc + Duration::seconds(config.desired_duration);
+        if meeting.date_utc == occurrence_utc && meeting.end_utc != end_utc {
+            collection
+                .update_one(
+                    doc! { "_id": meeting.id, "version": meeting.version },
+                    doc! {
+                        "$set": { "end_utc": end_utc.timestamp_millis() },
+                        "$inc": { "version": 1 }
+                    },
+                    None,
+                )
+                .await?;
+        }
+    }
+
+    Ok(unscheduled)
+}
+
+/// Brings the upcoming scheduled meetings of a config in line with its schedule
+/// after it changed: the occurrences no longer in the schedule are moved to the
+/// trash with what depends on them, the missing ones are created and the others
+/// are kept, with their ids. Cancelled occurrences stay in the trash.
+async fn reschedule_meetings(db_pool: &State<Pool>, config: &MeetingConfig) {
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    let occurrences = match upcoming_occurrences(&db, config).await {
+        Ok(Some(occurrences)) => occurrences,
+        Ok(None) => return,
+        Err(error) => {
+            eprintln!("[SCHEDULE][MEETING] ~ {}", error);
+            return;
+        }
+    };
+
+    let unscheduled = match unscheduled_meetings(&db, config, &occurrences).await {
+        Ok(unscheduled) => unscheduled,
+        Err(error) => {
+            eprintln!("[SCHEDULE][MEETING] ~ {}", error);
+            return;
+        }
+    };
+
+    if !unscheduled.is_empty() {
+        let result: Result<(), Status> = async {
+            let (db, mut session) = start_transaction(db_pool).await?;
+            let deleted_at = Utc::now().timestamp_millis();
+
+            soft_delete_many(
+                &db,
+                &mut session,
+                "meetings",
+                doc! { "_id": { "$in": &unscheduled } },
+                deleted_at,
+            )
+            .await?;
+            for collection in ["user_times", "attendances", "action_items"] {
+                soft_delete_many(
+                    &db,
+                    &mut session,
+                    collection,
+                    doc! { "meeting_id": { "$in": &unscheduled } },
+                    deleted_at,
+                )
+                .await?;
+            }
+
+            commit_transaction(&mut session).await
+        }
+        .await;
+
+        // the errors are logged already, the new occurrences are created anyway
+        if result.is_err() {
+            eprintln!("[SCHEDULE][MEETING] ~ unscheduled meetings not moved to the trash");
+        }
+    }
+
+    if let Err(error) = schedule_meetings(&db, config).await {
+        eprintln!("[SCHEDULE][MEETING] ~ {}", error);
+    }
+}
+
+/// Reschedules the upcoming meetings of the configs of a team after its
+/// work calendar changed
+pub(crate) async fn reschedule_team(db_pool: &State<Pool>, team_id: ObjectId) {
+    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+
+    let configs = match collection
+        .find(
+            doc! { "team_id": team_id, "schedule": { "$ne": null }, "deleted_at": null },
+            None,
+        )
+        .await
+    {
+        Ok(cursor) => cursor.try_collect::<Vec<MeetingConfig>>().await,
+        Err(error) => Err(error),
+    };
+
+    match configs {
+        Ok(configs) => {
+            for config in configs {
+                reschedule_meetings(db_pool, &config).await;
+            }
+        }
+        Err(error) => eprintln!("[SCHEDULE][MEETING_CONFIG] ~ {}", error),
+    }
+}
+
+/// Creates the upcoming meetings of every scheduled config, returning the amount created
+pub async fn create_scheduled_meetings(db: &Database) -> Result<u64, mongodb::error::Error> {
+    let configs = db
+        .collection::<MeetingConfig>("meeting_configs")
+        .find(doc! { "schedule": { "$ne": null }, "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<MeetingConfig>>()
+        .await?;
+
+    let mut created = 0;
+    for config in configs {
+        created += schedule_meetings(db, &config).await?;
+    }
+
+    Ok(created)
 }
 
 #[rocket::post("/", format = "json", data = "<meeting_config>")]
 pub async fn create(
     db_pool: &State<Pool>,
-    meeting_config: Json<MeetingConfig>,
-) -> Result<Response<MeetingConfig>, Status> {
+    meeting_config: Json<MeetingConfigRequestBody>,
+) -> Result<Response<MeetingConfigView>, ApiError> {
+    validate(&meeting_config.0)?;
+
     let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
     let team_collection = get_collection::<Team>(db_pool, "teams").await;
 
-    let mut new_config = meeting_config.0.clone();
+    let mut new_config = MeetingConfig::try_from(meeting_config.0)?;
 
     // Check if the team with the `team_id` provided exists
     let result = team_collection
         .find_one(
             doc! {
-                "_id": new_config.team_id.unwrap()
+                "_id": new_config.team_id.unwrap(),
+                "deleted_at": null
             },
             None,
         )
         .await
         .unwrap();
 
-    if let Some(_) = result {
+    if result.is_some() {
         let result = collection.insert_one(&new_config, None).await;
 
         match result {
             Ok(result) => {
                 new_config.id = Some(result.inserted_id.as_object_id().unwrap());
-                Ok(Response::Created(Json(new_config)))
+                if new_config.schedule.is_some() {
+                    reschedule_meetings(db_pool, &new_config).await;
+                }
+                config_changed(db_pool, ConfigChange::CREATED, &new_config).await;
+                Ok(Response::Created(Json(new_config.into())))
             }
-            Err(_) => Err(Status::InternalServerError),
+            Err(error) if is_validation_failure(&error) => Err(Status::UnprocessableEntity.into()),
+            Err(_) => Err(Status::InternalServerError.into()),
         }
     } else {
-        Err(Status::NotFound)
+        Err(Status::NotFound.into())
     }
 }
 
@@ -84,7 +484,7 @@ pub async fn create(
 pub async fn get(
     db_pool: &State<Pool>,
     meeting_config_id: String,
-) -> Result<Response<MeetingConfig>, Status> {
+) -> Result<Response<MeetingConfigView>, Status> {
     let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
 
     let meeting_config_id = match ObjectId::parse_str(meeting_config_id) {
@@ -93,12 +493,15 @@ pub async fn get(
     };
 
     let result = collection
-        .find_one(doc! { "_id": meeting_config_id }, None)
+        .find_one(doc! { "_id": meeting_config_id, "deleted_at": null }, None)
         .await
         .unwrap();
 
     match result {
-        Some(meeting) => Ok(Response::Success(Json(meeting))),
+        Some(meeting) => {
+            let etag = ETag(meeting.version);
+            Ok(Response::Tagged(Json(meeting.into()), etag))
+        }
         None => Err(Status::NotFound),
     }
 }
@@ -107,45 +510,48 @@ pub async fn get(
 pub async fn update(
     db_pool: &State<Pool>,
     meeting_config_id: String,
-    meeting_config: Json<MeetingConfig>,
-) -> Result<Response<MeetingConfig>, Status> {
+    if_match: IfMatch,
+    meeting_config: Json<MeetingConfigRequestBody>,
+) -> Result<Response<MeetingConfigView>, ApiError> {
+    validate(&meeting_config.0)?;
+
     let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
     let team_collection = get_collection::<Team>(db_pool, "teams").await;
 
-    let mut new_meeting_config = meeting_config.0.clone();
+    let new_meeting_config = MeetingConfig::try_from(meeting_config.0)?;
 
     let meeting_config_id = match ObjectId::parse_str(meeting_config_id) {
         Ok(id) => id,
-        Err(_) => return Err(Status::UnprocessableEntity),
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
     };
 
-    // get team_id as object_id
-    new_meeting_config.team_id = Some(
-        match ObjectId::parse_str(&new_meeting_config.team_id_str.unwrap()) {
-            Ok(id) => id,
-            Err(_) => return Err(Status::UnprocessableEntity),
-        },
-    );
-
     // check if that team exists
     let team_exists: bool = team_collection
-        .find_one(doc! { "_id": &new_meeting_config.team_id.unwrap() }, None)
+        .find_one(
+            doc! { "_id": &new_meeting_config.team_id.unwrap(), "deleted_at": null },
+            None,
+        )
         .await
         .unwrap()
         .is_some();
 
     if team_exists {
+        let mut filter = doc! { "_id": meeting_config_id, "deleted_at": null };
+        if_match.apply(&mut filter);
+
         let result = collection
             .find_one_and_update(
-                doc! { "_id": meeting_config_id },
+                filter,
                 doc! {
                     "$set": {
                         "team_id": &new_meeting_config.team_id.unwrap(),
                         "desired_duration": to_bson(&new_meeting_config.desired_duration).unwrap(),
                         "config_name": &new_meeting_config.config_name,
                         "description": &new_meeting_config.description,
-                        "meeting_type": &new_meeting_config.meeting_type
-                    }
+                        "meeting_type": &new_meeting_config.meeting_type,
+                        "schedule": to_bson(&new_meeting_config.schedule).unwrap()
+                    },
+                    "$inc": { "version": 1 }
                 },
                 FindOneAndUpdateOptions::builder()
                     .return_document(ReturnDocument::After)
@@ -155,55 +561,362 @@ pub async fn update(
 
         match result {
             Ok(result) => match result {
-                Some(new_config) => Ok(Response::Success(Json(new_config))),
-                None => Err(Status::NotFound),
+                Some(new_config) => {
+                    reschedule_meetings(db_pool, &new_config).await;
+                    config_changed(db_pool, ConfigChange::UPDATED, &new_config).await;
+                    let etag = ETag(new_config.version);
+                    Ok(Response::Tagged(Json(new_config.into()), etag))
+                }
+                None => {
+                    let exists = collection
+                        .find_one(doc! { "_id": meeting_config_id, "deleted_at": null }, None)
+                        .await
+                        .unwrap()
+                        .is_some();
+                    Err(if_match.failed_write_status(exists).into())
+                }
             },
+            Err(error) if is_validation_failure(&error) => Err(Status::UnprocessableEntity.into()),
             Err(error) => {
                 eprintln!("[UPDATE][MEETING_CONFIG] ~ {}", error);
-                Err(Status::InternalServerError)
+                Err(Status::InternalServerError.into())
             }
         }
     } else {
-        Err(Status::NotFound)
+        Err(Status::NotFound.into())
+    }
+}
+
+/// Fields of a meeting config that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MeetingConfigPatch {
+    #[validate(custom = "object_id")]
+    team_id: Option<String>,
+    #[validate(range(min = 1, max = 86400))]
+    desired_duration: Option<i64>,
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    config_name: Option<String>,
+    #[validate(length(max = 1000))]
+    description: Option<String>,
+    meeting_type: Option<MeetingType>,
+    /// `null` stops the recurring meetings
+    #[serde(default, deserialize_with = "nullable")]
+    schedule: Option<Option<Schedule>>,
+}
+
+/// Partial update of a meeting config, only the fields sent are changed
+#[rocket::patch("/<meeting_config_id>", format = "json", data = "<meeting_config>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    meeting_config_id: String,
+    if_match: IfMatch,
+    meeting_config: Json<MeetingConfigPatch>,
+) -> Result<Response<MeetingConfigView>, ApiError> {
+    validate(&meeting_config.0)?;
+
+    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+    let meeting_config_id = parse_id(&meeting_config_id)?;
+    let fields = meeting_config.0;
+
+    let mut changes = Document::new();
+    if let Some(team_id) = fields.team_id {
+        let team_id = parse_id(&team_id)?;
+        let team_collection = get_collection::<Team>(db_pool, "teams").await;
+        check_reference(&team_collection, team_id).await?;
+        changes.insert("team_id", team_id);
+    }
+    if let Some(desired_duration) = fields.desired_duration {
+        changes.insert("desired_duration", desired_duration);
+    }
+    if let Some(config_name) = fields.config_name {
+        changes.insert("config_name", config_name);
+    }
+    if let Some(description) = fields.description {
+        changes.insert("description", description);
     }
+    if let Some(meeting_type) = fields.meeting_type {
+        changes.insert("meeting_type", meeting_type.value());
+    }
+    let rescheduled = fields.schedule.is_some() || changes.contains_key("desired_duration");
+    if let Some(schedule) = fields.schedule {
+        if let Some(schedule) = &schedule {
+            validate(schedule)?;
+        }
+        changes.insert("schedule", to_bson(&schedule).unwrap());
+    }
+
+    let meeting_config = patch_one(&collection, meeting_config_id, if_match, changes).await?;
+    if rescheduled {
+        reschedule_meetings(db_pool, &meeting_config).await;
+    }
+    config_changed(db_pool, ConfigChange::UPDATED, &meeting_config).await;
+
+    let etag = ETag(meeting_config.version);
+    Ok(Response::Tagged(Json(meeting_config.into()), etag))
 }
 
 #[rocket::delete("/<meeting_config_id>")]
 pub async fn delete(
     db_pool: &State<Pool>,
     meeting_config_id: String,
-) -> Result<Response<MeetingConfig>, Status> {
-    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+    if_match: IfMatch,
+) -> Result<Response<MeetingConfigView>, ApiError> {
+    let meeting_config_id = match ObjectId::parse_str(meeting_config_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    // held meetings keep the history of the config, they have to be deleted first
+    let mut held = doc! { "config_id": meeting_config_id };
+    held.extend(MeetingStatus::HELD.filter());
+    let restrictions = [("meetings", held)];
+    if let Some(blockers) = find_blockers(&db, &mut session, &restrictions).await? {
+        return Err(ApiError::Blocked(Json(blockers)));
+    }
 
+    let deleted_at = Utc::now().timestamp_millis();
+    let result = soft_delete_one::<MeetingConfig>(
+        &db,
+        &mut session,
+        "meeting_configs",
+        meeting_config_id,
+        if_match.0,
+        deleted_at,
+    )
+    .await?;
+
+    let meeting_config = match result {
+        Some(meeting_config) => meeting_config,
+        None => {
+            let exists = is_active(&db, &mut session, "meeting_configs", meeting_config_id).await?;
+            return Err(if_match.failed_write_status(exists).into());
+        }
+    };
+
+    // the meetings that didn't take place go away with it, together with the
+    // attendance recorded in their live sessions and their action items
+    let scheduled = doc! { "config_id": meeting_config_id, "status": "SCHEDULED" };
+    let mut active = scheduled.clone();
+    active.insert("deleted_at", Bson::Null);
+    let meetings_id = find_ids(&db, &mut session, "meetings", active).await?;
+    soft_delete_many(&db, &mut session, "meetings", scheduled, deleted_at).await?;
+    soft_delete_many(
+        &db,
+        &mut session,
+        "attendances",
+        doc! { "meeting_id": { "$in": meetings_id.clone() } },
+        deleted_at,
+    )
+    .await?;
+    soft_delete_many(
+        &db,
+        &mut session,
+        "action_items",
+        doc! { "meeting_id": { "$in": meetings_id } },
+        deleted_at,
+    )
+    .await?;
+
+    commit_transaction(&mut session).await?;
+
+    config_changed(db_pool, ConfigChange::DELETED, &meeting_config).await;
+    Ok(Response::Success(Json(meeting_config.into())))
+}
+
+#[rocket::put("/<meeting_config_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    meeting_config_id: String,
+    if_match: IfMatch,
+) -> Result<Response<MeetingConfigView>, Status> {
     let meeting_config_id = match ObjectId::parse_str(meeting_config_id) {
         Ok(id) => id,
         Err(_) => return Err(Status::UnprocessableEntity),
     };
 
-    let result = collection
-        .find_one_and_delete(
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored = restore_one::<MeetingConfig>(
+        &db,
+        &mut session,
+        "meeting_configs",
+        meeting_config_id,
+        if_match.0,
+    )
+    .await?;
+    let mut meeting_config = match restored {
+        Some(meeting_config) => meeting_config,
+        None => {
+            let trashed =
+                is_trashed(&db, &mut session, "meeting_configs", meeting_config_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // the team has to be restored first
+    if let Some(team_id) = meeting_config.team_id {
+        if !is_active(&db, &mut session, "teams", team_id).await? {
+            return Err(Status::Conflict);
+        }
+    }
+
+    // scheduled meetings, their attendance and action items deleted together
+    // with the config
+    let deleted_at = meeting_config.deleted_at.unwrap().timestamp_millis();
+    let scheduled = doc! { "config_id": meeting_config_id, "status": "SCHEDULED" };
+    let mut trashed = scheduled.clone();
+    trashed.insert("deleted_at", deleted_at);
+    let meetings_id = find_ids(&db, &mut session, "meetings", trashed).await?;
+    restore_many(&db, &mut session, "meetings", scheduled, deleted_at).await?;
+    restore_many(
+        &db,
+        &mut session,
+        "attendances",
+        doc! { "meeting_id": { "$in": meetings_id.clone() } },
+        deleted_at,
+    )
+    .await?;
+    restore_many(
+        &db,
+        &mut session,
+        "action_items",
+        doc! { "meeting_id": { "$in": meetings_id } },
+        deleted_at,
+    )
+    .await?;
+
+    commit_transaction(&mut session).await?;
+
+    meeting_config.deleted_at = None;
+    meeting_config.version += 1;
+    config_changed(db_pool, ConfigChange::RESTORED, &meeting_config).await;
+    Ok(Response::Success(Json(meeting_config.into())))
+}
+
+#[rocket::get("/all?<team_id>&<meeting_type>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    team_id: Option<String>,
+    meeting_type: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<MeetingConfigView>>, Status> {
+    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+    let opts = PageOptions::new(
+        page,
+        limit,
+        sort,
+        &["config_name", "desired_duration", "meeting_type", "_id"],
+    )?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(team_id) = team_id {
+        match ObjectId::parse_str(team_id) {
+            Ok(id) => filter.insert("team_id", id),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+    }
+    if let Some(meeting_type) = meeting_type {
+        filter.insert("meeting_type", meeting_type);
+    }
+
+    let configs = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(configs.map(MeetingConfigView::from))))
+}
+
+#[rocket::get("/<meeting_config_id>/occurrences?<from>&<limit>")]
+pub async fn occurrences(
+    db_pool: &State<Pool>,
+    meeting_config_id: String,
+    from: Option<i64>,
+    limit: Option<usize>,
+) -> Result<Response<Vec<Occurrence>>, Status> {
+    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
+    let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
+
+    let meeting_config_id = parse_id(&meeting_config_id)?;
+    let from = match from {
+        Some(from) => match Utc.timestamp_millis_opt(from).single() {
+            Some(from) => from,
+            None => return Err(Status::UnprocessableEntity),
+        },
+        None => Utc::now(),
+    };
+    // the occurrences are looked for from the day before to a year later, which
+    // have to be valid dates too
+    let until = match (
+        from.checked_sub_signed(Duration::days(1)),
+        from.checked_add_signed(Duration::days(OCCURRENCES_AHEAD_DAYS)),
+    ) {
+        (Some(_), Some(until)) => until,
+        _ => return Err(Status::UnprocessableEntity),
+    };
+    let limit = limit.unwrap_or(10).min(MAX_OCCURRENCES);
+
+    let config = match collection
+        .find_one(doc! { "_id": meeting_config_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(config) => config,
+        None => return Err(Status::NotFound),
+    };
+
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+    let is_working_day = match is_working_day(&db, &config).await {
+        Ok(is_working_day) => is_working_day,
+        Err(error) => {
+            eprintln!("[OCCURRENCES][TEAM] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    // configs without a schedule have no occurrences, and the days off of the team are skipped
+    let starts: Vec<DateTime<Utc>> = match &config.schedule {
+        Some(schedule) => match schedule.occurrences(from) {
+            Ok(occurrences) => occurrences
+                .take_while(|start_utc| *start_utc < until)
+                .filter(is_working_day)
+                .take(limit)
+                .collect(),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        },
+        None => vec![],
+    };
+
+    // meetings already created for the occurrences
+    let occurrences_utc: Vec<i64> = starts.iter().map(|start| start.timestamp_millis()).collect();
+    let meetings: Vec<Meeting> = meeting_collection
+        .find(
             doc! {
-                "_id": meeting_config_id
+                "config_id": meeting_config_id,
+                "occurrence_utc": { "$in": occurrences_utc },
+                "deleted_at": null
             },
             None,
         )
         .await
+        .unwrap()
+        .try_collect()
+        .await
         .unwrap();
 
-    match result {
-        Some(meeting_config) => Ok(Response::Success(Json(meeting_config))),
-        None => Err(Status::NotFound),
-    }
-}
-
-#[rocket::get("/all")]
-pub async fn all(db_pool: &State<Pool>) -> Result<Json<Vec<MeetingConfig>>, Status> {
-    let collection = get_collection::<MeetingConfig>(db_pool, "meeting_configs").await;
-
-    let configs = collection.find(doc!{}, None).await.unwrap().try_collect::<Vec<MeetingConfig>>().await;
+    let occurrences = starts
+        .into_iter()
+        .map(|start_utc| Occurrence {
+            start_utc,
+            end_utc: start_utc + Duration::seconds(config.desired_duration),
+            meeting_id: meetings
+                .iter()
+                .find(|meeting| meeting.occurrence_utc == Some(start_utc))
+                .map(|meeting| meeting.id.unwrap().to_hex()),
+        })
+        .collect();
 
-    match configs {
-        Ok(configs) => Ok(Json(configs)),
-        Err(_) => Err(Status::InternalServerError)
-    }
+    Ok(Response::Success(Json(occurrences)))
 }
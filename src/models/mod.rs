@@ -1,10 +1,22 @@
 use rocket::{routes, Build};
 
+pub mod absence;
+pub mod action_item;
+pub mod attendance;
+pub mod calendar;
+pub mod chat;
+pub mod export;
+pub mod invitation;
+pub mod job;
 pub mod meeting;
 pub mod meeting_config;
+pub mod notification;
+pub mod stats;
 pub mod team;
+pub mod trash;
 pub mod user;
 pub mod user_time;
+pub mod webhook;
 
 pub fn mount(rocket: rocket::Rocket<Build>) -> rocket::Rocket<Build> {
     rocket
@@ -15,7 +27,11 @@ pub fn mount(rocket: rocket::Rocket<Build>) -> rocket::Rocket<Build> {
                 user::signup,
                 user::get,
                 user::update,
-                user::delete
+                user::patch,
+                user::delete,
+                user::restore,
+                user::get_user_times,
+                user::rotate_calendar_token
             ],
         )
         .mount(
@@ -24,18 +40,42 @@ pub fn mount(rocket: rocket::Rocket<Build>) -> rocket::Rocket<Build> {
                 team::create,
                 team::get,
                 team::update,
+                team::patch,
+                team::delete,
+                team::restore,
                 team::get_users,
+                team::get_members,
+                team::add_members,
+                team::remove_member,
+                team::transfer_ownership,
                 team::all
             ],
         )
-        .mount("/api/meeting", routes![meeting::create, meeting::get])
+        .mount(
+            "/api/meeting",
+            routes![
+                meeting::create,
+                meeting::get,
+                meeting::update,
+                meeting::patch,
+                meeting::delete,
+                meeting::restore,
+                meeting::get_user_times,
+                meeting::timeline,
+                meeting::session,
+                meeting::all
+            ],
+        )
         .mount(
             "/api/meeting_config",
             routes![
                 meeting_config::create,
                 meeting_config::get,
                 meeting_config::update,
+                meeting_config::patch,
                 meeting_config::delete,
+                meeting_config::restore,
+                meeting_config::occurrences,
                 meeting_config::all
             ],
         )
@@ -45,7 +85,104 @@ pub fn mount(rocket: rocket::Rocket<Build>) -> rocket::Rocket<Build> {
                 user_time::create,
                 user_time::get,
                 user_time::update,
-                user_time::delete
+                user_time::patch,
+                user_time::delete,
+                user_time::restore,
+                user_time::all
+            ],
+        )
+        .mount(
+            "/api/invitation",
+            routes![
+                invitation::create,
+                invitation::pending,
+                invitation::get,
+                invitation::accept,
+                invitation::decline,
+                invitation::revoke
+            ],
+        )
+        .mount(
+            "/api/absence",
+            routes![
+                absence::create,
+                absence::get,
+                absence::update,
+                absence::patch,
+                absence::delete,
+                absence::restore,
+                absence::all
+            ],
+        )
+        .mount(
+            "/api/attendance",
+            routes![
+                attendance::record,
+                attendance::get_meeting,
+                attendance::delete
+            ],
+        )
+        .mount(
+            "/api/action_item",
+            routes![
+                action_item::create,
+                action_item::get,
+                action_item::patch,
+                action_item::delete,
+                action_item::restore,
+                action_item::all
             ],
         )
+        .mount(
+            "/api/notification",
+            routes![
+                notification::get_preferences,
+                notification::update_preferences,
+                notification::inbox,
+                notification::read,
+                notification::read_all
+            ],
+        )
+        .mount(
+            "/api/webhook",
+            routes![
+                webhook::create,
+                webhook::get,
+                webhook::patch,
+                webhook::delete,
+                webhook::restore,
+                webhook::all,
+                webhook::deliveries,
+                webhook::test
+            ],
+        )
+        .mount(
+            "/api/chat",
+            routes![
+                chat::get_settings,
+                chat::update_settings,
+                chat::delete_settings,
+                chat::preview,
+                chat::post,
+                chat::slash_command
+            ],
+        )
+        .mount("/api/admin/jobs", routes![job::all, job::run])
+        .mount("/api/trash", routes![trash::purge])
+        .mount(
+            "/api/export",
+            routes![export::meetings, export::user_times, export::overruns],
+        )
+        .mount(
+            "/api/stats",
+            routes![
+                stats::meetings,
+                stats::team_attendance,
+                stats::user_attendance
+            ],
+        )
+        .mount(
+            "/api/calendar",
+            routes![calendar::user_feed, calendar::team_feed],
+        )
 }
@@ -0,0 +1,336 @@
+use crate::config::Pool;
+use crate::notifier::ChannelKind;
+use crate::utils::{
+    concurrency::IfMatch,
+    db::{get_collection, parse_id},
+    pagination::{paginate, Page, PageOptions},
+    responders::{ApiError, Response},
+    validation::{invalid_field, public_url, validate},
+};
+use bson::{doc, oid::ObjectId, to_bson};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Utc};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::user::User;
+
+/// Most minutes before a meeting its reminder can be sent
+pub const MAX_REMINDER_MINUTES: i64 = 120;
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum NotificationKind {
+    /// A scheduled meeting of a team of the user is about to start
+    MEETING_REMINDER,
+    /// An action item was assigned to the user
+    ACTION_ITEM_ASSIGNED,
+    /// An action item of the user is past its due date
+    ACTION_ITEM_OVERDUE,
+}
+
+fn default_channels() -> Vec<ChannelKind> {
+    vec![ChannelKind::INBOX, ChannelKind::EMAIL]
+}
+
+fn default_reminder_minutes() -> Option<i64> {
+    Some(10)
+}
+
+fn default_action_items() -> bool {
+    true
+}
+
+/// What a user wants to be notified about and how
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NotificationPreferences {
+    /// Channels the notifications are sent through, `INBOX` and `EMAIL` by default
+    #[serde(default = "default_channels")]
+    pub(crate) channels: Vec<ChannelKind>,
+    /// Endpoint of the `WEBHOOK` channel
+    #[serde(default)]
+    pub(crate) webhook_url: Option<String>,
+    /// Minutes before a scheduled meeting the reminder is sent, `None` disables them
+    #[serde(default = "default_reminder_minutes")]
+    pub(crate) reminder_minutes: Option<i64>,
+    /// Whether to be notified about the action items assigned to the user
+    #[serde(default = "default_action_items")]
+    pub(crate) action_items: bool,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        Self {
+            channels: default_channels(),
+            webhook_url: None,
+            reminder_minutes: default_reminder_minutes(),
+            action_items: default_action_items(),
+        }
+    }
+}
+
+impl NotificationPreferences {
+    /// Whether the user wants the notifications of the kind
+    pub(crate) fn wants(&self, kind: NotificationKind) -> bool {
+        match kind {
+            NotificationKind::MEETING_REMINDER => self.reminder_minutes.is_some(),
+            NotificationKind::ACTION_ITEM_ASSIGNED | NotificationKind::ACTION_ITEM_OVERDUE => {
+                self.action_items
+            }
+        }
+    }
+}
+
+/// Body of the update of the preferences of a user
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct NotificationPreferencesRequestBody {
+    /// EMAIL | WEBHOOK | INBOX, an empty list disables every notification
+    channels: Vec<ChannelKind>,
+    /// Required with the `WEBHOOK` channel
+    #[validate(custom = "public_url", length(max = 2048))]
+    webhook_url: Option<String>,
+    #[validate(range(min = 1, max = "MAX_REMINDER_MINUTES"))]
+    reminder_minutes: Option<i64>,
+    action_items: bool,
+}
+
+/// Notification sent to a user, listed in their inbox when they chose it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Notification {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) user_id: ObjectId,
+    pub(crate) kind: NotificationKind,
+    pub(crate) title: String,
+    pub(crate) body: String,
+    /// Meeting or action item the notification is about
+    pub(crate) subject_id: ObjectId,
+    /// Key of the notifications that are only sent once per user (reminders)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dedup_key: Option<String>,
+    /// Whether the notification is shown in the inbox of the user
+    pub(crate) inbox: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) created_at: DateTime<Utc>,
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) read_at: Option<DateTime<Utc>>,
+}
+
+/// Notification as returned by the API and posted to the webhooks
+#[derive(Serialize, Clone, Debug)]
+pub struct NotificationView {
+    id: String,
+    user_id: String,
+    kind: NotificationKind,
+    title: String,
+    body: String,
+    subject_id: String,
+    #[serde(with = "ts_milliseconds")]
+    created_at: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds_option")]
+    read_at: Option<DateTime<Utc>>,
+}
+
+impl From<Notification> for NotificationView {
+    fn from(notification: Notification) -> Self {
+        Self {
+            id: notification.id.map(|id| id.to_hex()).unwrap_or_default(),
+            user_id: notification.user_id.to_hex(),
+            kind: notification.kind,
+            title: notification.title,
+            body: notification.body,
+            subject_id: notification.subject_id.to_hex(),
+            created_at: notification.created_at,
+            read_at: notification.read_at,
+        }
+    }
+}
+
+/// Amount of notifications marked as read
+#[derive(Serialize, Clone, Debug)]
+pub struct ReadCount {
+    read: u64,
+}
+
+async fn active_user(db_pool: &State<Pool>, user_id: ObjectId) -> Result<User, Status> {
+    let collection = get_collection::<User>(db_pool, "users").await;
+
+    match collection
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(user) => Ok(user),
+        None => Err(Status::NotFound),
+    }
+}
+
+#[rocket::get("/preferences/<user_id>")]
+pub async fn get_preferences(
+    db_pool: &State<Pool>,
+    user_id: String,
+) -> Result<Response<NotificationPreferences>, Status> {
+    let user = active_user(db_pool, parse_id(&user_id)?).await?;
+
+    Ok(Response::Success(Json(user.notifications)))
+}
+
+#[rocket::put("/preferences/<user_id>", format = "json", data = "<preferences>")]
+pub async fn update_preferences(
+    db_pool: &State<Pool>,
+    user_id: String,
+    if_match: IfMatch,
+    preferences: Json<NotificationPreferencesRequestBody>,
+) -> Result<Response<NotificationPreferences>, ApiError> {
+    validate(&preferences.0)?;
+
+    let body = preferences.0;
+    if body.channels.contains(&ChannelKind::WEBHOOK) && body.webhook_url.is_none() {
+        return Err(invalid_field(
+            "webhook_url",
+            "required",
+            "is required with the WEBHOOK channel",
+        ));
+    }
+
+    let mut channels: Vec<ChannelKind> = vec![];
+    for channel in body.channels {
+        if !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+    let preferences = NotificationPreferences {
+        channels,
+        webhook_url: body.webhook_url,
+        reminder_minutes: body.reminder_minutes,
+        action_items: body.action_items,
+    };
+
+    let collection = get_collection::<User>(db_pool, "users").await;
+    let user_id = parse_id(&user_id)?;
+    let mut filter = doc! { "_id": user_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": { "notifications": to_bson(&preferences).unwrap() },
+                "$inc": { "version": 1 }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    match result {
+        Ok(Some(user)) => Ok(Response::Success(Json(user.notifications))),
+        Ok(None) => {
+            let exists = collection
+                .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
+        Err(error) => {
+            eprintln!("[UPDATE][NOTIFICATION_PREFERENCES] ~ {}", error);
+            Err(Status::InternalServerError.into())
+        }
+    }
+}
+
+/// In-app inbox of a user, newest first by default
+#[rocket::get("/inbox/<user_id>?<unread>&<page>&<limit>&<sort>")]
+pub async fn inbox(
+    db_pool: &State<Pool>,
+    user_id: String,
+    unread: Option<bool>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<NotificationView>>, Status> {
+    let collection = get_collection::<Notification>(db_pool, "notifications").await;
+    let user_id = parse_id(&user_id)?;
+    active_user(db_pool, user_id).await?;
+
+    let opts = PageOptions::new(page, limit, sort, &["-created_at", "_id"])?;
+
+    let mut filter = doc! { "user_id": user_id, "inbox": true };
+    if unread == Some(true) {
+        filter.insert("read_at", bson::Bson::Null);
+    }
+
+    let notifications = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(
+        notifications.map(NotificationView::from),
+    )))
+}
+
+#[rocket::put("/<notification_id>/read")]
+pub async fn read(
+    db_pool: &State<Pool>,
+    notification_id: String,
+) -> Result<Response<NotificationView>, Status> {
+    let collection = get_collection::<Notification>(db_pool, "notifications").await;
+    let notification_id = parse_id(&notification_id)?;
+
+    // reading it again keeps the first date
+    let result = collection
+        .find_one_and_update(
+            doc! { "_id": notification_id, "inbox": true },
+            vec![doc! {
+                "$set": { "read_at": { "$ifNull": ["$read_at", Utc::now().timestamp_millis()] } }
+            }],
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    match result {
+        Ok(Some(notification)) => Ok(Response::Success(Json(notification.into()))),
+        Ok(None) => Err(Status::NotFound),
+        Err(error) => {
+            eprintln!("[UPDATE][NOTIFICATION] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Marks every notification in the inbox of the user as read
+#[rocket::put("/inbox/<user_id>/read")]
+pub async fn read_all(
+    db_pool: &State<Pool>,
+    user_id: String,
+) -> Result<Response<ReadCount>, Status> {
+    let collection = get_collection::<Notification>(db_pool, "notifications").await;
+    let user_id = parse_id(&user_id)?;
+    active_user(db_pool, user_id).await?;
+
+    let result = collection
+        .update_many(
+            doc! { "user_id": user_id, "inbox": true, "read_at": null },
+            doc! { "$set": { "read_at": Utc::now().timestamp_millis() } },
+            None,
+        )
+        .await;
+
+    match result {
+        Ok(result) => Ok(Response::Success(Json(ReadCount {
+            read: result.modified_count,
+        }))),
+        Err(error) => {
+            eprintln!("[UPDATE][NOTIFICATION] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::config::Pool;
+use crate::utils::{
+    db::{get_collection, parse_id},
+    responders::Response,
+    work_calendar::Bucket,
+};
+use bson::{doc, oid::ObjectId, Document};
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use rocket::{http::Status, serde::json::Json, State};
+use serde::Serialize;
+
+use super::{
+    attendance::{Attendance, AttendanceStatus},
+    export::{team_meetings, ExportFilter},
+    meeting::{Meeting, MeetingStatus},
+    team::Team,
+    user::User,
+};
+
+/// Held meetings of a team grouped by local day or week
+#[derive(Serialize, Clone, Debug)]
+pub struct MeetingStats {
+    /// First local day of the bucket (a Monday for weeks)
+    start: NaiveDate,
+    meetings: u32,
+    /// Real duration of the meetings in milliseconds
+    duration_ms: i64,
+    /// Time the meetings lasted longer than desired, in milliseconds
+    overrun_ms: i64,
+    /// Meetings held on a day off (non working day or holiday) of the team
+    on_days_off: u32,
+}
+
+/// Meetings of a team bucketed by day or week in the timezone of the team
+#[rocket::get("/team/<team_id>/meetings?<bucket>&<from>&<to>&<config_id>")]
+pub async fn meetings(
+    db_pool: &State<Pool>,
+    team_id: String,
+    bucket: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    config_id: Option<String>,
+) -> Result<Response<Vec<MeetingStats>>, Status> {
+    let bucket = match bucket {
+        Some(bucket) => Bucket::from_str(&bucket).map_err(|_| Status::UnprocessableEntity)?,
+        None => Bucket::DAY,
+    };
+
+    let team_collection = get_collection::<Team>(db_pool, "teams").await;
+    let team = match team_collection
+        .find_one(doc! { "_id": parse_id(&team_id)?, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(team) => team,
+        None => return Err(Status::NotFound),
+    };
+    let calendar = team.calendar();
+
+    let (mut cursor, configs) =
+        team_meetings(db_pool, team_id, &ExportFilter::new(from, to, config_id)).await?;
+
+    let mut buckets: BTreeMap<NaiveDate, MeetingStats> = BTreeMap::new();
+    loop {
+        let meeting = match cursor.try_next().await {
+            Ok(Some(meeting)) => meeting,
+            Ok(None) => break,
+            Err(error) => {
+                eprintln!("[STATS][MEETING] ~ {}", error);
+                return Err(Status::InternalServerError);
+            }
+        };
+        let config = &configs[&meeting.config_id.unwrap()];
+
+        let start = calendar.bucket(meeting.date_utc, bucket);
+        let stats = buckets.entry(start).or_insert(MeetingStats {
+            start,
+            meetings: 0,
+            duration_ms: 0,
+            overrun_ms: 0,
+            on_days_off: 0,
+        });
+
+        let duration_ms = meeting.duration_ms();
+        stats.meetings += 1;
+        stats.duration_ms += duration_ms;
+        stats.overrun_ms += (duration_ms - config.desired_duration * 1000).max(0);
+        if !calendar.is_working_day(calendar.local(meeting.date_utc).date_naive()) {
+            stats.on_days_off += 1;
+        }
+    }
+
+    Ok(Response::Success(Json(buckets.into_values().collect())))
+}
+
+/// Attendance of one or several users to a set of meetings
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct AttendanceRate {
+    /// Attendances recorded
+    meetings: u32,
+    present: u32,
+    late: u32,
+    absent: u32,
+    excused: u32,
+    /// Minutes late added up
+    late_minutes: i64,
+    /// Share of the meetings attended (present or late) leaving the excused ones
+    /// aside, between 0 and 1. Missing without any meeting to count.
+    rate: Option<f64>,
+}
+
+impl AttendanceRate {
+    fn add(&mut self, attendance: &Attendance) {
+        self.meetings += 1;
+        match attendance.status {
+            AttendanceStatus::PRESENT => self.present += 1,
+            AttendanceStatus::LATE => self.late += 1,
+            AttendanceStatus::ABSENT => self.absent += 1,
+            AttendanceStatus::EXCUSED => self.excused += 1,
+        }
+        self.late_minutes += attendance.late_minutes.unwrap_or(0);
+
+        let expected = self.meetings - self.excused;
+        self.rate = (expected > 0).then(|| (self.present + self.late) as f64 / expected as f64);
+    }
+}
+
+/// Attendance of a user to the meetings of a team
+#[derive(Serialize, Clone, Debug)]
+pub struct UserAttendance {
+    user_id: String,
+    /// Name of the user (empty if the user doesn't exist anymore)
+    user_name: String,
+    #[serde(flatten)]
+    attendance: AttendanceRate,
+}
+
+/// Attendance to the held meetings of a team, overall and per user
+#[derive(Serialize, Clone, Debug)]
+pub struct TeamAttendance {
+    #[serde(flatten)]
+    total: AttendanceRate,
+    /// Sorted by name
+    users: Vec<UserAttendance>,
+}
+
+/// Active attendances recorded in the meetings matching `filter`
+async fn attendances(db_pool: &State<Pool>, filter: Document) -> Result<Vec<Attendance>, Status> {
+    let collection = get_collection::<Attendance>(db_pool, "attendances").await;
+
+    let attendances = match collection.find(filter, None).await {
+        Ok(cursor) => cursor.try_collect::<Vec<Attendance>>().await,
+        Err(error) => Err(error),
+    };
+
+    attendances.map_err(|error| {
+        eprintln!("[STATS][ATTENDANCE] ~ {}", error);
+        Status::InternalServerError
+    })
+}
+
+/// Attendance rate of the members of a team to its held meetings
+#[rocket::get("/team/<team_id>/attendance?<from>&<to>&<config_id>")]
+pub async fn team_attendance(
+    db_pool: &State<Pool>,
+    team_id: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    config_id: Option<String>,
+) -> Result<Response<TeamAttendance>, Status> {
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let (cursor, _) =
+        team_meetings(db_pool, team_id, &ExportFilter::new(from, to, config_id)).await?;
+    let meetings_id: Vec<ObjectId> = match cursor.try_collect::<Vec<Meeting>>().await {
+        Ok(meetings) => meetings.into_iter().filter_map(|meeting| meeting.id).collect(),
+        Err(error) => {
+            eprintln!("[STATS][MEETING] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let attendances = attendances(
+        db_pool,
+        doc! { "meeting_id": { "$in": meetings_id }, "deleted_at": null },
+    )
+    .await?;
+
+    let mut total = AttendanceRate::default();
+    let mut by_user: HashMap<ObjectId, AttendanceRate> = HashMap::new();
+    for attendance in &attendances {
+        total.add(attendance);
+        by_user.entry(attendance.user_id).or_default().add(attendance);
+    }
+
+    let users_id: Vec<ObjectId> = by_user.keys().cloned().collect();
+    let names: HashMap<ObjectId, String> = user_collection
+        .find(doc! { "_id": { "$in": users_id }, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .try_collect::<Vec<User>>()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| (user.id.unwrap(), user.name))
+        .collect();
+
+    let mut users: Vec<UserAttendance> = by_user
+        .into_iter()
+        .map(|(user_id, attendance)| UserAttendance {
+            user_id: user_id.to_hex(),
+            user_name: names.get(&user_id).cloned().unwrap_or_default(),
+            attendance,
+        })
+        .collect();
+    users.sort_by(|a, b| a.user_name.cmp(&b.user_name).then(a.user_id.cmp(&b.user_id)));
+
+    Ok(Response::Success(Json(TeamAttendance { total, users })))
+}
+
+/// Attendance rate of a user to the held meetings of every team
+#[rocket::get("/user/<user_id>/attendance?<from>&<to>")]
+pub async fn user_attendance(
+    db_pool: &State<Pool>,
+    user_id: String,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Response<AttendanceRate>, Status> {
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+    let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
+    let user_id = parse_id(&user_id)?;
+
+    let user_exists = user_collection
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .is_some();
+    if !user_exists {
+        return Err(Status::NotFound);
+    }
+
+    let attendances =
+        attendances(db_pool, doc! { "user_id": user_id, "deleted_at": null }).await?;
+
+    // only the held meetings of the period count
+    let meetings_id: Vec<ObjectId> = attendances.iter().map(|a| a.meeting_id).collect();
+    let mut meeting_query = doc! { "_id": { "$in": meetings_id }, "deleted_at": null };
+    meeting_query.extend(MeetingStatus::HELD.filter());
+    let mut date_query = Document::new();
+    if let Some(from) = from {
+        date_query.insert("$gte", from);
+    }
+    if let Some(to) = to {
+        date_query.insert("$lte", to);
+    }
+    if !date_query.is_empty() {
+        meeting_query.insert("date_utc", date_query);
+    }
+
+    let held: HashSet<ObjectId> = match meeting_collection.find(meeting_query, None).await {
+        Ok(cursor) => match cursor.try_collect::<Vec<Meeting>>().await {
+            Ok(meetings) => meetings.into_iter().filter_map(|meeting| meeting.id).collect(),
+            Err(error) => {
+                eprintln!("[STATS][MEETING] ~ {}", error);
+                return Err(Status::InternalServerError);
+            }
+        },
+        Err(error) => {
+            eprintln!("[STATS][MEETING] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let mut rate = AttendanceRate::default();
+    for attendance in attendances.iter().filter(|a| held.contains(&a.meeting_id)) {
+        rate.add(attendance);
+    }
+
+    Ok(Response::Success(Json(rate)))
+}
@@ -1,37 +1,277 @@
 use crate::config::Pool;
-use crate::utils::{db::get_collection, responders::Response};
-use mongodb::bson::{doc, oid::ObjectId};
+use crate::utils::{
+    db::{commit_transaction, get_collection, parse_id, start_transaction},
+    concurrency::{ETag, IfMatch},
+    integrity::{
+        find_blockers, is_active, is_trashed, restore_many, restore_one, soft_delete_many,
+        soft_delete_one,
+    },
+    pagination::{paginate, Page, PageOptions},
+    patch::{check_reference, patch_one},
+    responders::{ApiError, Response},
+    validation::{not_blank, object_id, object_ids, timezone, validate},
+    work_calendar::{default_timezone, default_working_days, WeekDay, WorkCalendar},
+};
+use mongodb::bson::{doc, oid::ObjectId, to_bson, Document};
 use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::{ClientSession, Database};
+use std::collections::HashMap;
 use rocket::State;
 use rocket::{http::Status, serde::json::Json};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+use chrono::serde::ts_milliseconds_option;
+use chrono::{DateTime, NaiveDate, Utc};
 use futures::TryStreamExt;
 
-use super::user::User;
+use super::{
+    chat::ChatSettings,
+    invitation::Invitation,
+    meeting_config::{reschedule_team, MeetingConfig},
+    user::{User, UserView},
+};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Team {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    id: Option<ObjectId>,
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) name: String,
+    pub(crate) users: Option<Vec<ObjectId>>,
+    /// Scrum master of the team, always one of `users`
+    pub(crate) owner: Option<ObjectId>,
+    /// IANA timezone of the team (ex: `Europe/Madrid`)
+    #[serde(default = "default_timezone")]
+    pub(crate) timezone: String,
+    /// Days of the week the team works
+    #[serde(default = "default_working_days")]
+    pub(crate) working_days: Vec<WeekDay>,
+    /// Local days the team doesn't work, sorted
+    #[serde(default)]
+    pub(crate) holidays: Vec<NaiveDate>,
+    /// Channel where the summaries of the dailies are posted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chat: Option<ChatSettings>,
+    /// Date and time when the team was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the team
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+impl Team {
+    /// When the team works, to render dates in its local time
+    pub(crate) fn calendar(&self) -> WorkCalendar {
+        WorkCalendar::new(&self.timezone, &self.working_days, &self.holidays)
+    }
+}
+
+/// Work calendars of the teams the configs belong to, indexed by config id. Deleted
+/// configs and teams are included, their meetings keep being rendered in their time.
+pub(crate) async fn calendars_by_config(
+    db: &Database,
+    configs_id: Vec<ObjectId>,
+) -> Result<HashMap<ObjectId, WorkCalendar>, mongodb::error::Error> {
+    let configs = db
+        .collection::<MeetingConfig>("meeting_configs")
+        .find(doc! { "_id": { "$in": configs_id } }, None)
+        .await?
+        .try_collect::<Vec<MeetingConfig>>()
+        .await?;
+
+    let teams_id: Vec<ObjectId> = configs.iter().filter_map(|config| config.team_id).collect();
+    let teams: HashMap<ObjectId, WorkCalendar> = db
+        .collection::<Team>("teams")
+        .find(doc! { "_id": { "$in": teams_id } }, None)
+        .await?
+        .try_collect::<Vec<Team>>()
+        .await?
+        .into_iter()
+        .map(|team| (team.id.unwrap(), team.calendar()))
+        .collect();
+
+    Ok(configs
+        .into_iter()
+        .filter_map(|config| {
+            let calendar = teams.get(&config.team_id?)?;
+            Some((config.id.unwrap(), calendar.clone()))
+        })
+        .collect())
+}
+
+/// Sorts the working days and holidays of a team, removing the duplicates
+fn normalize_days(
+    mut working_days: Vec<WeekDay>,
+    mut holidays: Vec<NaiveDate>,
+) -> (Vec<WeekDay>, Vec<NaiveDate>) {
+    working_days.sort();
+    working_days.dedup();
+    holidays.sort();
+    holidays.dedup();
+
+    (working_days, holidays)
+}
+
+/// Team as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct TeamView {
+    id: String,
     name: String,
-    users: Option<Vec<ObjectId>>,
+    users: Vec<String>,
+    owner: Option<String>,
+    timezone: String,
+    working_days: Vec<WeekDay>,
+    holidays: Vec<NaiveDate>,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<Team> for TeamView {
+    fn from(team: Team) -> Self {
+        Self {
+            id: team.id.unwrap().to_hex(),
+            name: team.name,
+            users: team
+                .users
+                .unwrap_or_default()
+                .iter()
+                .map(|id| id.to_hex())
+                .collect(),
+            owner: team.owner.map(|id| id.to_hex()),
+            timezone: team.timezone,
+            working_days: team.working_days,
+            holidays: team.holidays,
+            deleted_at: team.deleted_at,
+            version: team.version,
+        }
+    }
+}
+
+/// Body of the creation of a team
+#[derive(Deserialize, Validate, Debug, Clone)]
+pub struct TeamRequestBody {
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    name: String,
+    /// Ids of the members of the team
+    #[validate(custom = "object_ids")]
+    users: Option<Vec<String>>,
+    /// Id of the scrum master, added to the members if missing
+    #[validate(custom = "object_id")]
+    owner: Option<String>,
+    /// IANA timezone, UTC by default
+    #[validate(custom = "timezone")]
+    #[serde(default = "default_timezone")]
+    timezone: String,
+    /// Monday to Friday by default
+    #[validate(length(min = 1, max = 7))]
+    #[serde(default = "default_working_days")]
+    working_days: Vec<WeekDay>,
+    /// Local days the team doesn't work (ex: `2023-12-25`)
+    #[validate(length(max = 366))]
+    #[serde(default)]
+    holidays: Vec<NaiveDate>,
+}
+
+impl TryFrom<TeamRequestBody> for Team {
+    type Error = Status;
+
+    fn try_from(body: TeamRequestBody) -> Result<Self, Self::Error> {
+        let users = match body.users {
+            Some(users) => Some(
+                users
+                    .iter()
+                    .map(|id| parse_id(id))
+                    .collect::<Result<Vec<ObjectId>, Status>>()?,
+            ),
+            None => None,
+        };
+        let (working_days, holidays) = normalize_days(body.working_days, body.holidays);
+
+        Ok(Self {
+            id: None,
+            name: body.name,
+            users,
+            owner: body.owner.as_deref().map(parse_id).transpose()?,
+            timezone: body.timezone,
+            working_days,
+            holidays,
+            chat: None,
+            deleted_at: None,
+            version: 0,
+        })
+    }
+}
+
+/// Body of the update of a team, members and owner are changed with their own routes
+#[derive(Deserialize, Validate, Debug, Clone)]
+pub struct TeamUpdateRequestBody {
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    name: String,
+    #[validate(custom = "timezone")]
+    #[serde(default = "default_timezone")]
+    timezone: String,
+    #[validate(length(min = 1, max = 7))]
+    #[serde(default = "default_working_days")]
+    working_days: Vec<WeekDay>,
+    #[validate(length(max = 366))]
+    #[serde(default)]
+    holidays: Vec<NaiveDate>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TeamRole {
+    OWNER,
+    MEMBER,
+}
+
+/// Member of a team with its role
+#[derive(Serialize, Clone, Debug)]
+pub struct Membership {
+    user_id: String,
+    name: String,
+    email: String,
+    role: TeamRole,
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct MembersRequestBody {
+    /// Ids of the users to add to the team
+    #[validate(length(min = 1), custom = "object_ids")]
+    users: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct OwnerRequestBody {
+    /// Id of the member that becomes the owner of the team
+    #[validate(custom = "object_id")]
+    user_id: String,
 }
 
 #[rocket::post("/", format = "json", data = "<team>")]
-pub async fn create(db_pool: &State<Pool>, team: Json<Team>) -> Result<Response<Team>, Status> {
+pub async fn create(
+    db_pool: &State<Pool>,
+    team: Json<TeamRequestBody>,
+) -> Result<Response<TeamView>, ApiError> {
+    validate(&team.0)?;
+
     let collection = get_collection::<Team>(db_pool, "teams").await;
     let user_collection = get_collection::<User>(db_pool, "users").await;
 
-    let mut new_team: Team = team.0.clone();
+    let mut new_team = Team::try_from(team.0)?;
     let mut new_team_users: Vec<ObjectId> = vec![];
 
     if let Some(users) = &new_team.users {
         for provided_user in users {
             let exists_in_db = user_collection
-                .find_one(doc! { "_id": provided_user }, None)
+                .find_one(doc! { "_id": provided_user, "deleted_at": null }, None)
                 .await
                 .unwrap();
-            let exists_in_list = new_team_users.contains(&provided_user);
+            let exists_in_list = new_team_users.contains(provided_user);
             if let Some(user) = exists_in_db {
                 if !exists_in_list {
                     new_team_users.push(user.get_id());
@@ -42,14 +282,32 @@ pub async fn create(db_pool: &State<Pool>, team: Json<Team>) -> Result<Response<
         new_team.users = Some(new_team_users);
     }
 
+    // the owner has to exist and is always a member of the team
+    if let Some(owner) = new_team.owner {
+        let owner_exists = user_collection
+            .find_one(doc! { "_id": owner, "deleted_at": null }, None)
+            .await
+            .unwrap()
+            .is_some();
+
+        if !owner_exists {
+            return Err(Status::NotFound.into());
+        }
+
+        let users = new_team.users.get_or_insert_with(Vec::new);
+        if !users.contains(&owner) {
+            users.push(owner);
+        }
+    }
+
     let result = collection.insert_one(&new_team, None).await.unwrap();
     new_team.id = Some(result.inserted_id.as_object_id().unwrap());
 
-    Ok(Response::Created(Json(new_team)))
+    Ok(Response::Created(Json(new_team.into())))
 }
 
 #[rocket::get("/<team_id>")]
-pub async fn get(db_pool: &State<Pool>, team_id: String) -> Result<Response<Team>, Status> {
+pub async fn get(db_pool: &State<Pool>, team_id: String) -> Result<Response<TeamView>, Status> {
     let collection = get_collection::<Team>(db_pool, "teams").await;
 
     let team_id: ObjectId = match ObjectId::parse_str(team_id) {
@@ -60,7 +318,8 @@ pub async fn get(db_pool: &State<Pool>, team_id: String) -> Result<Response<Team
     let result = collection
         .find_one(
             doc! {
-                "_id": team_id
+                "_id": team_id,
+                "deleted_at": null
             },
             None,
         )
@@ -68,7 +327,10 @@ pub async fn get(db_pool: &State<Pool>, team_id: String) -> Result<Response<Team
         .unwrap();
 
     match result {
-        Some(team) => Ok(Response::Success(Json(team))),
+        Some(team) => {
+            let etag = ETag(team.version);
+            Ok(Response::Tagged(Json(team.into()), etag))
+        }
         None => Err(Status::NotFound),
     }
 }
@@ -77,36 +339,228 @@ pub async fn get(db_pool: &State<Pool>, team_id: String) -> Result<Response<Team
 pub async fn update(
     db_pool: &State<Pool>,
     team_id: String,
-    team: Json<Team>,
-) -> Result<Response<Team>, Status> {
+    if_match: IfMatch,
+    team: Json<TeamUpdateRequestBody>,
+) -> Result<Response<TeamView>, ApiError> {
+    validate(&team.0)?;
+
     let collection = get_collection::<Team>(db_pool, "teams").await;
 
     let team_id = match ObjectId::parse_str(team_id) {
         Ok(team_id) => team_id,
-        Err(_) => return Err(Status::UnprocessableEntity),
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
     };
 
+    // the team before the update tells if its work calendar changed
     let opts = FindOneAndUpdateOptions::builder()
-        .return_document(Some(ReturnDocument::After))
+        .return_document(Some(ReturnDocument::Before))
         .build();
 
+    let mut filter = doc! { "_id": team_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let body = team.0;
+    let (working_days, holidays) = normalize_days(body.working_days, body.holidays);
     let result = collection
         .find_one_and_update(
-            doc! { "_id": team_id },
-            doc! { "$set": { "name": team.0.name } },
+            filter,
+            doc! {
+                "$set": {
+                    "name": &body.name,
+                    "timezone": &body.timezone,
+                    "working_days": to_bson(&working_days).unwrap(),
+                    "holidays": to_bson(&holidays).unwrap()
+                },
+                "$inc": { "version": 1 }
+            },
             opts,
         )
         .await
         .unwrap();
 
     match result {
-        Some(new_team) => Ok(Response::Success(Json(new_team))),
-        None => Err(Status::NotFound),
+        Some(old_team) => {
+            let rescheduled = old_team.timezone != body.timezone
+                || old_team.working_days != working_days
+                || old_team.holidays != holidays;
+            let new_team = Team {
+                name: body.name,
+                timezone: body.timezone,
+                working_days,
+                holidays,
+                version: old_team.version + 1,
+                ..old_team
+            };
+
+            if rescheduled {
+                reschedule_team(db_pool, team_id).await;
+            }
+            let etag = ETag(new_team.version);
+            Ok(Response::Tagged(Json(new_team.into()), etag))
+        }
+        None => {
+            let exists = collection
+                .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
+    }
+}
+
+/// Fields of a team that can be changed with a PATCH, the missing ones are kept.
+/// Members and owner are changed with their own routes.
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TeamPatch {
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    name: Option<String>,
+    // with the path, as the field shadows the validator
+    #[validate(custom = "crate::utils::validation::timezone")]
+    timezone: Option<String>,
+    #[validate(length(min = 1, max = 7))]
+    working_days: Option<Vec<WeekDay>>,
+    #[validate(length(max = 366))]
+    holidays: Option<Vec<NaiveDate>>,
+}
+
+/// Partial update of a team, only the fields sent are changed
+#[rocket::patch("/<team_id>", format = "json", data = "<team>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+    team: Json<TeamPatch>,
+) -> Result<Response<TeamView>, ApiError> {
+    validate(&team.0)?;
+
+    let collection = get_collection::<Team>(db_pool, "teams").await;
+    let team_id = parse_id(&team_id)?;
+
+    let fields = team.0;
+    let (working_days, holidays) = normalize_days(
+        fields.working_days.clone().unwrap_or_default(),
+        fields.holidays.clone().unwrap_or_default(),
+    );
+
+    let mut changes = Document::new();
+    if let Some(name) = fields.name {
+        changes.insert("name", name);
     }
+    if let Some(timezone) = fields.timezone {
+        changes.insert("timezone", timezone);
+    }
+    if fields.working_days.is_some() {
+        changes.insert("working_days", to_bson(&working_days).unwrap());
+    }
+    if fields.holidays.is_some() {
+        changes.insert("holidays", to_bson(&holidays).unwrap());
+    }
+    let rescheduled = ["timezone", "working_days", "holidays"]
+        .iter()
+        .any(|field| changes.contains_key(field));
+
+    let team = patch_one(&collection, team_id, if_match, changes).await?;
+    if rescheduled {
+        reschedule_team(db_pool, team_id).await;
+    }
+
+    let etag = ETag(team.version);
+    Ok(Response::Tagged(Json(team.into()), etag))
+}
+
+#[rocket::delete("/<team_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+) -> Result<Response<TeamView>, ApiError> {
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    // the configs (and their meetings) of the team have to be deleted first
+    let restrictions = [("meeting_configs", doc! { "team_id": team_id })];
+    if let Some(blockers) = find_blockers(&db, &mut session, &restrictions).await? {
+        return Err(ApiError::Blocked(Json(blockers)));
+    }
+
+    let deleted_at = Utc::now().timestamp_millis();
+
+    let result =
+        soft_delete_one::<Team>(&db, &mut session, "teams", team_id, if_match.0, deleted_at)
+            .await?;
+
+    let team = match result {
+        Some(team) => team,
+        None => {
+            let exists = is_active(&db, &mut session, "teams", team_id).await?;
+            return Err(if_match.failed_write_status(exists).into());
+        }
+    };
+
+    // pending invitations can't be accepted anymore
+    db.collection::<Invitation>("invitations")
+        .update_many_with_session(
+            doc! { "team_id": team_id, "status": "PENDING" },
+            doc! { "$set": { "status": "REVOKED" } },
+            None,
+            &mut session,
+        )
+        .await
+        .unwrap();
+
+    // its webhooks stop being notified
+    soft_delete_many(&db, &mut session, "webhooks", doc! { "team_id": team_id }, deleted_at)
+        .await?;
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(team.into())))
+}
+
+#[rocket::put("/<team_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+) -> Result<Response<TeamView>, Status> {
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored = restore_one::<Team>(&db, &mut session, "teams", team_id, if_match.0).await?;
+    let mut team = match restored {
+        Some(team) => team,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "teams", team_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // webhooks deleted together with the team
+    let deleted_at = team.deleted_at.unwrap().timestamp_millis();
+    restore_many(&db, &mut session, "webhooks", doc! { "team_id": team_id }, deleted_at).await?;
+
+    commit_transaction(&mut session).await?;
+
+    team.deleted_at = None;
+    team.version += 1;
+    Ok(Response::Success(Json(team.into())))
 }
 
 #[rocket::get("/<team_id>/users")]
-pub async fn get_users(db_pool: &State<Pool>, team_id: String) -> Result<Json<Vec<User>>, Status> {
+pub async fn get_users(
+    db_pool: &State<Pool>,
+    team_id: String,
+) -> Result<Json<Vec<UserView>>, Status> {
     let collection = get_collection::<Team>(db_pool, "teams").await;
 
     let team_id = match ObjectId::parse_str(team_id) {
@@ -115,24 +569,25 @@ pub async fn get_users(db_pool: &State<Pool>, team_id: String) -> Result<Json<Ve
     };
 
     let team = collection
-        .find_one(doc! { "_id": team_id }, None)
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
         .await
         .unwrap();
 
     match team {
         Some(team) => {
             let user_collection = get_collection::<User>(db_pool, "users").await;
-            let mut users = Vec::<User>::new();
+            let mut users = Vec::<UserView>::new();
             let users_id: Vec<ObjectId> = team.users.unwrap_or(Vec::<ObjectId>::new());
 
-            // get users from users id's
+            // get users from users id's, skipping the ones in the trash
             for id in users_id {
                 let user = user_collection
-                    .find_one(doc! { "_id": id }, None)
+                    .find_one(doc! { "_id": id, "deleted_at": null }, None)
                     .await
-                    .unwrap()
                     .unwrap();
-                users.push(user);
+                if let Some(user) = user {
+                    users.push(user.into());
+                }
             }
             Ok(Json(users))
         }
@@ -140,17 +595,291 @@ pub async fn get_users(db_pool: &State<Pool>, team_id: String) -> Result<Json<Ve
     }
 }
 
-#[rocket::get("/all")]
-pub async fn all(db_pool: &State<Pool>) -> Result<Json<Vec<Team>>, Status> {
+#[rocket::get("/all?<user_id>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    user_id: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<TeamView>>, Status> {
     let collection = get_collection::<Team>(db_pool, "teams").await;
+    let opts = PageOptions::new(page, limit, sort, &["name", "_id"])?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(user_id) = user_id {
+        match ObjectId::parse_str(user_id) {
+            Ok(id) => filter.insert("users", id),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+    }
+
+    let teams = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(teams.map(TeamView::from))))
+}
 
-    let teams = collection.find(doc! {}, None).await.unwrap().try_collect::<Vec<Team>>().await;
+/// Adds the users to the team without duplicating them within the transaction of
+/// `session`, returns the updated team or `None` if it doesn't exist or doesn't
+/// have the expected version
+pub(crate) async fn add_users(
+    db: &Database,
+    session: &mut ClientSession,
+    team_id: ObjectId,
+    if_match: IfMatch,
+    users_id: Vec<ObjectId>,
+) -> Result<Option<Team>, Status> {
+    let collection = db.collection::<Team>("teams");
 
-    match teams {
-        Ok(teams) => Ok(Json(teams)),
-        Err(_) => Err(Status::InternalServerError)
+    // teams created without users have a null list, which can't be added to
+    let result = collection
+        .update_one_with_session(
+            doc! { "_id": team_id, "users": null, "deleted_at": null },
+            doc! { "$set": { "users": [] } },
+            None,
+            session,
+        )
+        .await;
+    if let Err(error) = result {
+        eprintln!("[UPDATE][TEAM] ~ {}", error);
+        return Err(Status::InternalServerError);
     }
 
+    let opts = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+
+    let mut filter = doc! { "_id": team_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    collection
+        .find_one_and_update_with_session(
+            filter,
+            doc! {
+                "$addToSet": { "users": { "$each": users_id } },
+                "$inc": { "version": 1 }
+            },
+            opts,
+            session,
+        )
+        .await
+        .map_err(|error| {
+            eprintln!("[UPDATE][TEAM] ~ {}", error);
+            Status::InternalServerError
+        })
 }
 
+#[rocket::get("/<team_id>/members")]
+pub async fn get_members(
+    db_pool: &State<Pool>,
+    team_id: String,
+) -> Result<Response<Vec<Membership>>, Status> {
+    let collection = get_collection::<Team>(db_pool, "teams").await;
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let team = match collection
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(team) => team,
+        None => return Err(Status::NotFound),
+    };
+
+    let users = user_collection
+        .find(
+            doc! { "_id": { "$in": team.users.unwrap_or_default() }, "deleted_at": null },
+            None,
+        )
+        .await
+        .unwrap()
+        .try_collect::<Vec<User>>()
+        .await
+        .unwrap();
+
+    let members = users
+        .into_iter()
+        .map(|user| {
+            let user_id = user.id.unwrap();
+            Membership {
+                user_id: user_id.to_hex(),
+                name: user.name,
+                email: user.email,
+                role: if team.owner == Some(user_id) {
+                    TeamRole::OWNER
+                } else {
+                    TeamRole::MEMBER
+                },
+            }
+        })
+        .collect();
+
+    Ok(Response::Success(Json(members)))
+}
+
+#[rocket::post("/<team_id>/members", format = "json", data = "<members>")]
+pub async fn add_members(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+    members: Json<MembersRequestBody>,
+) -> Result<Response<TeamView>, ApiError> {
+    validate(&members.0)?;
+
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let mut users_id: Vec<ObjectId> = vec![];
+    for user_id in &members.0.users {
+        match ObjectId::parse_str(user_id) {
+            Ok(id) if !users_id.contains(&id) => users_id.push(id),
+            Ok(_) => (),
+            Err(_) => return Err(Status::UnprocessableEntity.into()),
+        }
+    }
+
+    // every provided user has to exist
+    let existing_users = user_collection
+        .count_documents(doc! { "_id": { "$in": &users_id }, "deleted_at": null }, None)
+        .await
+        .unwrap();
+
+    if existing_users != users_id.len() as u64 {
+        return Err(Status::NotFound.into());
+    }
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+    let team = match add_users(&db, &mut session, team_id, if_match, users_id).await? {
+        Some(team) => team,
+        None => {
+            let exists = is_active(&db, &mut session, "teams", team_id).await?;
+            return Err(if_match.failed_write_status(exists).into());
+        }
+    };
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(team.into())))
+}
+
+#[rocket::delete("/<team_id>/members/<user_id>")]
+pub async fn remove_member(
+    db_pool: &State<Pool>,
+    team_id: String,
+    user_id: String,
+    if_match: IfMatch,
+) -> Result<Response<TeamView>, Status> {
+    let collection = get_collection::<Team>(db_pool, "teams").await;
 
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+    let user_id = match ObjectId::parse_str(user_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let team = match collection
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(team) => team,
+        None => return Err(Status::NotFound),
+    };
+
+    // the ownership has to be transferred before the owner leaves the team
+    if team.owner == Some(user_id) {
+        return Err(Status::Conflict);
+    }
+
+    let opts = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+
+    let member = doc! {
+        "_id": team_id,
+        "users": user_id,
+        "owner": { "$ne": user_id },
+        "deleted_at": null
+    };
+    let mut filter = member.clone();
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! { "$pull": { "users": user_id }, "$inc": { "version": 1 } },
+            opts,
+        )
+        .await
+        .unwrap();
+
+    match result {
+        Some(team) => Ok(Response::Success(Json(team.into()))),
+        None => {
+            let exists = collection.find_one(member, None).await.unwrap().is_some();
+            Err(if_match.failed_write_status(exists))
+        }
+    }
+}
+
+#[rocket::put("/<team_id>/owner", format = "json", data = "<owner>")]
+pub async fn transfer_ownership(
+    db_pool: &State<Pool>,
+    team_id: String,
+    if_match: IfMatch,
+    owner: Json<OwnerRequestBody>,
+) -> Result<Response<TeamView>, ApiError> {
+    validate(&owner.0)?;
+
+    let collection = get_collection::<Team>(db_pool, "teams").await;
+
+    let team_id = match ObjectId::parse_str(team_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+    let user_id = match ObjectId::parse_str(&owner.0.user_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let opts = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+
+    // only active members of the team can become its owner, the trashed users
+    // stay in the team until they're purged
+    let user_collection = get_collection::<User>(db_pool, "users").await;
+    check_reference(&user_collection, user_id).await?;
+
+    let member = doc! { "_id": team_id, "users": user_id, "deleted_at": null };
+    let mut filter = member.clone();
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! { "$set": { "owner": user_id }, "$inc": { "version": 1 } },
+            opts,
+        )
+        .await
+        .unwrap();
+
+    match result {
+        Some(team) => Ok(Response::Success(Json(team.into()))),
+        None => {
+            let exists = collection.find_one(member, None).await.unwrap().is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::config::Pool;
+use crate::utils::{
+    db::{commit_transaction, start_transaction},
+    integrity,
+    responders::Response,
+};
+use chrono::{Duration, Utc};
+use rocket::{http::Status, serde::json::Json, State};
+use std::collections::BTreeMap;
+
+/// Days a deleted entity stays in the trash when no other amount is provided
+pub const DEFAULT_PURGE_AFTER_DAYS: i64 = 30;
+
+const MILLISECONDS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Permanently deletes the entities that have been in the trash for more than `days`
+#[rocket::delete("/?<days>")]
+pub async fn purge(
+    db_pool: &State<Pool>,
+    days: Option<i64>,
+) -> Result<Response<BTreeMap<String, u64>>, Status> {
+    let days = days.unwrap_or(DEFAULT_PURGE_AFTER_DAYS);
+
+    if days < 0 {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    // the date has to fit in the ones chrono supports
+    let deleted_before = match days
+        .checked_mul(MILLISECONDS_PER_DAY)
+        .and_then(|ms| Utc::now().checked_sub_signed(Duration::milliseconds(ms)))
+    {
+        Some(deleted_before) => deleted_before.timestamp_millis(),
+        None => return Err(Status::UnprocessableEntity),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+    let report = integrity::purge(&db, &mut session, deleted_before).await?;
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(report)))
+}
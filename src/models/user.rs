@@ -1,21 +1,60 @@
-use crate::utils::db::get_collection;
+use crate::utils::db::{
+    commit_transaction, get_collection, is_duplicate_key, parse_id, start_transaction,
+};
+use crate::utils::integrity::{
+    find_blockers, is_active, is_trashed, restore_many, restore_one, soft_delete_many,
+    soft_delete_one,
+};
+use crate::utils::concurrency::{ETag, IfMatch};
+use crate::utils::pagination::{paginate, Page, PageOptions};
+use crate::utils::patch::patch_one;
+use crate::utils::token;
+use crate::utils::validation::{not_blank, validate};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use mongodb::bson::oid::ObjectId;
 use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
-use mongodb::{self, bson::doc};
+use mongodb::{self, bson::doc, bson::Document};
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::State;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+use std::collections::HashMap;
 
 use crate::config::Pool;
-use crate::utils::responders::Response;
+use crate::mailer::{Mail, SharedMailer};
+use crate::utils::responders::{ApiError, Response};
+
+use super::{
+    meeting::Meeting,
+    notification::NotificationPreferences,
+    user_time::{SegmentMarker, UserTime},
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    id: Option<ObjectId>,
-    name: String,
-    email: String,
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) name: String,
+    pub(crate) email: String,
+    /// Secret of the calendar feeds of the user, none until one is requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) calendar_token: Option<String>,
+    /// What the user wants to be notified about and how
+    #[serde(default)]
+    pub(crate) notifications: NotificationPreferences,
+    /// Date and time when the user was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the user
+    #[serde(default)]
+    pub(crate) version: i64,
 }
 
 impl User {
@@ -24,8 +63,84 @@ impl User {
     }
 }
 
-#[derive(Debug)]
-enum UserError {}
+/// Emails are stored trimmed and in lowercase, so they are found however they are typed
+pub(crate) fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// User as returned by the API
+#[derive(Serialize, Debug, Clone)]
+pub struct UserView {
+    id: String,
+    name: String,
+    email: String,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<User> for UserView {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.unwrap().to_hex(),
+            name: user.name,
+            email: user.email,
+            deleted_at: user.deleted_at,
+            version: user.version,
+        }
+    }
+}
+
+/// Body of the signup
+#[derive(Deserialize, Validate, Debug, Clone)]
+pub struct UserRequestBody {
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    name: String,
+    #[validate(email, length(max = 254))]
+    email: String,
+}
+
+impl From<UserRequestBody> for User {
+    fn from(body: UserRequestBody) -> Self {
+        Self {
+            id: None,
+            name: body.name,
+            email: normalize_email(&body.email),
+            calendar_token: None,
+            notifications: NotificationPreferences::default(),
+            deleted_at: None,
+            version: 0,
+        }
+    }
+}
+
+/// Body of the update of a user, the email can only be changed with a PATCH
+#[derive(Deserialize, Validate, Debug, Clone)]
+pub struct UserUpdateRequestBody {
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    name: String,
+}
+
+/// Speaking turn of a user in a meeting, with the meeting info resolved
+#[derive(Serialize, Debug, Clone)]
+pub struct UserMeetingTime {
+    /// Id of the UserTime document
+    user_time_id: String,
+    /// Meeting Id
+    meeting_id: String,
+    /// Id of the Meeting Configuration of the meeting
+    config_id: Option<String>,
+    /// Date and time when the meeting started (none if the meeting doesn't exist anymore)
+    #[serde(with = "ts_milliseconds_option")]
+    date_utc: Option<DateTime<Utc>>,
+    /// Date and time when the user started speaking
+    #[serde(with = "ts_milliseconds")]
+    start_utc: DateTime<Utc>,
+    /// Length of the turn in milliseconds
+    duration_ms: i64,
+    /// Whether the turn was an interruption or crosstalk
+    marker: Option<SegmentMarker>,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LoginRequestBody {
@@ -36,23 +151,31 @@ pub struct LoginRequestBody {
 pub async fn login(
     db_pool: &State<Pool>,
     email: Json<LoginRequestBody>,
-) -> Result<Response<User>, Status> {
+) -> Result<Response<UserView>, Status> {
     let collection = get_collection::<User>(db_pool, "users").await;
 
     let user = collection
-        .find_one(doc! {"email": email.0.email}, None)
+        .find_one(
+            doc! {"email": email.0.email.as_deref().map(normalize_email), "deleted_at": null},
+            None,
+        )
         .await
         .unwrap();
 
     match user {
-        Some(user) => Ok(Response::Success(Json(user))),
+        Some(user) => Ok(Response::Success(Json(user.into()))),
         None => Err(Status::Unauthorized),
     }
 }
 
 #[rocket::post("/signup", format = "json", data = "<user>")]
-pub async fn signup(db_pool: &State<Pool>, user: Json<User>) -> Result<Response<User>, Status> {
-    let mut new_user = user.0.clone();
+pub async fn signup(
+    db_pool: &State<Pool>,
+    user: Json<UserRequestBody>,
+) -> Result<Response<UserView>, ApiError> {
+    validate(&user.0)?;
+
+    let mut new_user = User::from(user.0);
     let collection = get_collection::<User>(db_pool, "users").await;
 
     // check if the email is already registered
@@ -63,18 +186,26 @@ pub async fn signup(db_pool: &State<Pool>, user: Json<User>) -> Result<Response<
         .is_some();
 
     if registered {
-        return Err(Status::Conflict);
+        return Err(Status::Conflict.into());
     }
 
-    let result = collection.insert_one(&new_user, None).await.unwrap();
+    // the unique index on the email catches concurrent signups
+    let result = match collection.insert_one(&new_user, None).await {
+        Ok(result) => result,
+        Err(error) if is_duplicate_key(&error) => return Err(Status::Conflict.into()),
+        Err(error) => {
+            eprintln!("[INSERT][USER] ~ {}", error);
+            return Err(Status::InternalServerError.into());
+        }
+    };
 
     new_user.id = Some(result.inserted_id.as_object_id().unwrap());
 
-    Ok(Response::Created(Json(new_user)))
+    Ok(Response::Created(Json(new_user.into())))
 }
 
 #[rocket::get("/<user_id>", format = "json")]
-pub async fn get(db_pool: &State<Pool>, user_id: String) -> Result<Response<User>, Status> {
+pub async fn get(db_pool: &State<Pool>, user_id: String) -> Result<Response<UserView>, Status> {
     let collection = get_collection::<User>(db_pool, "users").await;
 
     let user_id = match ObjectId::parse_str(user_id) {
@@ -83,12 +214,15 @@ pub async fn get(db_pool: &State<Pool>, user_id: String) -> Result<Response<User
     };
 
     let user = collection
-        .find_one(doc! { "_id": user_id }, None)
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
         .await
         .unwrap();
 
     match user {
-        Some(user) => Ok(Response::Success(Json(user))),
+        Some(user) => {
+            let etag = ETag(user.version);
+            Ok(Response::Tagged(Json(user.into()), etag))
+        }
         None => Err(Status::NotFound),
     }
 }
@@ -97,50 +231,340 @@ pub async fn get(db_pool: &State<Pool>, user_id: String) -> Result<Response<User
 pub async fn update(
     db_pool: &State<Pool>,
     user_id: String,
-    user: Json<User>,
-) -> Result<Response<User>, Status> {
+    if_match: IfMatch,
+    user: Json<UserUpdateRequestBody>,
+) -> Result<Response<UserView>, ApiError> {
+    validate(&user.0)?;
+
     let collection = get_collection::<User>(db_pool, "users").await;
 
     let user_id = match ObjectId::parse_str(user_id) {
         Ok(user_id) => user_id,
-        Err(_) => return Err(Status::UnprocessableEntity),
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
     };
 
     let opts = FindOneAndUpdateOptions::builder()
         .return_document(Some(ReturnDocument::After))
         .build();
 
+    let mut filter = doc! { "_id": user_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
     let result = collection
         .find_one_and_update(
-            doc! { "_id": user_id },
-            doc! { "$set": { "name": user.0.name } },
+            filter,
+            doc! { "$set": { "name": user.0.name }, "$inc": { "version": 1 } },
             opts,
         )
         .await
         .unwrap();
 
     match result {
-        Some(new_user) => Ok(Response::Success(Json(new_user))),
-        None => Err(Status::NotFound),
+        Some(new_user) => {
+            let etag = ETag(new_user.version);
+            Ok(Response::Tagged(Json(new_user.into()), etag))
+        }
+        None => {
+            let exists = collection
+                .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists).into())
+        }
     }
 }
 
+/// Fields of a user that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    #[validate(length(min = 1, max = 100), custom = "not_blank")]
+    name: Option<String>,
+    #[validate(email, length(max = 254))]
+    email: Option<String>,
+}
+
+/// Partial update of a user, only the fields sent are changed
+#[rocket::patch("/<user_id>", format = "json", data = "<user>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    user_id: String,
+    if_match: IfMatch,
+    user: Json<UserPatch>,
+) -> Result<Response<UserView>, ApiError> {
+    validate(&user.0)?;
+
+    let collection = get_collection::<User>(db_pool, "users").await;
+    let user_id = parse_id(&user_id)?;
+
+    let mut changes = Document::new();
+    if let Some(name) = user.0.name {
+        changes.insert("name", name);
+    }
+    if let Some(email) = user.0.email {
+        changes.insert("email", normalize_email(&email));
+    }
+
+    // a duplicated email is rejected by the unique index with a 409
+    let user = patch_one(&collection, user_id, if_match, changes).await?;
+
+    let etag = ETag(user.version);
+    Ok(Response::Tagged(Json(user.into()), etag))
+}
+
 #[rocket::delete("/<user_id>")]
-pub async fn delete(db_pool: &State<Pool>, user_id: String) -> Result<Response<User>, Status> {
+pub async fn delete(
+    db_pool: &State<Pool>,
+    user_id: String,
+    if_match: IfMatch,
+) -> Result<Response<UserView>, ApiError> {
+    let user_id = match ObjectId::parse_str(user_id) {
+        Ok(user_id) => user_id,
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    // the ownership of the user's teams has to be transferred first
+    let restrictions = [("teams", doc! { "owner": user_id })];
+    if let Some(blockers) = find_blockers(&db, &mut session, &restrictions).await? {
+        return Err(ApiError::Blocked(Json(blockers)));
+    }
+
+    let deleted_at = Utc::now().timestamp_millis();
+
+    let result =
+        soft_delete_one::<User>(&db, &mut session, "users", user_id, if_match.0, deleted_at)
+            .await?;
+
+    let user = match result {
+        Some(user) => user,
+        None => {
+            let exists = is_active(&db, &mut session, "users", user_id).await?;
+            return Err(if_match.failed_write_status(exists).into());
+        }
+    };
+
+    // the user stays in the teams until it's purged, in case it's restored
+    soft_delete_many(
+        &db,
+        &mut session,
+        "user_times",
+        doc! { "user_id": user_id },
+        deleted_at,
+    )
+    .await?;
+    soft_delete_many(
+        &db,
+        &mut session,
+        "absences",
+        doc! { "user_id": user_id },
+        deleted_at,
+    )
+    .await?;
+    soft_delete_many(
+        &db,
+        &mut session,
+        "attendances",
+        doc! { "user_id": user_id },
+        deleted_at,
+    )
+    .await?;
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(user.into())))
+}
+
+#[rocket::put("/<user_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    user_id: String,
+    if_match: IfMatch,
+) -> Result<Response<UserView>, Status> {
+    let user_id = match ObjectId::parse_str(user_id) {
+        Ok(user_id) => user_id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored = restore_one::<User>(&db, &mut session, "users", user_id, if_match.0).await?;
+    let mut user = match restored {
+        Some(user) => user,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "users", user_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // times, absences and attendance deleted together with the user
+    let deleted_at = user.deleted_at.take().unwrap().timestamp_millis();
+    user.version += 1;
+    restore_many(
+        &db,
+        &mut session,
+        "user_times",
+        doc! { "user_id": user_id },
+        deleted_at,
+    )
+    .await?;
+    restore_many(
+        &db,
+        &mut session,
+        "absences",
+        doc! { "user_id": user_id },
+        deleted_at,
+    )
+    .await?;
+    restore_many(
+        &db,
+        &mut session,
+        "attendances",
+        doc! { "user_id": user_id },
+        deleted_at,
+    )
+    .await?;
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(user.into())))
+}
+
+#[rocket::get("/<user_id>/user_times?<page>&<limit>")]
+pub async fn get_user_times(
+    db_pool: &State<Pool>,
+    user_id: String,
+    page: Option<u64>,
+    limit: Option<u64>,
+) -> Result<Response<Page<UserMeetingTime>>, Status> {
     let collection = get_collection::<User>(db_pool, "users").await;
+    let user_time_collection = get_collection::<UserTime>(db_pool, "user_times").await;
+    let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
 
     let user_id = match ObjectId::parse_str(user_id) {
         Ok(user_id) => user_id,
         Err(_) => return Err(Status::UnprocessableEntity),
     };
 
-    let result = collection
-        .find_one_and_delete(doc! { "_id": user_id }, None)
+    let user_exists = collection
+        .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
         .await
-        .unwrap();
+        .unwrap()
+        .is_some();
 
-    match result {
-        Some(user) => Ok(Response::Success(Json(user))),
-        None => Err(Status::Conflict),
+    if !user_exists {
+        return Err(Status::NotFound);
     }
+
+    // newest turns first
+    let opts = PageOptions::new(page, limit, None, &["-start_utc"])?;
+    let user_times = paginate(
+        &user_time_collection,
+        doc! { "user_id": user_id, "deleted_at": null },
+        &opts,
+    ).await?;
+
+    // get the meetings of the page in a single query
+    let meetings_id: Vec<ObjectId> = user_times
+        .items()
+        .iter()
+        .filter_map(|t| t.meeting_id)
+        .collect();
+    let meetings: HashMap<ObjectId, Meeting> = meeting_collection
+        .find(
+            doc! { "_id": { "$in": meetings_id }, "deleted_at": null },
+            None,
+        )
+        .await
+        .unwrap()
+        .try_collect::<Vec<Meeting>>()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|meeting| (meeting.id.unwrap(), meeting))
+        .collect();
+
+    let result = user_times.map(|user_time| {
+        let meeting_id = user_time.meeting_id.unwrap();
+        let meeting = meetings.get(&meeting_id);
+        UserMeetingTime {
+            user_time_id: user_time.id.unwrap().to_hex(),
+            meeting_id: meeting_id.to_hex(),
+            config_id: meeting.and_then(|m| m.config_id).map(|id| id.to_hex()),
+            date_utc: meeting.map(|m| m.date_utc),
+            start_utc: user_time.start_utc,
+            duration_ms: user_time.duration_ms(),
+            marker: user_time.marker,
+        }
+    });
+
+    Ok(Response::Success(Json(result)))
+}
+
+/// Generates a new calendar token for the user, the feeds of the previous one
+/// stop working. Whoever knows the token reads the meetings of the user, so it's
+/// only sent to the email of the user and never returned.
+#[rocket::post("/<user_id>/calendar_token")]
+pub async fn rotate_calendar_token(
+    db_pool: &State<Pool>,
+    mailer: &State<SharedMailer>,
+    user_id: String,
+    if_match: IfMatch,
+) -> Result<Response<UserView>, Status> {
+    let collection = get_collection::<User>(db_pool, "users").await;
+    let user_id = parse_id(&user_id)?;
+    let token = token::generate();
+
+    let mut filter = doc! { "_id": user_id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let result = collection
+        .find_one_and_update(
+            filter,
+            doc! { "$set": { "calendar_token": &token }, "$inc": { "version": 1 } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await;
+
+    let user = match result {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            let exists = collection
+                .find_one(doc! { "_id": user_id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            return Err(if_match.failed_write_status(exists));
+        }
+        Err(error) => {
+            eprintln!("[UPDATE][USER] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let app_url = std::env::var("APP_URL").unwrap_or_default();
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Your calendar feed".to_owned(),
+        body: format!(
+            "Subscribe to this address in your calendar app to see the meetings of your \
+             teams: {}/api/calendar/{}/meetings.ics\n\n\
+             Don't share it, anyone with the address sees your meetings. The addresses \
+             sent before stop working.",
+            app_url, token
+        ),
+    };
+
+    // the previous token is revoked anyway, a new one can be requested
+    if let Err(error) = mailer.send(&mail).await {
+        eprintln!("[MAILER][CALENDAR_TOKEN] ~ {}", error);
+        return Err(Status::InternalServerError);
+    }
+
+    let etag = ETag(user.version);
+    Ok(Response::Tagged(Json(user.into()), etag))
 }
@@ -1,77 +1,175 @@
 use crate::{
     config::Pool,
-    models::{meeting::Meeting, user::User},
-    utils::{db::get_collection, responders::Response},
+    models::{
+        meeting::{meeting_event, Meeting, MeetingStatus},
+        user::User,
+        webhook::WebhookEvent,
+    },
+    utils::{
+        concurrency::{ETag, IfMatch},
+        db::{commit_transaction, get_collection, parse_id, start_transaction},
+        integrity::{is_active, is_trashed, restore_one, soft_delete_one},
+        pagination::{paginate, Page, PageOptions},
+        patch::{check_reference, nullable, patch_one},
+        validation::{object_id, validate, validate_period},
+        responders::{ApiError, Response},
+    },
+    webhooks,
 };
 use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
-use bson::{doc, oid::ObjectId, to_bson};
+use bson::{doc, oid::ObjectId, to_bson, Document};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Utc};
 use rocket::{http::Status, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[allow(dead_code)]
+/// Special kind of speaking segment, a regular turn has none
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SegmentMarker {
+    /// The user cut off whoever was speaking
+    INTERRUPTION,
+    /// The user spoke at the same time as someone else
+    CROSSTALK,
+}
+
+/// Speaking turn of a user in a meeting, a user can speak several times per meeting
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserTime {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    id: Option<ObjectId>,
+    pub(crate) id: Option<ObjectId>,
     /// User Id
-    user_id: Option<ObjectId>,
-    /// String version of User Id
-    #[serde(skip_serializing)]
-    user_id_str: Option<String>,
+    pub(crate) user_id: Option<ObjectId>,
     /// Meeting Id
-    meeting_id: Option<ObjectId>,
-    /// String version of Meeting Id
-    #[serde(skip_serializing)]
-    meeting_id_str: Option<String>,
-    /// Time in seconds (max: 65000 [8h])
-    time: u16,
+    pub(crate) meeting_id: Option<ObjectId>,
+    /// Date and time when the user started speaking
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) start_utc: DateTime<Utc>,
+    /// Date and time when the user stopped speaking
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) end_utc: DateTime<Utc>,
+    /// Whether the turn was an interruption or crosstalk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) marker: Option<SegmentMarker>,
+    /// Date and time when the time was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the time
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+impl UserTime {
+    /// Length of the turn in milliseconds
+    pub(crate) fn duration_ms(&self) -> i64 {
+        (self.end_utc - self.start_utc).num_milliseconds()
+    }
+}
+
+/// User time as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct UserTimeView {
+    id: String,
+    user_id: Option<String>,
+    meeting_id: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    start_utc: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end_utc: DateTime<Utc>,
+    /// Length of the turn in milliseconds
+    duration_ms: i64,
+    marker: Option<SegmentMarker>,
+    #[serde(with = "ts_milliseconds_option", skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<UserTime> for UserTimeView {
+    fn from(user_time: UserTime) -> Self {
+        Self {
+            id: user_time.id.unwrap().to_hex(),
+            user_id: user_time.user_id.map(|id| id.to_hex()),
+            meeting_id: user_time.meeting_id.map(|id| id.to_hex()),
+            duration_ms: user_time.duration_ms(),
+            start_utc: user_time.start_utc,
+            end_utc: user_time.end_utc,
+            marker: user_time.marker,
+            deleted_at: user_time.deleted_at,
+            version: user_time.version,
+        }
+    }
+}
+
+/// Body of the creation and the update of a user time
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct UserTimeRequestBody {
+    #[validate(custom = "object_id")]
+    user_id: String,
+    #[validate(custom = "object_id")]
+    meeting_id: String,
+    /// Date and time when the user started speaking (ms since epoch)
+    #[serde(with = "ts_milliseconds")]
+    start_utc: DateTime<Utc>,
+    /// Date and time when the user stopped speaking (ms since epoch), after `start_utc`
+    #[serde(with = "ts_milliseconds")]
+    end_utc: DateTime<Utc>,
+    /// Whether the turn was an interruption or crosstalk
+    #[serde(default)]
+    marker: Option<SegmentMarker>,
+}
+
+impl TryFrom<UserTimeRequestBody> for UserTime {
+    type Error = Status;
+
+    fn try_from(body: UserTimeRequestBody) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: None,
+            user_id: Some(parse_id(&body.user_id)?),
+            meeting_id: Some(parse_id(&body.meeting_id)?),
+            start_utc: body.start_utc,
+            end_utc: body.end_utc,
+            marker: body.marker,
+            deleted_at: None,
+            version: 0,
+        })
+    }
 }
 
 #[rocket::post("/", format = "json", data = "<user_time>")]
 pub async fn create(
     db_pool: &State<Pool>,
-    user_time: Json<UserTime>,
-) -> Result<Response<UserTime>, Status> {
+    user_time: Json<UserTimeRequestBody>,
+) -> Result<Response<UserTimeView>, ApiError> {
+    validate(&user_time.0)?;
+    validate_period(user_time.0.start_utc, user_time.0.end_utc, "end_utc")?;
+
     let collection = get_collection::<UserTime>(db_pool, "user_times").await;
     let user_collection = get_collection::<User>(db_pool, "users").await;
     let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
 
-    let mut new_user_time = user_time.0.clone();
-    let user_id_str: &str = match &new_user_time.user_id_str {
-        Some(id) => id,
-        None => return Err(Status::UnprocessableEntity),
-    };
-    let meeting_id_str: &str = match &new_user_time.meeting_id_str {
-        Some(id) => id,
-        None => return Err(Status::UnprocessableEntity),
-    };
-
-    // parse ids to objectids
-    new_user_time.user_id = match ObjectId::parse_str(user_id_str) {
-        Ok(id) => Some(id),
-        Err(_) => return Err(Status::UnprocessableEntity),
-    };
-    new_user_time.meeting_id = match ObjectId::parse_str(meeting_id_str) {
-        Ok(id) => Some(id),
-        Err(_) => return Err(Status::UnprocessableEntity),
-    };
+    let mut new_user_time = UserTime::try_from(user_time.0)?;
 
     // Check if both user and meeting exist
-    let meeting_exists = meeting_collection
+    let meeting = meeting_collection
         .find_one(
             doc! {
-                "_id": &new_user_time.meeting_id
+                "_id": &new_user_time.meeting_id,
+                "deleted_at": null
             },
             None,
         )
         .await
-        .unwrap()
-        .is_some();
+        .unwrap();
 
     let user_exists = user_collection
         .find_one(
             doc! {
-                "_id": &new_user_time.user_id
+                "_id": &new_user_time.user_id,
+                "deleted_at": null
             },
             None,
         )
@@ -80,17 +178,32 @@ pub async fn create(
         .is_some();
 
     // if they both exist, create the new user_time document
-    if meeting_exists && user_exists {
-        match collection.insert_one(&new_user_time, None).await {
+    match meeting {
+        Some(meeting) if user_exists => match collection.insert_one(&new_user_time, None).await {
             Ok(result) => {
                 let id = result.inserted_id.as_object_id().unwrap();
                 new_user_time.id = Some(id);
-                Ok(Response::Created(Json(new_user_time)))
+
+                // the first turn recorded in a scheduled meeting starts its session
+                let db = db_pool.get().await.unwrap().default_database().unwrap();
+                if meeting.status == MeetingStatus::SCHEDULED {
+                    meeting_event(&db, &meeting, WebhookEvent::MEETING_STARTED).await;
+                }
+                let view = UserTimeView::from(new_user_time);
+                webhooks::emit_for_meeting(
+                    &db,
+                    &meeting,
+                    WebhookEvent::USER_TIME_RECORDED,
+                    None,
+                    &view,
+                )
+                .await;
+
+                Ok(Response::Created(Json(view)))
             }
-            Err(_) => Err(Status::InternalServerError),
-        }
-    } else {
-        Err(Status::NotFound)
+            Err(_) => Err(Status::InternalServerError.into()),
+        },
+        _ => Err(Status::NotFound.into()),
     }
 }
 
@@ -98,7 +211,7 @@ pub async fn create(
 pub async fn get(
     db_pool: &State<Pool>,
     user_time_id: String,
-) -> Result<Response<UserTime>, Status> {
+) -> Result<Response<UserTimeView>, Status> {
     let collection = get_collection::<UserTime>(db_pool, "user_times").await;
 
     let user_time_id = match ObjectId::parse_str(user_time_id) {
@@ -109,7 +222,8 @@ pub async fn get(
     let user_time = collection
         .find_one(
             doc! {
-                "_id": user_time_id
+                "_id": user_time_id,
+                "deleted_at": null
             },
             None,
         )
@@ -117,7 +231,10 @@ pub async fn get(
         .unwrap();
 
     match user_time {
-        Some(user_time) => Ok(Response::Success(Json(user_time))),
+        Some(user_time) => {
+            let etag = ETag(user_time.version);
+            Ok(Response::Tagged(Json(user_time.into()), etag))
+        }
         None => Err(Status::NotFound),
     }
 }
@@ -126,35 +243,29 @@ pub async fn get(
 pub async fn update(
     db_pool: &State<Pool>,
     user_time_id: String,
-    user_time: Json<UserTime>,
-) -> Result<Response<UserTime>, Status> {
+    if_match: IfMatch,
+    user_time: Json<UserTimeRequestBody>,
+) -> Result<Response<UserTimeView>, ApiError> {
+    validate(&user_time.0)?;
+    validate_period(user_time.0.start_utc, user_time.0.end_utc, "end_utc")?;
+
     let collection = get_collection::<UserTime>(db_pool, "user_times").await;
     let user_collection = get_collection::<User>(db_pool, "users").await;
     let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
 
-    let mut new_user_time = user_time.0.clone();
+    let new_user_time = UserTime::try_from(user_time.0)?;
 
     // 1. parse user_time_id and check if it exists
     let user_time_id = match ObjectId::parse_str(user_time_id) {
         Ok(id) => id,
-        Err(_) => return Err(Status::UnprocessableEntity),
+        Err(_) => return Err(Status::UnprocessableEntity.into()),
     };
 
-    let exists_user_time = collection.find_one(doc! { "_id": user_time_id }, None).await.unwrap().is_some();
+    let exists_user_time = collection.find_one(doc! { "_id": user_time_id, "deleted_at": null }, None).await.unwrap().is_some();
 
     if exists_user_time {
-        // parse the user and meeting id to objectd's
-        new_user_time.user_id = match ObjectId::parse_str(user_time.0.user_id_str.unwrap()) {
-            Ok(id) => Some(id),
-            Err(_) => return Err(Status::UnprocessableEntity),
-        };
-        new_user_time.meeting_id = match ObjectId::parse_str(user_time.0.meeting_id_str.unwrap()) {
-            Ok(id) => Some(id),
-            Err(_) => return Err(Status::UnprocessableEntity),
-        };
-
-        let user_exists = user_collection.find_one(doc! { "_id": new_user_time.user_id.unwrap() }, None).await.unwrap().is_some();
-        let meeting_exists = meeting_collection.find_one(doc! { "_id": new_user_time.meeting_id.unwrap() }, None).await.unwrap().is_some();
+        let user_exists = user_collection.find_one(doc! { "_id": new_user_time.user_id.unwrap(), "deleted_at": null }, None).await.unwrap().is_some();
+        let meeting_exists = meeting_collection.find_one(doc! { "_id": new_user_time.meeting_id.unwrap(), "deleted_at": null }, None).await.unwrap().is_some();
 
         if user_exists && meeting_exists {
             // if both exists, update
@@ -162,51 +273,225 @@ pub async fn update(
                 .return_document(Some(ReturnDocument::After))
                 .build();
 
+            let mut filter = doc! { "_id": user_time_id, "deleted_at": null };
+            if_match.apply(&mut filter);
+
             let result = collection.find_one_and_update(
-                    doc! {
-                        "_id": user_time_id
-                    },
+                    filter,
                     doc! {
                         "$set": {
                             "user_id": new_user_time.user_id.unwrap(),
                             "meeting_id": new_user_time.meeting_id.unwrap(),
-                            "time": to_bson(&new_user_time.time).unwrap()
-                        }
+                            "start_utc": new_user_time.start_utc.timestamp_millis(),
+                            "end_utc": new_user_time.end_utc.timestamp_millis(),
+                            "marker": to_bson(&new_user_time.marker).unwrap()
+                        },
+                        "$inc": { "version": 1 }
                     },
                     opts
             ).await.unwrap();
 
             match result {
-                Some(res) => Ok(Response::Success(Json(res))),
-                None => Err(Status::NotFound),
+                Some(res) => {
+                    let etag = ETag(res.version);
+                    Ok(Response::Tagged(Json(res.into()), etag))
+                }
+                // it existed before the update, so someone else changed it in the meantime
+                None => Err(if_match.failed_write_status(true).into()),
             }
 
         } else {
-            Err(Status::NotFound)
+            Err(Status::NotFound.into())
         }
     } else {
-        Err(Status::NotFound)
+        Err(Status::NotFound.into())
     }
 
 }
 
-#[rocket::delete("/<user_time_id>")]
-pub async fn delete(db_pool: &State<Pool>, user_time_id: String) -> Result<Response<UserTime>, Status> {
+/// Fields of a user time that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UserTimePatch {
+    #[validate(custom = "object_id")]
+    user_id: Option<String>,
+    #[validate(custom = "object_id")]
+    meeting_id: Option<String>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    start_utc: Option<DateTime<Utc>>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    end_utc: Option<DateTime<Utc>>,
+    /// `null` turns the segment back into a regular turn
+    #[serde(default, deserialize_with = "nullable")]
+    marker: Option<Option<SegmentMarker>>,
+}
+
+/// Partial update of a user time, only the fields sent are changed
+#[rocket::patch("/<user_time_id>", format = "json", data = "<user_time>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    user_time_id: String,
+    if_match: IfMatch,
+    user_time: Json<UserTimePatch>,
+) -> Result<Response<UserTimeView>, ApiError> {
+    validate(&user_time.0)?;
+
     let collection = get_collection::<UserTime>(db_pool, "user_times").await;
+    let user_time_id = parse_id(&user_time_id)?;
+    let fields = user_time.0;
+
+    // the new start or end has to be checked against the one that is kept
+    if fields.start_utc.is_some() || fields.end_utc.is_some() {
+        let current = match collection
+            .find_one(doc! { "_id": user_time_id, "deleted_at": null }, None)
+            .await
+            .unwrap()
+        {
+            Some(user_time) => user_time,
+            None => return Err(Status::NotFound.into()),
+        };
 
+        validate_period(
+            fields.start_utc.unwrap_or(current.start_utc),
+            fields.end_utc.unwrap_or(current.end_utc),
+            "end_utc",
+        )?;
+    }
+
+    let mut changes = Document::new();
+    if let Some(user_id) = fields.user_id {
+        let user_id = parse_id(&user_id)?;
+        let user_collection = get_collection::<User>(db_pool, "users").await;
+        check_reference(&user_collection, user_id).await?;
+        changes.insert("user_id", user_id);
+    }
+    if let Some(meeting_id) = fields.meeting_id {
+        let meeting_id = parse_id(&meeting_id)?;
+        let meeting_collection = get_collection::<Meeting>(db_pool, "meetings").await;
+        check_reference(&meeting_collection, meeting_id).await?;
+        changes.insert("meeting_id", meeting_id);
+    }
+    if let Some(start_utc) = fields.start_utc {
+        changes.insert("start_utc", start_utc.timestamp_millis());
+    }
+    if let Some(end_utc) = fields.end_utc {
+        changes.insert("end_utc", end_utc.timestamp_millis());
+    }
+    if let Some(marker) = fields.marker {
+        changes.insert("marker", to_bson(&marker).unwrap());
+    }
+
+    let user_time = patch_one(&collection, user_time_id, if_match, changes).await?;
+
+    let etag = ETag(user_time.version);
+    Ok(Response::Tagged(Json(user_time.into()), etag))
+}
+
+#[rocket::delete("/<user_time_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    user_time_id: String,
+    if_match: IfMatch,
+) -> Result<Response<UserTimeView>, Status> {
     let user_time_id = match ObjectId::parse_str(user_time_id) {
         Ok(id) => id,
         Err(_) => return Err(Status::UnprocessableEntity)
     };
 
-    let result = collection
-        .find_one_and_delete(doc! { "_id": user_time_id }, None)
-        .await
-        .unwrap();
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let result = soft_delete_one::<UserTime>(
+        &db,
+        &mut session,
+        "user_times",
+        user_time_id,
+        if_match.0,
+        Utc::now().timestamp_millis(),
+    )
+    .await?;
+
+    let user_time = match result {
+        Some(user_time) => user_time,
+        None if if_match.0.is_some()
+            && is_active(&db, &mut session, "user_times", user_time_id).await? =>
+        {
+            return Err(Status::PreconditionFailed)
+        }
+        None => return Err(Status::Conflict),
+    };
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(user_time.into())))
+
+}
+
+#[rocket::put("/<user_time_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    user_time_id: String,
+    if_match: IfMatch,
+) -> Result<Response<UserTimeView>, Status> {
+    let user_time_id = match ObjectId::parse_str(user_time_id) {
+        Ok(id) => id,
+        Err(_) => return Err(Status::UnprocessableEntity),
+    };
+
+    let (db, mut session) = start_transaction(db_pool).await?;
 
-    match result {
-        Some(user_time) => Ok(Response::Success(Json(user_time))),
-        None => Err(Status::Conflict)
+    let restored =
+        restore_one::<UserTime>(&db, &mut session, "user_times", user_time_id, if_match.0).await?;
+    let mut user_time = match restored {
+        Some(user_time) => user_time,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "user_times", user_time_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // the user and the meeting have to be restored first
+    let user_active = is_active(&db, &mut session, "users", user_time.user_id.unwrap()).await?;
+    let meeting_active =
+        is_active(&db, &mut session, "meetings", user_time.meeting_id.unwrap()).await?;
+
+    if !user_active || !meeting_active {
+        return Err(Status::Conflict);
     }
 
+    commit_transaction(&mut session).await?;
+
+    user_time.deleted_at = None;
+    user_time.version += 1;
+    Ok(Response::Success(Json(user_time.into())))
+}
+
+#[rocket::get("/all?<user_id>&<meeting_id>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    user_id: Option<String>,
+    meeting_id: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<UserTimeView>>, Status> {
+    let collection = get_collection::<UserTime>(db_pool, "user_times").await;
+    let opts = PageOptions::new(page, limit, sort, &["start_utc", "_id"])?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(user_id) = user_id {
+        match ObjectId::parse_str(user_id) {
+            Ok(id) => filter.insert("user_id", id),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+    }
+    if let Some(meeting_id) = meeting_id {
+        match ObjectId::parse_str(meeting_id) {
+            Ok(id) => filter.insert("meeting_id", id),
+            Err(_) => return Err(Status::UnprocessableEntity),
+        };
+    }
+
+    let user_times = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(user_times.map(UserTimeView::from))))
 }
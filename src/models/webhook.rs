@@ -0,0 +1,464 @@
+use crate::config::Pool;
+use crate::utils::{
+    concurrency::{ETag, IfMatch},
+    db::{commit_transaction, get_collection, parse_id, start_transaction},
+    integrity::{is_active, is_trashed, restore_one, soft_delete_one},
+    pagination::{paginate, Page, PageOptions},
+    patch::patch_one,
+    responders::{ApiError, Response},
+    token,
+    validation::{invalid_field, object_id, public_url, validate},
+};
+use crate::webhooks;
+use bson::{doc, oid::ObjectId, to_bson, Document};
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option};
+use chrono::{DateTime, Utc};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::team::Team;
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum WebhookEvent {
+    /// The first speaking turn or attendance of a scheduled meeting was recorded
+    MEETING_STARTED,
+    /// The real dates of a meeting were recorded
+    MEETING_FINISHED,
+    /// A speaking turn was recorded
+    USER_TIME_RECORDED,
+    /// A config was created, updated, deleted or restored
+    CONFIG_CHANGED,
+    /// An action item was agreed on in a retro
+    ACTION_ITEM_CREATED,
+    /// Sent by the test route only, it can't be subscribed to
+    PING,
+}
+
+/// Endpoint of a team notified of the events it subscribed to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) team_id: ObjectId,
+    pub(crate) url: String,
+    pub(crate) events: Vec<WebhookEvent>,
+    /// Key of the HMAC signature of the payloads, only returned on creation
+    pub(crate) secret: String,
+    /// Inactive webhooks keep their pending deliveries until they're activated again
+    pub(crate) active: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) created_at: DateTime<Utc>,
+    /// Date and time when the webhook was moved to the trash
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, sent as the ETag of the webhook
+    #[serde(default)]
+    pub(crate) version: i64,
+}
+
+/// Webhook as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct WebhookView {
+    id: String,
+    team_id: String,
+    url: String,
+    events: Vec<WebhookEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    active: bool,
+    #[serde(with = "ts_milliseconds")]
+    created_at: DateTime<Utc>,
+    #[serde(
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    deleted_at: Option<DateTime<Utc>>,
+    version: i64,
+}
+
+impl From<Webhook> for WebhookView {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id.unwrap().to_hex(),
+            team_id: webhook.team_id.to_hex(),
+            url: webhook.url,
+            events: webhook.events,
+            secret: None,
+            active: webhook.active,
+            created_at: webhook.created_at,
+            deleted_at: webhook.deleted_at,
+            version: webhook.version,
+        }
+    }
+}
+
+/// Body of the creation of a webhook
+#[derive(Deserialize, Validate, Clone, Debug)]
+pub struct WebhookRequestBody {
+    #[validate(custom = "object_id")]
+    team_id: String,
+    #[validate(custom = "public_url", length(max = 2048))]
+    url: String,
+    #[validate(length(min = 1))]
+    events: Vec<WebhookEvent>,
+}
+
+/// Fields of a webhook that can be changed with a PATCH, the missing ones are kept
+#[derive(Deserialize, Validate, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookPatch {
+    #[validate(custom = "public_url", length(max = 2048))]
+    url: Option<String>,
+    #[validate(length(min = 1))]
+    events: Option<Vec<WebhookEvent>>,
+    active: Option<bool>,
+}
+
+/// Events without duplicates, `PING` can't be subscribed to
+fn normalize_events(events: Vec<WebhookEvent>) -> Result<Vec<WebhookEvent>, ApiError> {
+    let mut normalized: Vec<WebhookEvent> = vec![];
+    for event in events {
+        if event == WebhookEvent::PING {
+            return Err(invalid_field(
+                "events",
+                "ping",
+                "PING is only sent by the test route",
+            ));
+        }
+        if !normalized.contains(&event) {
+            normalized.push(event);
+        }
+    }
+
+    Ok(normalized)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryStatus {
+    /// Waiting for its first attempt or a retry
+    PENDING,
+    /// Claimed by an instance posting it, due again once `next_attempt_at` passes
+    /// if the attempt was never recorded
+    SENDING,
+    DELIVERED,
+    /// Every attempt failed
+    FAILED,
+}
+
+/// Request made to deliver an event
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryAttempt {
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) at: DateTime<Utc>,
+    pub(crate) duration_ms: i64,
+    /// HTTP status answered, none if the endpoint couldn't be reached. The body
+    /// answered isn't kept.
+    pub(crate) response_status: Option<i32>,
+    pub(crate) error: Option<String>,
+}
+
+/// Event waiting in the outbox to be delivered to a webhook, kept afterwards as
+/// the log of its attempts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) webhook_id: ObjectId,
+    pub(crate) event: WebhookEvent,
+    /// JSON body posted, signed as it is
+    pub(crate) payload: String,
+    /// Key of the events only delivered once per webhook (meeting started/finished)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dedup_key: Option<String>,
+    pub(crate) status: DeliveryStatus,
+    #[serde(default)]
+    pub(crate) attempts: Vec<DeliveryAttempt>,
+    /// When the delivery is due, none once delivered or failed
+    #[serde(
+        default,
+        with = "ts_milliseconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_milliseconds")]
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+/// Delivery as returned by the API
+#[derive(Serialize, Clone, Debug)]
+pub struct WebhookDeliveryView {
+    id: String,
+    webhook_id: String,
+    event: WebhookEvent,
+    payload: String,
+    status: DeliveryStatus,
+    attempts: Vec<DeliveryAttempt>,
+    #[serde(with = "ts_milliseconds_option")]
+    next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_milliseconds")]
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookDelivery> for WebhookDeliveryView {
+    fn from(delivery: WebhookDelivery) -> Self {
+        Self {
+            id: delivery.id.unwrap().to_hex(),
+            webhook_id: delivery.webhook_id.to_hex(),
+            event: delivery.event,
+            payload: delivery.payload,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            next_attempt_at: delivery.next_attempt_at,
+            created_at: delivery.created_at,
+        }
+    }
+}
+
+async fn active_webhook(db_pool: &State<Pool>, webhook_id: ObjectId) -> Result<Webhook, Status> {
+    let collection = get_collection::<Webhook>(db_pool, "webhooks").await;
+
+    match collection
+        .find_one(doc! { "_id": webhook_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+    {
+        Some(webhook) => Ok(webhook),
+        None => Err(Status::NotFound),
+    }
+}
+
+/// Subscribes a team to events, the secret of the signatures is only returned here
+#[rocket::post("/", format = "json", data = "<webhook>")]
+pub async fn create(
+    db_pool: &State<Pool>,
+    webhook: Json<WebhookRequestBody>,
+) -> Result<Response<WebhookView>, ApiError> {
+    validate(&webhook.0)?;
+
+    let collection = get_collection::<Webhook>(db_pool, "webhooks").await;
+    let team_collection = get_collection::<Team>(db_pool, "teams").await;
+
+    let body = webhook.0;
+    let team_id = parse_id(&body.team_id)?;
+    let team_exists = team_collection
+        .find_one(doc! { "_id": team_id, "deleted_at": null }, None)
+        .await
+        .unwrap()
+        .is_some();
+
+    if !team_exists {
+        return Err(Status::NotFound.into());
+    }
+
+    let mut new_webhook = Webhook {
+        id: None,
+        team_id,
+        url: body.url,
+        events: normalize_events(body.events)?,
+        secret: token::generate(),
+        active: true,
+        created_at: Utc::now(),
+        deleted_at: None,
+        version: 0,
+    };
+
+    match collection.insert_one(&new_webhook, None).await {
+        Ok(result) => new_webhook.id = Some(result.inserted_id.as_object_id().unwrap()),
+        Err(error) => {
+            eprintln!("[INSERT][WEBHOOK] ~ {}", error);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    let secret = new_webhook.secret.clone();
+    let mut view = WebhookView::from(new_webhook);
+    view.secret = Some(secret);
+    Ok(Response::Created(Json(view)))
+}
+
+#[rocket::get("/<webhook_id>")]
+pub async fn get(
+    db_pool: &State<Pool>,
+    webhook_id: String,
+) -> Result<Response<WebhookView>, Status> {
+    let webhook = active_webhook(db_pool, parse_id(&webhook_id)?).await?;
+
+    let etag = ETag(webhook.version);
+    Ok(Response::Tagged(Json(webhook.into()), etag))
+}
+
+/// Partial update of a webhook, only the fields sent are changed
+#[rocket::patch("/<webhook_id>", format = "json", data = "<webhook>")]
+pub async fn patch(
+    db_pool: &State<Pool>,
+    webhook_id: String,
+    if_match: IfMatch,
+    webhook: Json<WebhookPatch>,
+) -> Result<Response<WebhookView>, ApiError> {
+    validate(&webhook.0)?;
+
+    let collection = get_collection::<Webhook>(db_pool, "webhooks").await;
+    let webhook_id = parse_id(&webhook_id)?;
+    let fields = webhook.0;
+
+    let mut changes = Document::new();
+    if let Some(url) = fields.url {
+        changes.insert("url", url);
+    }
+    if let Some(events) = fields.events {
+        changes.insert("events", to_bson(&normalize_events(events)?).unwrap());
+    }
+    if let Some(active) = fields.active {
+        changes.insert("active", active);
+    }
+
+    let webhook = patch_one(&collection, webhook_id, if_match, changes).await?;
+
+    let etag = ETag(webhook.version);
+    Ok(Response::Tagged(Json(webhook.into()), etag))
+}
+
+#[rocket::delete("/<webhook_id>")]
+pub async fn delete(
+    db_pool: &State<Pool>,
+    webhook_id: String,
+    if_match: IfMatch,
+) -> Result<Response<WebhookView>, Status> {
+    let webhook_id = parse_id(&webhook_id)?;
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let result = soft_delete_one::<Webhook>(
+        &db,
+        &mut session,
+        "webhooks",
+        webhook_id,
+        if_match.0,
+        Utc::now().timestamp_millis(),
+    )
+    .await?;
+
+    let webhook = match result {
+        Some(webhook) => webhook,
+        None => {
+            let exists = is_active(&db, &mut session, "webhooks", webhook_id).await?;
+            return Err(if_match.failed_write_status(exists));
+        }
+    };
+
+    commit_transaction(&mut session).await?;
+
+    Ok(Response::Success(Json(webhook.into())))
+}
+
+#[rocket::put("/<webhook_id>/restore")]
+pub async fn restore(
+    db_pool: &State<Pool>,
+    webhook_id: String,
+    if_match: IfMatch,
+) -> Result<Response<WebhookView>, Status> {
+    let webhook_id = parse_id(&webhook_id)?;
+
+    let (db, mut session) = start_transaction(db_pool).await?;
+
+    let restored =
+        restore_one::<Webhook>(&db, &mut session, "webhooks", webhook_id, if_match.0).await?;
+    let mut webhook = match restored {
+        Some(webhook) => webhook,
+        None => {
+            let trashed = is_trashed(&db, &mut session, "webhooks", webhook_id).await?;
+            return Err(if_match.failed_write_status(trashed));
+        }
+    };
+
+    // the team has to be restored first
+    if !is_active(&db, &mut session, "teams", webhook.team_id).await? {
+        return Err(Status::Conflict);
+    }
+
+    commit_transaction(&mut session).await?;
+
+    webhook.deleted_at = None;
+    webhook.version += 1;
+    Ok(Response::Success(Json(webhook.into())))
+}
+
+#[rocket::get("/all?<team_id>&<page>&<limit>&<sort>")]
+pub async fn all(
+    db_pool: &State<Pool>,
+    team_id: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<WebhookView>>, Status> {
+    let collection = get_collection::<Webhook>(db_pool, "webhooks").await;
+    let opts = PageOptions::new(page, limit, sort, &["created_at", "url", "_id"])?;
+
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(team_id) = team_id {
+        filter.insert("team_id", parse_id(&team_id)?);
+    }
+
+    let webhooks = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(webhooks.map(WebhookView::from))))
+}
+
+/// Delivery logs of a webhook, newest first by default
+#[rocket::get("/<webhook_id>/deliveries?<status>&<page>&<limit>&<sort>")]
+pub async fn deliveries(
+    db_pool: &State<Pool>,
+    webhook_id: String,
+    status: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    sort: Option<String>,
+) -> Result<Response<Page<WebhookDeliveryView>>, Status> {
+    let collection = get_collection::<WebhookDelivery>(db_pool, "webhook_deliveries").await;
+    let webhook_id = parse_id(&webhook_id)?;
+    active_webhook(db_pool, webhook_id).await?;
+
+    let opts = PageOptions::new(page, limit, sort, &["-created_at", "_id"])?;
+
+    let mut filter = doc! { "webhook_id": webhook_id };
+    match status.as_deref() {
+        Some(status @ ("PENDING" | "SENDING" | "DELIVERED" | "FAILED")) => {
+            filter.insert("status", status);
+        }
+        Some(_) => return Err(Status::UnprocessableEntity),
+        None => {}
+    }
+
+    let deliveries = paginate(&collection, filter, &opts).await?;
+
+    Ok(Response::Success(Json(
+        deliveries.map(WebhookDeliveryView::from),
+    )))
+}
+
+/// Sends a `PING` event to the webhook right away, even if it's inactive, and
+/// returns its delivery. A failed ping is retried like any other delivery.
+#[rocket::post("/<webhook_id>/test")]
+pub async fn test(
+    db_pool: &State<Pool>,
+    webhook_id: String,
+) -> Result<Response<WebhookDeliveryView>, Status> {
+    let webhook = active_webhook(db_pool, parse_id(&webhook_id)?).await?;
+    let db = db_pool.get().await.unwrap().default_database().unwrap();
+
+    let result = webhooks::ping(&db, &webhook).await;
+
+    match result {
+        Ok(delivery) => Ok(Response::Created(Json(delivery.into()))),
+        Err(error) => {
+            eprintln!("[WEBHOOK][TEST] ~ {}", error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+
+use super::{Channel, ChannelError};
+use crate::mailer::{Mail, SharedMailer};
+use crate::models::notification::Notification;
+use crate::models::user::User;
+
+/// Sends the notifications by email, through the mailer of the application
+pub struct EmailChannel {
+    mailer: SharedMailer,
+}
+
+impl EmailChannel {
+    pub fn new(mailer: SharedMailer) -> Self {
+        Self { mailer }
+    }
+}
+
+#[async_trait]
+impl Channel for EmailChannel {
+    async fn deliver(&self, user: &User, notification: &Notification) -> Result<(), ChannelError> {
+        let mail = Mail {
+            to: user.email.clone(),
+            subject: notification.title.clone(),
+            body: notification.body.clone(),
+        };
+
+        self.mailer
+            .send(&mail)
+            .await
+            .map_err(|error| ChannelError(error.to_string()))
+    }
+}
@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use rocket::tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use std::path::PathBuf;
+
+use super::{Channel, ChannelError, ChannelKind};
+use crate::models::notification::Notification;
+use crate::models::user::User;
+
+/// Channel for local development, prints the notifications or appends them to a file
+pub struct LogChannel {
+    /// Channel being replaced, written in every entry
+    kind: ChannelKind,
+    /// File where the notifications are appended (`NOTIFICATION_LOG_FILE`), stdout
+    /// if not provided
+    file: Option<PathBuf>,
+}
+
+impl LogChannel {
+    pub fn from_env(kind: ChannelKind) -> Self {
+        Self {
+            kind,
+            file: std::env::var("NOTIFICATION_LOG_FILE")
+                .ok()
+                .map(PathBuf::from),
+        }
+    }
+}
+
+#[async_trait]
+impl Channel for LogChannel {
+    async fn deliver(&self, user: &User, notification: &Notification) -> Result<(), ChannelError> {
+        let entry = format!(
+            "{:?} to {} ({:?})\n{}\n\n{}\n---\n",
+            self.kind, user.email, notification.kind, notification.title, notification.body
+        );
+
+        match &self.file {
+            Some(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|error| ChannelError(error.to_string()))?;
+
+                file.write_all(entry.as_bytes())
+                    .await
+                    .map_err(|error| ChannelError(error.to_string()))
+            }
+            None => {
+                println!("[NOTIFIER] ~ {}", entry);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Delivery of the notifications to the users, through the channels each user
+//! chose in their preferences. Every notification is stored first, it's what the
+//! in-app inbox lists and what keeps a reminder from being sent twice.
+
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::Utc;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::mailer::SharedMailer;
+use crate::models::notification::{Notification, NotificationKind};
+use crate::models::user::User;
+use crate::utils::db::is_duplicate_key;
+
+pub mod email;
+pub mod log;
+pub mod webhook;
+
+/// Way a notification reaches a user
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// Email to the address of the user
+    EMAIL,
+    /// JSON POST to the `webhook_url` of the user
+    WEBHOOK,
+    /// In-app inbox (`/api/notification/inbox`)
+    INBOX,
+}
+
+#[derive(Debug)]
+pub struct ChannelError(pub String);
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Delivers the notifications outside of the application
+#[async_trait]
+pub trait Channel: Send + Sync {
+    async fn deliver(&self, user: &User, notification: &Notification) -> Result<(), ChannelError>;
+}
+
+/// Notification to be sent to a user
+#[derive(Debug, Clone)]
+pub struct Draft {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+    /// Meeting or action item the notification is about
+    pub subject_id: ObjectId,
+    /// A notification with the same key isn't sent twice to a user
+    pub dedup_key: Option<String>,
+}
+
+/// Channels the notifications are delivered through, the inbox is the database
+pub struct Notifier {
+    email: Box<dyn Channel>,
+    webhook: Box<dyn Channel>,
+}
+
+/// Notifier shared between the routes (managed by rocket) and the background jobs
+pub type SharedNotifier = Arc<Notifier>;
+
+/// Builds the notifier selected with the `NOTIFIER` variable: `log` writes the
+/// emails and webhooks to stdout or `NOTIFICATION_LOG_FILE` for local development,
+/// anything else sends them (emails through the mailer)
+pub fn from_env(mailer: SharedMailer) -> SharedNotifier {
+    match std::env::var("NOTIFIER").as_deref() {
+        Ok("log") => {
+            println!("[NOTIFIER] ~ Logging notifications instead of delivering them");
+            Arc::new(Notifier {
+                email: Box::new(log::LogChannel::from_env(ChannelKind::EMAIL)),
+                webhook: Box::new(log::LogChannel::from_env(ChannelKind::WEBHOOK)),
+            })
+        }
+        _ => Arc::new(Notifier {
+            email: Box::new(email::EmailChannel::new(mailer)),
+            webhook: Box::new(webhook::WebhookChannel::new()),
+        }),
+    }
+}
+
+impl Notifier {
+    /// Sends a notification to the user if their preferences allow it, returning
+    /// whether it was sent. Channels failing are logged, the notification stays
+    /// in the inbox anyway.
+    pub async fn notify(
+        &self,
+        db: &Database,
+        user: &User,
+        draft: Draft,
+    ) -> Result<bool, mongodb::error::Error> {
+        let preferences = &user.notifications;
+        if !preferences.wants(draft.kind) {
+            return Ok(false);
+        }
+
+        let mut notification = Notification {
+            id: None,
+            user_id: user.id.unwrap(),
+            kind: draft.kind,
+            title: draft.title,
+            body: draft.body,
+            subject_id: draft.subject_id,
+            dedup_key: draft.dedup_key,
+            inbox: preferences.channels.contains(&ChannelKind::INBOX),
+            created_at: Utc::now(),
+            read_at: None,
+        };
+
+        match db
+            .collection::<Notification>("notifications")
+            .insert_one(&notification, None)
+            .await
+        {
+            Ok(result) => notification.id = result.inserted_id.as_object_id(),
+            Err(error) if is_duplicate_key(&error) => return Ok(false),
+            Err(error) => return Err(error),
+        }
+
+        for channel in &preferences.channels {
+            let result = match channel {
+                ChannelKind::EMAIL => self.email.deliver(user, &notification).await,
+                ChannelKind::WEBHOOK if preferences.webhook_url.is_some() => {
+                    self.webhook.deliver(user, &notification).await
+                }
+                ChannelKind::WEBHOOK | ChannelKind::INBOX => Ok(()),
+            };
+
+            if let Err(error) = result {
+                eprintln!("[NOTIFIER][{:?}] ~ {}", channel, error);
+            }
+        }
+
+        Ok(true)
+    }
+}
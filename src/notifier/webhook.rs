@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use std::time::Duration;
+
+use super::{Channel, ChannelError};
+use crate::models::notification::{Notification, NotificationView};
+use crate::models::user::User;
+use crate::utils::outbound::client_for;
+
+/// Seconds to wait for the endpoint of a user
+const TIMEOUT_SECONDS: u64 = 10;
+
+/// Posts the notifications as JSON to the `webhook_url` of the user, only if it's
+/// an HTTPS URL on a public address
+pub struct WebhookChannel;
+
+impl WebhookChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for WebhookChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Channel for WebhookChannel {
+    async fn deliver(&self, user: &User, notification: &Notification) -> Result<(), ChannelError> {
+        let url = match &user.notifications.webhook_url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        let client = client_for(url, Duration::from_secs(TIMEOUT_SECONDS))
+            .await
+            .map_err(ChannelError)?;
+
+        let response = client
+            .post(url)
+            .json(&NotificationView::from(notification.clone()))
+            .send()
+            .await
+            .map_err(|error| ChannelError(error.to_string()))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(ChannelError(format!("{} answered {}", url, status))),
+        }
+    }
+}
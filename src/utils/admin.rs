@@ -0,0 +1,44 @@
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::Sha256;
+
+/// Request made by an administrator, sending the `ADMIN_TOKEN` variable as a bearer
+/// token (`Authorization: Bearer <token>`). Without the variable the admin routes
+/// are disabled and fail with a 403.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match std::env::var("ADMIN_TOKEN") {
+            Ok(token) if !token.is_empty() => token,
+            _ => return Outcome::Failure((Status::Forbidden, ())),
+        };
+
+        let sent = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match sent {
+            Some(sent) if matches(sent.trim(), &token) => Outcome::Success(Admin),
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// Compares the tokens in constant time, whatever their length, through their
+/// HMAC-SHA256 so the time taken doesn't tell how much of the token was right
+fn matches(sent: &str, token: &str) -> bool {
+    let tag = |key: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(b"admin");
+        mac
+    };
+
+    tag(sent).verify_slice(&tag(token).finalize().into_bytes()).is_ok()
+}
@@ -0,0 +1,61 @@
+use bson::Document;
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// Version of a document sent in the `ETag` header
+#[derive(Debug, Clone, Copy)]
+pub struct ETag(pub i64);
+
+impl From<ETag> for Header<'static> {
+    fn from(etag: ETag) -> Self {
+        Header::new("ETag", format!("\"{}\"", etag.0))
+    }
+}
+
+/// Version of the document expected by the client, taken from the `If-Match` header.
+/// The header is required on the writes: without it they fail with a 428, and
+/// `If-Match: *` opts out explicitly, the write isn't conditional.
+#[derive(Debug, Clone, Copy)]
+pub struct IfMatch(pub Option<i64>);
+
+impl IfMatch {
+    /// Restricts the filter of a write to the expected version
+    pub fn apply(&self, filter: &mut Document) {
+        if let Some(version) = self.0 {
+            filter.insert("version", version);
+        }
+    }
+
+    /// Status of a conditional write that didn't match any document, knowing if
+    /// the document exists: 412 if it was changed by someone else, 404 otherwise
+    pub fn failed_write_status(&self, exists: bool) -> Status {
+        if exists && self.0.is_some() {
+            Status::PreconditionFailed
+        } else {
+            Status::NotFound
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let value = match request.headers().get_one("If-Match") {
+            Some(value) => value.trim(),
+            None => return Outcome::Failure((Status::PreconditionRequired, ())),
+        };
+
+        if value == "*" {
+            return Outcome::Success(IfMatch(None));
+        }
+
+        let version = value.trim_start_matches("W/").trim_matches('"').parse::<i64>();
+
+        match version {
+            Ok(version) => Outcome::Success(IfMatch(Some(version))),
+            Err(_) => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
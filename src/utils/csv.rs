@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+/// Serializes a single record (struct, tuple or slice) into a CSV line,
+/// quoting and escaping the fields when needed
+pub fn to_row<T: Serialize>(record: T) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+
+    writer.serialize(record).unwrap();
+
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
@@ -1,3 +1,5 @@
+use bson::oid::ObjectId;
+use mongodb::error::{Error, ErrorKind, WriteFailure};
 use mongodb::{options::ClientOptions, Client};
 
 pub async fn check_db_working() {
@@ -13,7 +15,7 @@ pub async fn check_db_working() {
 
     while !connected {
         let connection_result = db.list_collection_names(None).await;
-        if let Err(_) = connection_result {
+        if connection_result.is_err() {
             eprintln!("[DB] ~ Connection failed after {} tries", tries);
             tries += 1;
         } else {
@@ -30,3 +32,68 @@ pub async fn get_collection<T>(
     let db = db_pool.get().await.unwrap().default_database().unwrap();
     db.collection::<T>(name)
 }
+
+/// Starts a session with an open transaction. Collections used with the session
+/// have to be taken from the returned database, as it shares the same client.
+pub async fn start_transaction(
+    db_pool: &rocket::State<crate::config::Pool>,
+) -> Result<(mongodb::Database, mongodb::ClientSession), rocket::http::Status> {
+    let client = db_pool.get().await.unwrap().clone();
+    let db = client.default_database().unwrap();
+
+    let mut session = match client.start_session(None).await {
+        Ok(session) => session,
+        Err(error) => {
+            eprintln!("[DB][SESSION] ~ {}", error);
+            return Err(rocket::http::Status::InternalServerError);
+        }
+    };
+
+    match session.start_transaction(None).await {
+        Ok(_) => Ok((db, session)),
+        Err(error) => {
+            eprintln!("[DB][TRANSACTION] ~ {}", error);
+            Err(rocket::http::Status::InternalServerError)
+        }
+    }
+}
+
+/// Commits the transaction of the session
+pub async fn commit_transaction(
+    session: &mut mongodb::ClientSession,
+) -> Result<(), rocket::http::Status> {
+    match session.commit_transaction().await {
+        Ok(_) => Ok(()),
+        Err(error) => {
+            eprintln!("[DB][TRANSACTION] ~ {}", error);
+            Err(rocket::http::Status::InternalServerError)
+        }
+    }
+}
+
+/// Parses an id sent by the client as a hex string, failing with a 422 if it isn't
+/// a valid ObjectId
+pub fn parse_id(id: &str) -> Result<ObjectId, rocket::http::Status> {
+    match ObjectId::parse_str(id) {
+        Ok(id) => Ok(id),
+        Err(_) => Err(rocket::http::Status::UnprocessableEntity),
+    }
+}
+
+fn error_code(error: &Error) -> Option<i32> {
+    match &*error.kind {
+        ErrorKind::Write(WriteFailure::WriteError(error)) => Some(error.code),
+        ErrorKind::Command(error) => Some(error.code),
+        _ => None,
+    }
+}
+
+/// Checks if the error was caused by a document violating an unique index
+pub fn is_duplicate_key(error: &Error) -> bool {
+    error_code(error) == Some(11000)
+}
+
+/// Checks if the error was caused by a document rejected by the validator of the collection
+pub fn is_validation_failure(error: &Error) -> bool {
+    error_code(error) == Some(121)
+}
@@ -0,0 +1,153 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, Tz};
+
+/// Domain of the UIDs of the events, they have to be globally unique and stable
+const UID_DOMAIN: &str = "smt-backend";
+
+/// Stable UID of an event generated from the document with `id` of `kind`
+/// (ex: `config-64...@smt-backend`)
+pub fn uid(kind: &str, id: &str) -> String {
+    format!("{}-{}@{}", kind, id, UID_DOMAIN)
+}
+
+/// Escapes the special characters of a TEXT value (RFC 5545 3.3.11)
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Date and time in UTC (ex: `20230502T073000Z`)
+pub fn utc(date: DateTime<Utc>) -> String {
+    date.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Date and time without timezone, to be used with a `TZID` (ex: `20230502T093000`)
+pub fn local(date: NaiveDateTime) -> String {
+    date.format("%Y%m%dT%H%M%S").to_string()
+}
+
+/// Offset from UTC of a timezone (ex: `+0200`)
+fn utc_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Builder of an iCalendar (`.ics`) document
+pub struct Calendar {
+    lines: Vec<String>,
+}
+
+impl Calendar {
+    pub fn new(name: &str) -> Self {
+        let mut calendar = Self { lines: vec![] };
+        calendar.property("BEGIN", "VCALENDAR");
+        calendar.property("VERSION", "2.0");
+        calendar.property("PRODID", "-//Scrum Master Tools//SMT Backend//EN");
+        calendar.property("CALSCALE", "GREGORIAN");
+        calendar.property("METHOD", "PUBLISH");
+        calendar.text("X-WR-CALNAME", name);
+        calendar
+    }
+
+    /// Adds a property whose value is written as it is (dates, rules, ...)
+    pub fn property(&mut self, name: &str, value: &str) -> &mut Self {
+        self.lines.push(format!("{}:{}", name, value));
+        self
+    }
+
+    /// Adds a property whose value is a text, escaping it
+    pub fn text(&mut self, name: &str, value: &str) -> &mut Self {
+        self.property(name, &escape(value))
+    }
+
+    /// Adds the definition of `tz` (`VTIMEZONE`) referenced by the `TZID` of the
+    /// events, with the offset at `from` and every change until `until`
+    pub fn timezone(&mut self, tz: Tz, from: DateTime<Utc>, until: DateTime<Utc>) -> &mut Self {
+        self.property("BEGIN", "VTIMEZONE");
+        self.property("TZID", tz.name());
+
+        let mut offset = tz.offset_from_utc_datetime(&from.naive_utc());
+        self.observance(&offset, &offset, from);
+
+        let mut day = from;
+        while day < until {
+            let next_day = day + Duration::days(1);
+            let next = tz.offset_from_utc_datetime(&next_day.naive_utc());
+            if next.fix() != offset.fix() {
+                // the change happened during the day, down to the second
+                let (mut before, mut after) = (day, next_day);
+                while after - before > Duration::seconds(1) {
+                    let middle = before + (after - before) / 2;
+                    if tz.offset_from_utc_datetime(&middle.naive_utc()).fix() == offset.fix() {
+                        before = middle;
+                    } else {
+                        after = middle;
+                    }
+                }
+                self.observance(&offset, &next, after);
+                offset = next;
+            }
+            day = next_day;
+        }
+
+        self.property("END", "VTIMEZONE")
+    }
+
+    /// Offset of a timezone used from `since` on
+    fn observance(
+        &mut self,
+        previous: &<Tz as TimeZone>::Offset,
+        offset: &<Tz as TimeZone>::Offset,
+        since: DateTime<Utc>,
+    ) {
+        let kind = if offset.dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+        let previous_seconds = previous.fix().local_minus_utc();
+
+        self.property("BEGIN", kind);
+        // in the local time of the offset it replaces
+        self.property(
+            "DTSTART",
+            &local(since.naive_utc() + Duration::seconds(previous_seconds as i64)),
+        );
+        self.property("TZOFFSETFROM", &utc_offset(previous_seconds));
+        self.property("TZOFFSETTO", &utc_offset(offset.fix().local_minus_utc()));
+        self.text("TZNAME", &offset.to_string());
+        self.property("END", kind);
+    }
+
+    /// Serializes the calendar, with CRLF line endings and the lines longer
+    /// than 75 octets folded
+    pub fn build(mut self) -> String {
+        self.property("END", "VCALENDAR");
+
+        let mut ics = String::new();
+        for line in self.lines {
+            ics.push_str(&fold(&line));
+            ics.push_str("\r\n");
+        }
+
+        ics
+    }
+}
+
+/// Splits a content line in lines of at most 75 octets, the next ones starting with
+/// a space. Multi-byte characters are never split.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut length = 0;
+
+    for character in line.chars() {
+        if length + character.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(character);
+        length += character.len_utf8();
+    }
+
+    folded
+}
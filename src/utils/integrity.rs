@@ -0,0 +1,471 @@
+//! Referential integrity between the collections.
+//!
+//! Deletes are soft: the entity gets a `deleted_at` date and is hidden from every
+//! read until it's restored or purged. Every relation follows one of these policies
+//! when the referenced entity is deleted:
+//!
+//! | Referenced    | Referencing               | Policy                                  |
+//! |---------------|---------------------------|-----------------------------------------|
+//! | Team          | MeetingConfig.team_id     | restrict                                |
+//! | Team          | Invitation.team_id        | cascade (pending invitations revoked)   |
+//! | Team          | Webhook.team_id           | cascade                                 |
+//! | User          | Team.owner                | restrict                                |
+//! | User          | Team.users                | nullify (pulled from the list on purge) |
+//! | User          | UserTime.user_id          | cascade                                 |
+//! | User          | Absence.user_id           | cascade                                 |
+//! | User          | Attendance.user_id        | cascade                                 |
+//! | User          | ActionItem.assignee_id    | nullify (unassigned on purge)           |
+//! | User          | Notification.user_id      | cascade (deleted on purge)              |
+//! | MeetingConfig | Meeting.config_id         | restrict (held), cascade (scheduled)    |
+//! | Meeting       | UserTime.meeting_id       | cascade                                 |
+//! | Meeting       | Attendance.meeting_id     | cascade                                 |
+//! | Meeting       | ActionItem.meeting_id     | cascade                                 |
+//! | Webhook       | WebhookDelivery.webhook_id| cascade (deleted on purge)              |
+//!
+//! Restricted deletes fail with a 409 listing the blockers, and every delete runs
+//! inside a transaction so it's never applied partially. Entities deleted in cascade
+//! share the `deleted_at` of their parent, so restoring the parent restores them too.
+//! The purge keeps the entities still referenced by others that aren't purged, so
+//! those can always be restored.
+
+use bson::{doc, oid::ObjectId, Bson, Document};
+use futures::TryStreamExt;
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{ClientSession, Database};
+use rocket::http::Status;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+
+/// Collections whose documents can be moved to the trash, in purge order
+const TRASHABLE: [&str; 9] = [
+    "user_times",
+    "attendances",
+    "action_items",
+    "absences",
+    "meetings",
+    "meeting_configs",
+    "webhooks",
+    "teams",
+    "users",
+];
+
+/// References that keep a document in the trash while the document referencing it
+/// isn't purged, as `(referencing collection, field, referenced collection)`.
+/// Sorted so the referencing collections are settled before the ones they keep.
+const PURGE_REFERENCES: [(&str, &str, &str); 10] = [
+    ("user_times", "meeting_id", "meetings"),
+    ("attendances", "meeting_id", "meetings"),
+    ("action_items", "meeting_id", "meetings"),
+    ("meetings", "config_id", "meeting_configs"),
+    ("meeting_configs", "team_id", "teams"),
+    ("webhooks", "team_id", "teams"),
+    ("teams", "owner", "users"),
+    ("user_times", "user_id", "users"),
+    ("attendances", "user_id", "users"),
+    ("absences", "user_id", "users"),
+];
+
+/// Documents of a collection that prevent a delete
+#[derive(Serialize, Clone, Debug)]
+pub struct Blocker {
+    collection: String,
+    /// Hex ids of the documents
+    ids: Vec<String>,
+}
+
+/// Body of the 409 returned by a restricted delete
+#[derive(Serialize, Clone, Debug)]
+pub struct Blockers {
+    message: String,
+    blockers: Vec<Blocker>,
+}
+
+/// Looks for documents matching any of the `(collection, filter)` restrictions,
+/// returning `None` if nothing blocks the delete
+pub async fn find_blockers(
+    db: &Database,
+    session: &mut ClientSession,
+    restrictions: &[(&str, Document)],
+) -> Result<Option<Blockers>, Status> {
+    let mut blockers = vec![];
+
+    for (collection, filter) in restrictions {
+        // entities in the trash don't block anything
+        let mut filter = filter.clone();
+        filter.insert("deleted_at", Bson::Null);
+
+        let opts = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+        let result = db
+            .collection::<Document>(collection)
+            .find_with_session(filter, opts, session)
+            .await;
+
+        let ids = match result {
+            Ok(mut cursor) => cursor.stream(session).try_collect::<Vec<Document>>().await,
+            Err(error) => Err(error),
+        };
+
+        let ids: Vec<String> = match ids {
+            Ok(ids) => ids
+                .iter()
+                .filter_map(|document| document.get_object_id("_id").ok())
+                .map(|id| id.to_hex())
+                .collect(),
+            Err(error) => {
+                eprintln!("[INTEGRITY][{}] ~ {}", collection.to_uppercase(), error);
+                return Err(Status::InternalServerError);
+            }
+        };
+
+        if !ids.is_empty() {
+            blockers.push(Blocker {
+                collection: collection.to_string(),
+                ids,
+            });
+        }
+    }
+
+    if blockers.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Blockers {
+            message: "The entity is referenced by other entities".to_owned(),
+            blockers,
+        }))
+    }
+}
+
+/// Moves an entity to the trash, returning it or `None` if it doesn't exist (or
+/// isn't at the `expected_version` when provided)
+pub async fn soft_delete_one<T>(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    id: ObjectId,
+    expected_version: Option<i64>,
+    deleted_at: i64,
+) -> Result<Option<T>, Status>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let opts = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    let mut filter = doc! { "_id": id, "deleted_at": null };
+    if let Some(version) = expected_version {
+        filter.insert("version", version);
+    }
+
+    let result = db
+        .collection::<T>(collection)
+        .find_one_and_update_with_session(
+            filter,
+            doc! { "$set": { "deleted_at": deleted_at }, "$inc": { "version": 1 } },
+            opts,
+            session,
+        )
+        .await;
+
+    result.map_err(|error| {
+        eprintln!("[DELETE][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Moves every entity matching `filter` to the trash (cascade)
+pub async fn soft_delete_many(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    mut filter: Document,
+    deleted_at: i64,
+) -> Result<(), Status> {
+    filter.insert("deleted_at", Bson::Null);
+
+    let result = db
+        .collection::<Document>(collection)
+        .update_many_with_session(
+            filter,
+            doc! { "$set": { "deleted_at": deleted_at }, "$inc": { "version": 1 } },
+            None,
+            session,
+        )
+        .await;
+
+    result.map(|_| ()).map_err(|error| {
+        eprintln!("[DELETE][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Ids of the entities matching `filter`, to cascade a delete or a restore to
+/// their own children
+pub async fn find_ids(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    filter: Document,
+) -> Result<Vec<Bson>, Status> {
+    let result = db
+        .collection::<Document>(collection)
+        .distinct_with_session("_id", filter, None, session)
+        .await;
+
+    result.map_err(|error| {
+        eprintln!("[INTEGRITY][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Takes an entity out of the trash, returning it as it was in the trash (so the
+/// caller knows its `deleted_at`, and its version is one less than the stored one)
+/// or `None` if it isn't in the trash or doesn't have the `expected_version`
+pub async fn restore_one<T>(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    id: ObjectId,
+    expected_version: Option<i64>,
+) -> Result<Option<T>, Status>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let opts = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::Before)
+        .build();
+
+    let mut filter = doc! { "_id": id, "deleted_at": { "$ne": null } };
+    if let Some(version) = expected_version {
+        filter.insert("version", version);
+    }
+
+    let result = db
+        .collection::<T>(collection)
+        .find_one_and_update_with_session(
+            filter,
+            doc! { "$unset": { "deleted_at": "" }, "$inc": { "version": 1 } },
+            opts,
+            session,
+        )
+        .await;
+
+    result.map_err(|error| {
+        eprintln!("[RESTORE][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Takes out of the trash the entities matching `filter` that were deleted in
+/// cascade with their parent (deleted at the same date)
+pub async fn restore_many(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    mut filter: Document,
+    deleted_at: i64,
+) -> Result<(), Status> {
+    filter.insert("deleted_at", deleted_at);
+
+    let result = db
+        .collection::<Document>(collection)
+        .update_many_with_session(
+            filter,
+            doc! { "$unset": { "deleted_at": "" }, "$inc": { "version": 1 } },
+            None,
+            session,
+        )
+        .await;
+
+    result.map(|_| ()).map_err(|error| {
+        eprintln!("[RESTORE][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Checks that the entity exists and isn't in the trash
+pub async fn is_active(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    id: ObjectId,
+) -> Result<bool, Status> {
+    let result = db
+        .collection::<Document>(collection)
+        .find_one_with_session(doc! { "_id": id, "deleted_at": null }, None, session)
+        .await;
+
+    result.map(|document| document.is_some()).map_err(|error| {
+        eprintln!("[INTEGRITY][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Checks that the entity exists and is in the trash
+pub async fn is_trashed(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    id: ObjectId,
+) -> Result<bool, Status> {
+    let result = db
+        .collection::<Document>(collection)
+        .find_one_with_session(doc! { "_id": id, "deleted_at": { "$ne": null } }, None, session)
+        .await;
+
+    result.map(|document| document.is_some()).map_err(|error| {
+        eprintln!("[INTEGRITY][{}] ~ {}", collection.to_uppercase(), error);
+        Status::InternalServerError
+    })
+}
+
+/// Ids of the documents of every trashable collection moved to the trash before
+/// `deleted_before`, except the ones still referenced by a document that isn't
+/// purged (ex: a team whose config was deleted later waits for the config)
+async fn purgeable(
+    db: &Database,
+    session: &mut ClientSession,
+    deleted_before: i64,
+) -> Result<BTreeMap<&'static str, Vec<Bson>>, Status> {
+    let filter = doc! { "deleted_at": { "$lt": deleted_before } };
+
+    let mut ids = BTreeMap::new();
+    for collection in TRASHABLE {
+        ids.insert(collection, find_ids(db, session, collection, filter.clone()).await?);
+    }
+
+    for (referencing, field, referenced) in PURGE_REFERENCES {
+        let kept = db
+            .collection::<Document>(referencing)
+            .distinct_with_session(
+                field,
+                doc! {
+                    field: { "$in": &ids[referenced] },
+                    "_id": { "$nin": &ids[referencing] }
+                },
+                None,
+                session,
+            )
+            .await;
+
+        let kept = match kept {
+            Ok(kept) => kept,
+            Err(error) => {
+                eprintln!("[PURGE][{}] ~ {}", referencing.to_uppercase(), error);
+                return Err(Status::InternalServerError);
+            }
+        };
+
+        ids.get_mut(referenced)
+            .unwrap()
+            .retain(|id| !kept.contains(id));
+    }
+
+    Ok(ids)
+}
+
+/// Permanently deletes every entity moved to the trash before `deleted_before`
+/// (ms since epoch), returning the amount of documents deleted per collection.
+/// Entities still referenced by others that aren't purged are kept.
+pub async fn purge(
+    db: &Database,
+    session: &mut ClientSession,
+    deleted_before: i64,
+) -> Result<BTreeMap<String, u64>, Status> {
+    let mut report = BTreeMap::new();
+    let ids = purgeable(db, session, deleted_before).await?;
+
+    // purged users are pulled from the teams they belonged to
+    let users = &ids["users"];
+    let result = db
+        .collection::<Document>("teams")
+        .update_many_with_session(
+            doc! { "users": { "$in": users } },
+            doc! { "$pull": { "users": { "$in": users } } },
+            None,
+            session,
+        )
+        .await;
+
+    if let Err(error) = result {
+        eprintln!("[PURGE][TEAMS] ~ {}", error);
+        return Err(Status::InternalServerError);
+    }
+
+    // their action items are kept unassigned, their notifications are deleted
+    let result = db
+        .collection::<Document>("action_items")
+        .update_many_with_session(
+            doc! { "assignee_id": { "$in": users } },
+            doc! { "$unset": { "assignee_id": "" }, "$inc": { "version": 1 } },
+            None,
+            session,
+        )
+        .await;
+
+    if let Err(error) = result {
+        eprintln!("[PURGE][ACTION_ITEMS] ~ {}", error);
+        return Err(Status::InternalServerError);
+    }
+
+    let result = db
+        .collection::<Document>("notifications")
+        .delete_many_with_session(doc! { "user_id": { "$in": users } }, None, session)
+        .await;
+
+    if let Err(error) = result {
+        eprintln!("[PURGE][NOTIFICATIONS] ~ {}", error);
+        return Err(Status::InternalServerError);
+    }
+
+    // the outbox and logs of the purged webhooks go away with them
+    let webhooks = &ids["webhooks"];
+
+    let result = db
+        .collection::<Document>("webhook_deliveries")
+        .delete_many_with_session(doc! { "webhook_id": { "$in": webhooks } }, None, session)
+        .await;
+
+    match result {
+        Ok(result) => report.insert("webhook_deliveries".to_owned(), result.deleted_count),
+        Err(error) => {
+            eprintln!("[PURGE][WEBHOOK_DELIVERIES] ~ {}", error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    // and the summaries and chat sessions of the purged meetings
+    let meetings = &ids["meetings"];
+
+    for collection in ["chat_summaries", "chat_sessions"] {
+        let result = db
+            .collection::<Document>(collection)
+            .delete_many_with_session(doc! { "meeting_id": { "$in": meetings } }, None, session)
+            .await;
+
+        match result {
+            Ok(result) => report.insert(collection.to_owned(), result.deleted_count),
+            Err(error) => {
+                eprintln!("[PURGE][{}] ~ {}", collection.to_uppercase(), error);
+                return Err(Status::InternalServerError);
+            }
+        };
+    }
+
+    for collection in TRASHABLE {
+        let result = db
+            .collection::<Document>(collection)
+            .delete_many_with_session(doc! { "_id": { "$in": &ids[collection] } }, None, session)
+            .await;
+
+        match result {
+            Ok(result) => report.insert(collection.to_owned(), result.deleted_count),
+            Err(error) => {
+                eprintln!("[PURGE][{}] ~ {}", collection.to_uppercase(), error);
+                return Err(Status::InternalServerError);
+            }
+        };
+    }
+
+    Ok(report)
+}
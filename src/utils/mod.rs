@@ -1,2 +1,15 @@
+pub mod admin;
+pub mod concurrency;
+pub mod csv;
 pub mod db;
+pub mod ical;
+pub mod integrity;
+pub mod outbound;
+pub mod pagination;
+pub mod patch;
+pub mod recurrence;
 pub mod responders;
+pub mod schema;
+pub mod token;
+pub mod validation;
+pub mod work_calendar;
@@ -0,0 +1,126 @@
+//! Requests to the URLs given by the clients: the webhooks of the teams and of the
+//! users and the channels of the chat summaries. Only HTTPS endpoints on public
+//! addresses are reached, checked again when the host is resolved, and redirects
+//! aren't followed, so the API can't be used to reach its own network.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::{redirect, Client, Url};
+use rocket::tokio::net::lookup_host;
+
+/// Whether an address is reachable from the internet: not loopback, private,
+/// link-local (cloud metadata), shared, multicast or reserved. The IPv4 addresses
+/// embedded in IPv6 ones (mapped, compatible, NAT64 and 6to4) are checked too.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || octets[0] == 0
+                // shared address space, 100.64.0.0/10
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+                // protocol assignments, 192.0.0.0/24
+                || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)
+                // benchmarking, 198.18.0.0/15
+                || (octets[0] == 198 && octets[1] & 0xfe == 18)
+                // reserved, 240.0.0.0/4
+                || octets[0] >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = embedded_ipv4(ip) {
+                return is_public(IpAddr::V4(ip));
+            }
+            let segments = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local, fc00::/7
+                || segments[0] & 0xfe00 == 0xfc00
+                // link-local, fe80::/10, and the deprecated site-local, fec0::/10
+                || segments[0] & 0xff80 == 0xfe80
+                // documentation, 2001:db8::/32
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+                // Teredo, 2001::/32, whose IPv4 address is obfuscated
+                || (segments[0] == 0x2001 && segments[1] == 0)
+                // discard only, 100::/64
+                || (segments[0] == 0x0100 && segments[1..4] == [0, 0, 0])
+                // local-use NAT64, 64:ff9b:1::/48
+                || (segments[0] == 0x64 && segments[1] == 0xff9b && segments[2] == 1))
+        }
+    }
+}
+
+/// IPv4 address an IPv6 one is translated to: IPv4-mapped (`::ffff:a.b.c.d`),
+/// IPv4-compatible (`::a.b.c.d`), NAT64 (`64:ff9b::a.b.c.d`) and 6to4 (`2002:ab:cd::`)
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let ipv4 = |high: u16, low: u16| {
+        let [a, b] = high.to_be_bytes();
+        let [c, d] = low.to_be_bytes();
+        Ipv4Addr::new(a, b, c, d)
+    };
+
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low] | [0, 0, 0, 0, 0, 0, high, low] => {
+            Some(ipv4(high, low))
+        }
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(ipv4(high, low)),
+        [0x2002, high, low, ..] => Some(ipv4(high, low)),
+        _ => None,
+    }
+}
+
+/// Host of an HTTPS URL, `Err` with the reason if it can't be reached: another
+/// scheme, a local name or an address that isn't public
+pub fn check_url(url: &str) -> Result<(Url, String), &'static str> {
+    let url = Url::parse(url).map_err(|_| "must be a URL")?;
+    if url.scheme() != "https" {
+        return Err("must be an https URL");
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']').to_lowercase(),
+        None => return Err("must have a host"),
+    };
+    if host == "localhost" || host.ends_with(".localhost") {
+        return Err("must be a public host");
+    }
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        if !is_public(ip) {
+            return Err("must be a public host");
+        }
+    }
+
+    Ok((url, host))
+}
+
+/// Client to request `url`, pinned to the addresses its host resolves to once
+/// they're checked to be public, so a second lookup can't point it elsewhere
+pub async fn client_for(url: &str, timeout: Duration) -> Result<Client, String> {
+    let (url, host) = check_url(url).map_err(|reason| format!("the URL {}", reason))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = lookup_host((host.as_str(), port))
+        .await
+        .map_err(|error| format!("{} can't be resolved: {}", host, error))?
+        .collect();
+    if addrs.is_empty() || addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err(format!("{} doesn't resolve to a public address", host));
+    }
+
+    let mut builder = Client::builder()
+        .timeout(timeout)
+        .redirect(redirect::Policy::none());
+    if host.parse::<IpAddr>().is_err() {
+        builder = builder.resolve_to_addrs(&host, &addrs);
+    }
+
+    builder.build().map_err(|error| error.to_string())
+}
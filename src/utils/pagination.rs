@@ -0,0 +1,136 @@
+use bson::{doc, Document};
+use futures::TryStreamExt;
+use mongodb::{options::FindOptions, Collection};
+use rocket::http::Status;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Amount of items returned when no limit is provided
+const DEFAULT_LIMIT: u64 = 20;
+/// Maximum amount of items that can be requested in a single page
+const MAX_LIMIT: u64 = 100;
+
+/// Envelope returned by every list endpoint
+#[derive(Serialize, Clone, Debug)]
+pub struct Page<T> {
+    /// Items of the requested page
+    items: Vec<T>,
+    /// Amount of documents matching the filters
+    total: u64,
+    /// Requested page (starting at 1)
+    page: u64,
+    /// Maximum amount of items in the page
+    limit: u64,
+}
+
+impl<T> Page<T> {
+    /// Items of the page
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Converts every item of the page, keeping the pagination info
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            page: self.page,
+            limit: self.limit,
+        }
+    }
+}
+
+/// Page, size and sort order requested in the query string
+#[derive(Debug)]
+pub struct PageOptions {
+    page: u64,
+    limit: u64,
+    sort: Document,
+}
+
+impl PageOptions {
+    /// Builds the options from the raw query params.
+    ///
+    /// `sort` is a field name optionally prefixed with `-` for descending order,
+    /// and it must be one of `sortable`. The first sortable field (which may carry
+    /// the `-` prefix too) is used by default.
+    pub fn new(
+        page: Option<u64>,
+        limit: Option<u64>,
+        sort: Option<String>,
+        sortable: &[&str],
+    ) -> Result<Self, Status> {
+        let page = page.unwrap_or(1);
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+
+        if page == 0 || limit == 0 {
+            return Err(Status::UnprocessableEntity);
+        }
+
+        // the documents skipped have to fit in the i64 sent to the database
+        let limit = limit.min(MAX_LIMIT);
+        match (page - 1).checked_mul(limit) {
+            Some(skipped) if skipped <= i64::MAX as u64 => {}
+            _ => return Err(Status::UnprocessableEntity),
+        }
+
+        let sort = sort.unwrap_or_else(|| sortable[0].to_owned());
+        let (field, order) = match sort.strip_prefix('-') {
+            Some(field) => (field, -1),
+            None => (sort.as_str(), 1),
+        };
+
+        if !sortable.iter().any(|s| s.trim_start_matches('-') == field) {
+            return Err(Status::UnprocessableEntity);
+        }
+
+        Ok(Self {
+            page,
+            limit,
+            sort: doc! { field: order, "_id": order },
+        })
+    }
+
+    fn find_options(&self) -> FindOptions {
+        FindOptions::builder()
+            .sort(self.sort.clone())
+            .skip((self.page - 1) * self.limit)
+            .limit(self.limit as i64)
+            .build()
+    }
+}
+
+/// Runs `filter` against `collection`, returning the requested page and the total count
+pub async fn paginate<T>(
+    collection: &Collection<T>,
+    filter: Document,
+    opts: &PageOptions,
+) -> Result<Page<T>, Status>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let total = match collection.count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(error) => {
+            eprintln!("[PAGINATE][{}] ~ {}", collection.name().to_uppercase(), error);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let items = match collection.find(filter, opts.find_options()).await {
+        Ok(cursor) => cursor.try_collect::<Vec<T>>().await,
+        Err(error) => Err(error),
+    };
+
+    match items {
+        Ok(items) => Ok(Page {
+            items,
+            total,
+            page: opts.page,
+            limit: opts.limit,
+        }),
+        Err(error) => {
+            eprintln!("[PAGINATE][{}] ~ {}", collection.name().to_uppercase(), error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
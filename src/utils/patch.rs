@@ -0,0 +1,87 @@
+use bson::{doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::Collection;
+use rocket::http::Status;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+
+use super::concurrency::IfMatch;
+use super::db::{is_duplicate_key, is_validation_failure};
+
+/// Deserializes a nullable field of a patch, telling a missing field (`None`,
+/// kept as it is) from a `null` one (`Some(None)`, removed). Used together with
+/// `#[serde(default)]`. The required fields are plain options, a `null` leaves
+/// them unchanged.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Fails with a 404 unless the document referenced by a patch exists and isn't deleted
+pub async fn check_reference<T>(collection: &Collection<T>, id: ObjectId) -> Result<(), Status>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    match collection
+        .find_one(doc! { "_id": id, "deleted_at": null }, None)
+        .await
+    {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(Status::NotFound),
+        Err(error) => {
+            eprintln!("[PATCH][{}] ~ {}", collection.name().to_uppercase(), error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Sets the validated `changes` on the active document with `id` and bumps its
+/// version, returning the updated document. An empty patch doesn't change
+/// anything, the document is returned as it is.
+pub async fn patch_one<T>(
+    collection: &Collection<T>,
+    id: ObjectId,
+    if_match: IfMatch,
+    changes: Document,
+) -> Result<T, Status>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let mut filter = doc! { "_id": id, "deleted_at": null };
+    if_match.apply(&mut filter);
+
+    let result = if changes.is_empty() {
+        collection.find_one(filter, None).await
+    } else {
+        collection
+            .find_one_and_update(
+                filter,
+                doc! { "$set": changes, "$inc": { "version": 1 } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+    };
+
+    match result {
+        Ok(Some(document)) => Ok(document),
+        Ok(None) => {
+            let exists = collection
+                .find_one(doc! { "_id": id, "deleted_at": null }, None)
+                .await
+                .unwrap()
+                .is_some();
+            Err(if_match.failed_write_status(exists))
+        }
+        Err(error) if is_duplicate_key(&error) => Err(Status::Conflict),
+        Err(error) if is_validation_failure(&error) => Err(Status::UnprocessableEntity),
+        Err(error) => {
+            eprintln!("[PATCH][{}] ~ {}", collection.name().to_uppercase(), error);
+            Err(Status::InternalServerError)
+        }
+    }
+}
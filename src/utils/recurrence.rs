@@ -0,0 +1,175 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::validation::{rrule, timezone};
+
+/// How often a recurrence repeats
+#[derive(Clone, Debug, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+}
+
+/// Subset of the RFC 5545 RRULE supported by meeting schedules: a `FREQ` of
+/// `DAILY` or `WEEKLY`, an optional `INTERVAL` between 1 and 2 and an optional
+/// `BYDAY` (ex: `FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR`, `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recurrence {
+    frequency: Frequency,
+    interval: u32,
+    /// Days of the week when the recurrence happens, every day if empty
+    days: Vec<Weekday>,
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday, String> {
+    match day {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("unknown day {}", day)),
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut days = vec![];
+
+        for part in rule.split(';') {
+            let (name, value) = match part.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(format!("{} isn't a NAME=VALUE pair", part)),
+            };
+
+            match name {
+                "FREQ" => {
+                    frequency = match value {
+                        "DAILY" => Some(Frequency::Daily),
+                        "WEEKLY" => Some(Frequency::Weekly),
+                        _ => return Err(format!("unsupported frequency {}", value)),
+                    }
+                }
+                "INTERVAL" => {
+                    interval = match value.parse() {
+                        Ok(interval @ 1..=2) => interval,
+                        _ => return Err("the interval must be 1 or 2".to_owned()),
+                    }
+                }
+                "BYDAY" => {
+                    days = value
+                        .split(',')
+                        .map(parse_weekday)
+                        .collect::<Result<Vec<_>, _>>()?;
+                }
+                _ => return Err(format!("unsupported part {}", name)),
+            }
+        }
+
+        match frequency {
+            Some(frequency) => Ok(Recurrence {
+                frequency,
+                interval,
+                days,
+            }),
+            None => Err("FREQ is required".to_owned()),
+        }
+    }
+}
+
+impl Recurrence {
+    /// Whether the recurrence happens on `date`, counting the intervals from `first_day`
+    fn happens_on(&self, first_day: NaiveDate, date: NaiveDate) -> bool {
+        if date < first_day {
+            return false;
+        }
+
+        let on_day = if self.days.is_empty() {
+            // a weekly recurrence without days repeats on the weekday it started
+            self.frequency == Frequency::Daily || date.weekday() == first_day.weekday()
+        } else {
+            self.days.contains(&date.weekday())
+        };
+
+        let period = match self.frequency {
+            Frequency::Daily => (date - first_day).num_days(),
+            Frequency::Weekly => (week_start(date) - week_start(first_day)).num_weeks(),
+        };
+
+        on_day && period % self.interval as i64 == 0
+    }
+}
+
+/// Monday of the week of `date`
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+/// Recurring schedule of the meetings created from a config
+#[derive(Serialize, Deserialize, Validate, Clone, Debug)]
+pub struct Schedule {
+    /// Recurrence rule (ex: `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO`)
+    #[validate(custom = "rrule")]
+    pub(crate) rrule: String,
+    /// First day of the schedule, in its timezone (ex: `2023-05-02`)
+    pub(crate) starts_on: NaiveDate,
+    /// Local time when the meetings start (ex: `09:30:00`)
+    pub(crate) start_time: NaiveTime,
+    /// IANA name of the timezone of the team (ex: `Europe/Madrid`)
+    #[validate(custom = "timezone")]
+    pub(crate) timezone: String,
+}
+
+impl Schedule {
+    /// Start dates of the meetings from `from` (included) on, in chronological
+    /// order. Fails if the rule or the timezone isn't valid.
+    pub fn occurrences(
+        &self,
+        from: DateTime<Utc>,
+    ) -> Result<impl Iterator<Item = DateTime<Utc>>, String> {
+        let recurrence = Recurrence::from_str(&self.rrule)?;
+        let tz = Tz::from_str(&self.timezone).map_err(|error| error.to_string())?;
+
+        let first_day = self.starts_on;
+        let start_time = self.start_time;
+        // the day before, as `from` may already be the next day in the timezone
+        let from_day = from.with_timezone(&tz).date_naive().pred_opt().unwrap();
+
+        Ok(first_day
+            .max(from_day)
+            .iter_days()
+            .filter(move |date| recurrence.happens_on(first_day, *date))
+            .filter_map(move |date| local_to_utc(&tz, date, start_time))
+            .filter(move |date| *date >= from))
+    }
+
+    /// Start date of the first meeting, none if the rule or the timezone isn't valid
+    pub fn first_occurrence(&self) -> Option<DateTime<Utc>> {
+        // the day before in UTC is always before the first day in the timezone
+        let from = self.starts_on.pred_opt()?.and_time(NaiveTime::MIN);
+        self.occurrences(Utc.from_utc_datetime(&from)).ok()?.next()
+    }
+}
+
+/// Converts a local date and time to UTC. A time skipped by a daylight saving
+/// change is moved one hour later, and the first of the repeated ones is used.
+fn local_to_utc(tz: &Tz, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    let local = date.and_time(time);
+
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|date| date.with_timezone(&Utc))
+}
@@ -1,10 +1,33 @@
-use rocket::{response::Responder, serde::json::Json};
+use rocket::{http::Status, response::Responder, serde::json::Json};
 use serde::Serialize;
 
+use super::{concurrency::ETag, integrity::Blockers, validation::FieldErrors};
+
 #[derive(Responder)]
 pub enum Response<T: Serialize + Clone> {
     #[response(status = 200, content_type = "json")]
     Success(Json<T>),
     #[response(status = 201, content_type = "json")]
     Created(Json<T>),
+    /// Success response with the version of the document in the `ETag` header
+    #[response(status = 200, content_type = "json")]
+    Tagged(Json<T>, ETag),
+}
+
+/// Errors that carry a json body explaining the failure, on top of the plain statuses
+#[derive(Responder, Debug)]
+pub enum ApiError {
+    Status(Status),
+    /// The entity can't be deleted because other entities depend on it
+    #[response(status = 409, content_type = "json")]
+    Blocked(Json<Blockers>),
+    /// The body of the request breaks some validation rules
+    #[response(status = 422, content_type = "json")]
+    Invalid(Json<FieldErrors>),
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        ApiError::Status(status)
+    }
 }
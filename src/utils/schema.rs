@@ -0,0 +1,408 @@
+use bson::{doc, Document};
+use mongodb::{options::IndexOptions, Database, IndexModel};
+
+/// JSON schema validator of every collection, applied on startup
+fn validators() -> Vec<(&'static str, Document)> {
+    let object_id_or_null = doc! { "bsonType": ["objectId", "null"] };
+    let deleted_at = doc! { "bsonType": ["long", "null"] };
+    let version = doc! { "bsonType": ["long", "int"], "minimum": 0 };
+    let webhook_events = vec![
+        "MEETING_STARTED",
+        "MEETING_FINISHED",
+        "USER_TIME_RECORDED",
+        "CONFIG_CHANGED",
+        "ACTION_ITEM_CREATED",
+        "PING",
+    ];
+
+    vec![
+        (
+            "users",
+            doc! {
+                "bsonType": "object",
+                "required": ["name", "email"],
+                "properties": {
+                    "name": { "bsonType": "string" },
+                    "email": { "bsonType": "string" },
+                    "calendar_token": { "bsonType": ["string", "null"] },
+                    "notifications": { "bsonType": "object" },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "teams",
+            doc! {
+                "bsonType": "object",
+                "required": ["name"],
+                "properties": {
+                    "name": { "bsonType": "string" },
+                    "users": {
+                        "bsonType": ["array", "null"],
+                        "items": { "bsonType": "objectId" }
+                    },
+                    "owner": object_id_or_null.clone(),
+                    "timezone": { "bsonType": "string" },
+                    "working_days": {
+                        "bsonType": "array",
+                        "items": { "enum": ["MO", "TU", "WE", "TH", "FR", "SA", "SU"] }
+                    },
+                    "holidays": {
+                        "bsonType": "array",
+                        "items": { "bsonType": "string" }
+                    },
+                    "chat": {
+                        "bsonType": ["object", "null"],
+                        "required": ["format", "webhook_url"],
+                        "properties": {
+                            "format": { "enum": ["SLACK", "TEAMS"] },
+                            "webhook_url": { "bsonType": "string" }
+                        }
+                    },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "meeting_configs",
+            doc! {
+                "bsonType": "object",
+                "required": ["desired_duration", "config_name", "description", "meeting_type"],
+                "properties": {
+                    "team_id": object_id_or_null.clone(),
+                    "desired_duration": { "bsonType": ["long", "int"] },
+                    "config_name": { "bsonType": "string" },
+                    "description": { "bsonType": "string" },
+                    "meeting_type": { "enum": ["RETRO", "DAILY"] },
+                    "schedule": {
+                        "bsonType": ["object", "null"],
+                        "required": ["rrule", "starts_on", "start_time", "timezone"],
+                        "properties": {
+                            "rrule": { "bsonType": "string" },
+                            "starts_on": { "bsonType": "string" },
+                            "start_time": { "bsonType": "string" },
+                            "timezone": { "bsonType": "string" }
+                        }
+                    },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "meetings",
+            doc! {
+                "bsonType": "object",
+                "required": ["date_utc", "end_utc"],
+                "properties": {
+                    "config_id": object_id_or_null.clone(),
+                    "date_utc": { "bsonType": "long" },
+                    "end_utc": { "bsonType": "long" },
+                    "status": { "enum": ["SCHEDULED", "HELD"] },
+                    "occurrence_utc": { "bsonType": ["long", "null"] },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "user_times",
+            doc! {
+                "bsonType": "object",
+                "required": ["start_utc", "end_utc"],
+                "properties": {
+                    "user_id": object_id_or_null.clone(),
+                    "meeting_id": object_id_or_null,
+                    "start_utc": { "bsonType": "long" },
+                    "end_utc": { "bsonType": "long" },
+                    "marker": { "enum": ["INTERRUPTION", "CROSSTALK", null] },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "absences",
+            doc! {
+                "bsonType": "object",
+                "required": ["user_id", "kind", "start_date", "end_date"],
+                "properties": {
+                    "user_id": { "bsonType": "objectId" },
+                    "kind": { "enum": ["VACATION", "SICK", "OTHER"] },
+                    "start_date": { "bsonType": "string" },
+                    "end_date": { "bsonType": "string" },
+                    "note": { "bsonType": ["string", "null"] },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "attendances",
+            doc! {
+                "bsonType": "object",
+                "required": ["meeting_id", "user_id", "status", "recorded_at"],
+                "properties": {
+                    "meeting_id": { "bsonType": "objectId" },
+                    "user_id": { "bsonType": "objectId" },
+                    "status": { "enum": ["PRESENT", "LATE", "ABSENT", "EXCUSED"] },
+                    "late_minutes": { "bsonType": ["long", "null"] },
+                    "recorded_at": { "bsonType": "long" },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "action_items",
+            doc! {
+                "bsonType": "object",
+                "required": ["meeting_id", "title", "done", "created_at"],
+                "properties": {
+                    "meeting_id": { "bsonType": "objectId" },
+                    "title": { "bsonType": "string" },
+                    "assignee_id": { "bsonType": ["objectId", "null"] },
+                    "due_date": { "bsonType": ["string", "null"] },
+                    "done": { "bsonType": "bool" },
+                    "created_at": { "bsonType": "long" },
+                    "deleted_at": deleted_at.clone(),
+                    "version": version.clone()
+                }
+            },
+        ),
+        (
+            "notifications",
+            doc! {
+                "bsonType": "object",
+                "required": ["user_id", "kind", "title", "body", "subject_id", "inbox", "created_at"],
+                "properties": {
+                    "user_id": { "bsonType": "objectId" },
+                    "kind": { "enum": ["MEETING_REMINDER", "ACTION_ITEM_ASSIGNED", "ACTION_ITEM_OVERDUE"] },
+                    "title": { "bsonType": "string" },
+                    "body": { "bsonType": "string" },
+                    "subject_id": { "bsonType": "objectId" },
+                    "dedup_key": { "bsonType": ["string", "null"] },
+                    "inbox": { "bsonType": "bool" },
+                    "created_at": { "bsonType": "long" },
+                    "read_at": { "bsonType": ["long", "null"] }
+                }
+            },
+        ),
+        (
+            "webhooks",
+            doc! {
+                "bsonType": "object",
+                "required": ["team_id", "url", "events", "secret", "active", "created_at"],
+                "properties": {
+                    "team_id": { "bsonType": "objectId" },
+                    "url": { "bsonType": "string" },
+                    "events": {
+                        "bsonType": "array",
+                        "items": { "enum": webhook_events.clone() }
+                    },
+                    "secret": { "bsonType": "string" },
+                    "active": { "bsonType": "bool" },
+                    "created_at": { "bsonType": "long" },
+                    "deleted_at": deleted_at,
+                    "version": version
+                }
+            },
+        ),
+        (
+            "webhook_deliveries",
+            doc! {
+                "bsonType": "object",
+                "required": ["webhook_id", "event", "payload", "status", "attempts", "created_at"],
+                "properties": {
+                    "webhook_id": { "bsonType": "objectId" },
+                    "event": { "enum": webhook_events },
+                    "payload": { "bsonType": "string" },
+                    "dedup_key": { "bsonType": ["string", "null"] },
+                    "status": { "enum": ["PENDING", "SENDING", "DELIVERED", "FAILED"] },
+                    "attempts": { "bsonType": "array" },
+                    "next_attempt_at": { "bsonType": ["long", "null"] },
+                    "created_at": { "bsonType": "long" }
+                }
+            },
+        ),
+        (
+            "chat_summaries",
+            doc! {
+                "bsonType": "object",
+                "required": ["meeting_id", "team_id", "format", "status", "attempts", "updated_at"],
+                "properties": {
+                    "meeting_id": { "bsonType": "objectId" },
+                    "team_id": { "bsonType": "objectId" },
+                    "format": { "enum": ["SLACK", "TEAMS"] },
+                    "status": { "enum": ["POSTED", "FAILED"] },
+                    "attempts": { "bsonType": ["int", "long"], "minimum": 0 },
+                    "last_error": { "bsonType": ["string", "null"] },
+                    "updated_at": { "bsonType": "long" },
+                    "posted_at": { "bsonType": ["long", "null"] }
+                }
+            },
+        ),
+        (
+            "chat_sessions",
+            doc! {
+                "bsonType": "object",
+                "required": ["team_id", "meeting_id", "started_by", "speaking_order", "current", "started_at", "turn_started_at"],
+                "properties": {
+                    "team_id": { "bsonType": "objectId" },
+                    "meeting_id": { "bsonType": "objectId" },
+                    "started_by": { "bsonType": "objectId" },
+                    "speaking_order": {
+                        "bsonType": "array",
+                        "items": { "bsonType": "objectId" }
+                    },
+                    "current": { "bsonType": ["int", "long"], "minimum": 0 },
+                    "started_at": { "bsonType": "long" },
+                    "turn_started_at": { "bsonType": "long" }
+                }
+            },
+        ),
+        (
+            "invitations",
+            doc! {
+                "bsonType": "object",
+                "required": ["team_id", "email", "invited_by", "token", "status", "created_at", "expires_at"],
+                "properties": {
+                    "team_id": { "bsonType": "objectId" },
+                    "email": { "bsonType": "string" },
+                    "invited_by": { "bsonType": "objectId" },
+                    "token": { "bsonType": "string" },
+                    "status": { "enum": ["PENDING", "ACCEPTED", "DECLINED", "REVOKED"] },
+                    "created_at": { "bsonType": "long" },
+                    "expires_at": { "bsonType": "long" }
+                }
+            },
+        ),
+    ]
+}
+
+/// Indexes of every collection, applied on startup
+fn indexes() -> Vec<(&'static str, IndexModel)> {
+    let index = |keys: Document| IndexModel::builder().keys(keys).build();
+    let unique = |keys: Document| {
+        IndexModel::builder()
+            .keys(keys)
+            .options(IndexOptions::builder().unique(true).build())
+            .build()
+    };
+
+    vec![
+        ("users", unique(doc! { "email": 1 })),
+        (
+            "users",
+            IndexModel::builder()
+                .keys(doc! { "calendar_token": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! { "calendar_token": { "$type": "string" } })
+                        .build(),
+                )
+                .build(),
+        ),
+        ("teams", index(doc! { "users": 1 })),
+        ("teams", index(doc! { "owner": 1 })),
+        ("meeting_configs", index(doc! { "team_id": 1 })),
+        ("meetings", index(doc! { "config_id": 1, "date_utc": -1 })),
+        ("meetings", index(doc! { "date_utc": -1 })),
+        // a meeting per occurrence of the schedule of a config
+        (
+            "meetings",
+            IndexModel::builder()
+                .keys(doc! { "config_id": 1, "occurrence_utc": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! { "occurrence_utc": { "$exists": true } })
+                        .build(),
+                )
+                .build(),
+        ),
+        ("user_times", index(doc! { "meeting_id": 1 })),
+        ("user_times", index(doc! { "user_id": 1, "start_utc": -1 })),
+        ("absences", index(doc! { "user_id": 1, "start_date": 1 })),
+        // a single attendance per meeting and user, restored instead of duplicated
+        ("attendances", unique(doc! { "meeting_id": 1, "user_id": 1 })),
+        ("attendances", index(doc! { "user_id": 1 })),
+        ("action_items", index(doc! { "meeting_id": 1 })),
+        ("action_items", index(doc! { "assignee_id": 1, "done": 1 })),
+        // a reminder or overdue notice is only sent once to a user
+        (
+            "notifications",
+            IndexModel::builder()
+                .keys(doc! { "user_id": 1, "dedup_key": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! { "dedup_key": { "$type": "string" } })
+                        .build(),
+                )
+                .build(),
+        ),
+        ("notifications", index(doc! { "user_id": 1, "created_at": -1 })),
+        ("webhooks", index(doc! { "team_id": 1 })),
+        ("chat_summaries", unique(doc! { "meeting_id": 1 })),
+        ("chat_sessions", unique(doc! { "team_id": 1 })),
+        ("webhook_deliveries", index(doc! { "status": 1, "next_attempt_at": 1 })),
+        ("webhook_deliveries", index(doc! { "webhook_id": 1, "created_at": -1 })),
+        // a meeting is only started or finished once per webhook
+        (
+            "webhook_deliveries",
+            IndexModel::builder()
+                .keys(doc! { "webhook_id": 1, "dedup_key": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! { "dedup_key": { "$type": "string" } })
+                        .build(),
+                )
+                .build(),
+        ),
+        ("invitations", unique(doc! { "token": 1 })),
+        ("invitations", index(doc! { "team_id": 1, "email": 1 })),
+    ]
+}
+
+/// Creates the collections with their validators and indexes, updating them when
+/// they already exist. Panics if the schema can't be applied, as the API can't
+/// guarantee the integrity of the data without it.
+pub async fn bootstrap(db: &Database) {
+    println!("[DB] ~ Applying schema...");
+
+    let existing = db.list_collection_names(None).await.unwrap();
+
+    for (collection, validator) in validators() {
+        let mut command = if existing.iter().any(|name| name == collection) {
+            doc! { "collMod": collection }
+        } else {
+            doc! { "create": collection }
+        };
+
+        command.insert("validator", doc! { "$jsonSchema": validator });
+        command.insert("validationLevel", "moderate");
+        command.insert("validationAction", "error");
+
+        if let Err(error) = db.run_command(command, None).await {
+            panic!("[DB][SCHEMA][{}] ~ {}", collection.to_uppercase(), error);
+        }
+    }
+
+    for (collection, index) in indexes() {
+        let result = db
+            .collection::<Document>(collection)
+            .create_index(index, None)
+            .await;
+
+        if let Err(error) = result {
+            panic!("[DB][INDEX][{}] ~ {}", collection.to_uppercase(), error);
+        }
+    }
+
+    println!("[DB] ~ Schema applied!");
+}
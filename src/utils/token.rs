@@ -0,0 +1,9 @@
+use rand::RngCore;
+
+/// Generates an unguessable token of 32 random bytes encoded as hex
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,195 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use super::recurrence::Recurrence;
+use super::responders::ApiError;
+
+/// Rule broken by a field of a request body
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldError {
+    /// Path of the field (ex: `users[2]`)
+    field: String,
+    /// Name of the broken rule (ex: `length`, `email`, `range`)
+    code: String,
+    message: String,
+}
+
+/// Body of the 422 returned when a request body breaks validation rules
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldErrors {
+    message: String,
+    errors: Vec<FieldError>,
+}
+
+impl From<ValidationErrors> for FieldErrors {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut field_errors = vec![];
+        flatten(&errors, None, &mut field_errors);
+        field_errors.sort_by(|a, b| a.field.cmp(&b.field));
+
+        FieldErrors {
+            message: "The request body is not valid".to_owned(),
+            errors: field_errors,
+        }
+    }
+}
+
+fn flatten(errors: &ValidationErrors, prefix: Option<&str>, result: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, field),
+            None => field.to_string(),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                for error in errors {
+                    result.push(FieldError {
+                        field: path.clone(),
+                        code: error.code.to_string(),
+                        message: describe(error),
+                    });
+                }
+            }
+            ValidationErrorsKind::Struct(errors) => flatten(errors, Some(&path), result),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    flatten(errors, Some(&format!("{}[{}]", path, index)), result);
+                }
+            }
+        }
+    }
+}
+
+/// Human readable explanation of a broken rule
+fn describe(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let min = error.params.get("min");
+    let max = error.params.get("max");
+
+    match (error.code.as_ref(), min, max) {
+        ("length", Some(min), Some(max)) => format!("length must be between {} and {}", min, max),
+        ("length", Some(min), None) => format!("length must be at least {}", min),
+        ("length", None, Some(max)) => format!("length must be at most {}", max),
+        ("range", Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+        ("range", Some(min), None) => format!("must be at least {}", min),
+        ("range", None, Some(max)) => format!("must be at most {}", max),
+        ("email", _, _) => "must be a valid email".to_owned(),
+        (code, _, _) => format!("breaks the {} rule", code),
+    }
+}
+
+/// Checks the rules of a request body, failing with a 422 that lists every broken rule
+pub fn validate<T: Validate>(body: &T) -> Result<(), ApiError> {
+    match body.validate() {
+        Ok(()) => Ok(()),
+        Err(errors) => Err(ApiError::Invalid(Json(errors.into()))),
+    }
+}
+
+/// Checks that a period ends after it starts, failing with a 422 on `end_field`.
+/// Used on top of `validate` as the rule depends on two fields.
+pub fn validate_period(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    end_field: &'static str,
+) -> Result<(), ApiError> {
+    if end > start {
+        return Ok(());
+    }
+
+    Err(invalid_field(end_field, "period", "must be after the start"))
+}
+
+/// Checks that a range of days doesn't end before it starts (both days are
+/// included), failing with a 422 on `end_field`
+pub fn validate_date_range(
+    start: NaiveDate,
+    end: NaiveDate,
+    end_field: &'static str,
+) -> Result<(), ApiError> {
+    if end >= start {
+        return Ok(());
+    }
+
+    Err(invalid_field(end_field, "date_range", "must not be before the start"))
+}
+
+/// 422 for a rule that depends on several fields, reported on `field`
+pub fn invalid_field(field: &'static str, code: &'static str, message: &'static str) -> ApiError {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, error(code, message));
+    ApiError::Invalid(Json(errors.into()))
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::from(message));
+    error
+}
+
+/// Rejects texts made only of whitespace
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(error("blank", "must not be blank"));
+    }
+
+    Ok(())
+}
+
+/// Rejects ids that aren't a 24 characters hex ObjectId
+pub fn object_id(value: &str) -> Result<(), ValidationError> {
+    if bson::oid::ObjectId::parse_str(value).is_err() {
+        return Err(error("object_id", "must be a 24 characters hex id"));
+    }
+
+    Ok(())
+}
+
+/// Rejects lists with any id that isn't a 24 characters hex ObjectId
+pub fn object_ids(values: &[String]) -> Result<(), ValidationError> {
+    if values.iter().any(|value| object_id(value).is_err()) {
+        return Err(error("object_id", "must only contain 24 characters hex ids"));
+    }
+
+    Ok(())
+}
+
+/// Rejects recurrence rules outside of the supported subset (see [`Recurrence`])
+pub fn rrule(value: &str) -> Result<(), ValidationError> {
+    if Recurrence::from_str(value).is_err() {
+        return Err(error(
+            "rrule",
+            "must be a DAILY or WEEKLY rule with an optional INTERVAL (1 or 2) and BYDAY",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects the URLs the API can't call: other schemes than https, local hosts and
+/// addresses that aren't public
+pub fn public_url(value: &str) -> Result<(), ValidationError> {
+    match super::outbound::check_url(value) {
+        Ok(_) => Ok(()),
+        Err(reason) => Err(error("public_url", reason)),
+    }
+}
+
+/// Rejects unknown IANA timezones
+pub fn timezone(value: &str) -> Result<(), ValidationError> {
+    if Tz::from_str(value).is_err() {
+        return Err(error("timezone", "must be an IANA timezone (ex: Europe/Madrid)"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Day of the week, named like in the RRULE `BYDAY`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WeekDay {
+    MO,
+    TU,
+    WE,
+    TH,
+    FR,
+    SA,
+    SU,
+}
+
+impl From<Weekday> for WeekDay {
+    fn from(day: Weekday) -> Self {
+        match day {
+            Weekday::Mon => WeekDay::MO,
+            Weekday::Tue => WeekDay::TU,
+            Weekday::Wed => WeekDay::WE,
+            Weekday::Thu => WeekDay::TH,
+            Weekday::Fri => WeekDay::FR,
+            Weekday::Sat => WeekDay::SA,
+            Weekday::Sun => WeekDay::SU,
+        }
+    }
+}
+
+/// Timezone of the teams that didn't choose one
+pub fn default_timezone() -> String {
+    "UTC".to_owned()
+}
+
+/// Working days of the teams that didn't choose them, Monday to Friday
+pub fn default_working_days() -> Vec<WeekDay> {
+    vec![WeekDay::MO, WeekDay::TU, WeekDay::WE, WeekDay::TH, WeekDay::FR]
+}
+
+/// Period the dates are grouped by in the analytics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bucket {
+    /// Local day
+    DAY,
+    /// Local week, starting on Monday
+    WEEK,
+}
+
+impl FromStr for Bucket {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "DAY" => Ok(Bucket::DAY),
+            "WEEK" => Ok(Bucket::WEEK),
+            _ => Err(()),
+        }
+    }
+}
+
+/// When a team works: its timezone, working days and holidays
+#[derive(Clone, Debug)]
+pub struct WorkCalendar {
+    tz: Tz,
+    working_days: Vec<WeekDay>,
+    holidays: Vec<NaiveDate>,
+}
+
+impl WorkCalendar {
+    /// Calendar of a team, an unknown timezone is taken as UTC
+    pub fn new(timezone: &str, working_days: &[WeekDay], holidays: &[NaiveDate]) -> Self {
+        Self {
+            tz: Tz::from_str(timezone).unwrap_or(Tz::UTC),
+            working_days: working_days.to_vec(),
+            holidays: holidays.to_vec(),
+        }
+    }
+
+    /// IANA name of the timezone
+    pub fn timezone(&self) -> &str {
+        self.tz.name()
+    }
+
+    /// Date and time in the timezone of the team
+    pub fn local(&self, date: DateTime<Utc>) -> DateTime<Tz> {
+        date.with_timezone(&self.tz)
+    }
+
+    /// Whether `date` (local) is a working day that isn't a holiday
+    pub fn is_working_day(&self, date: NaiveDate) -> bool {
+        self.working_days.contains(&date.weekday().into()) && !self.holidays.contains(&date)
+    }
+
+    /// First local day of the bucket `date` belongs to
+    pub fn bucket(&self, date: DateTime<Utc>, bucket: Bucket) -> NaiveDate {
+        let day = self.local(date).date_naive();
+
+        match bucket {
+            Bucket::DAY => day,
+            Bucket::WEEK => day - Duration::days(day.weekday().num_days_from_monday() as i64),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Outgoing webhooks of the teams.
+//!
+//! Emitting an event writes it to the outbox (`webhook_deliveries`), once per
+//! active webhook of the team subscribed to it, and the `deliver_webhooks` job
+//! posts the due deliveries. A delivery is claimed (`SENDING`) before it's posted,
+//! so only one instance posts it, and it's due again after [`CLAIM_SECONDS`] if
+//! the instance died meanwhile. A failed delivery is retried with an exponential
+//! backoff ([`retry_delay`]) up to [`MAX_ATTEMPTS`] times, every attempt is kept
+//! as the delivery log, without the body answered. Webhooks are only called on
+//! HTTPS public addresses and redirects aren't followed (see
+//! [`crate::utils::outbound`]).
+//!
+//! Every request carries the headers:
+//! - `X-Webhook-Event`: name of the event
+//! - `X-Webhook-Delivery`: id of the delivery, the same across its retries
+//! - `X-Webhook-Timestamp`: seconds since epoch when the request was signed
+//! - `X-Webhook-Signature`: `sha256=<hex>`, the HMAC-SHA256 of
+//!   `<timestamp>.<body>` keyed with the secret of the webhook
+
+use std::time::Instant;
+
+use bson::{doc, oid::ObjectId, to_bson};
+use chrono::serde::ts_milliseconds;
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::{error::Error, Database};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::models::meeting::Meeting;
+use crate::models::meeting_config::MeetingConfig;
+use crate::models::webhook::{
+    DeliveryAttempt, DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent,
+};
+use crate::utils::db::is_duplicate_key;
+use crate::utils::outbound::client_for;
+
+/// Attempts of a delivery before it's marked as failed
+pub const MAX_ATTEMPTS: usize = 8;
+
+/// Seconds before the first retry, doubled on every retry (1 minute, 2, 4... ~2 hours)
+const RETRY_BASE_SECONDS: i64 = 60;
+
+/// Seconds to wait for the endpoint of a webhook
+const TIMEOUT_SECONDS: u64 = 10;
+
+/// Deliveries posted per run of the job
+const BATCH_SIZE: usize = 100;
+
+/// Seconds a delivery stays claimed by the instance posting it
+const CLAIM_SECONDS: i64 = 60;
+
+/// Body posted to the webhooks
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize> {
+    /// Id of the event, the same for every webhook it's delivered to
+    id: String,
+    event: WebhookEvent,
+    team_id: String,
+    #[serde(with = "ts_milliseconds")]
+    created_at: DateTime<Utc>,
+    data: &'a T,
+}
+
+/// Data of the `PING` event
+#[derive(Serialize)]
+struct Ping {
+    webhook_id: String,
+}
+
+/// HMAC-SHA256 of `<timestamp>.<payload>` keyed with `secret`, as hex
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Time to wait before the next attempt, after `attempts` failed ones
+pub fn retry_delay(attempts: usize) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(16) as u32;
+    Duration::seconds(RETRY_BASE_SECONDS * 2_i64.pow(exponent))
+}
+
+fn envelope<T: Serialize>(
+    event_id: ObjectId,
+    event: WebhookEvent,
+    team_id: ObjectId,
+    data: &T,
+) -> String {
+    rocket::serde::json::to_string(&Envelope {
+        id: event_id.to_hex(),
+        event,
+        team_id: team_id.to_hex(),
+        created_at: Utc::now(),
+        data,
+    })
+    .unwrap()
+}
+
+/// Adds a delivery to the outbox, `None` if the webhook already got an event
+/// with the same `dedup_key`
+async fn enqueue(
+    db: &Database,
+    webhook: &Webhook,
+    event: WebhookEvent,
+    payload: String,
+    dedup_key: Option<String>,
+) -> Result<Option<WebhookDelivery>, Error> {
+    let now = Utc::now();
+    let mut delivery = WebhookDelivery {
+        id: None,
+        webhook_id: webhook.id.unwrap(),
+        event,
+        payload,
+        dedup_key,
+        status: DeliveryStatus::PENDING,
+        attempts: vec![],
+        next_attempt_at: Some(now),
+        created_at: now,
+    };
+
+    match db
+        .collection::<WebhookDelivery>("webhook_deliveries")
+        .insert_one(&delivery, None)
+        .await
+    {
+        Ok(result) => {
+            delivery.id = result.inserted_id.as_object_id();
+            Ok(Some(delivery))
+        }
+        Err(error) if is_duplicate_key(&error) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Adds the event to the outbox of every active webhook of the team subscribed
+/// to it. An event with a `dedup_key` is only delivered once per webhook. Errors
+/// are logged, the change that raised the event is already saved.
+pub async fn emit<T: Serialize>(
+    db: &Database,
+    team_id: ObjectId,
+    event: WebhookEvent,
+    dedup_key: Option<String>,
+    data: &T,
+) {
+    let result: Result<(), Error> = async {
+        let webhooks = db
+            .collection::<Webhook>("webhooks")
+            .find(
+                doc! {
+                    "team_id": team_id,
+                    "events": to_bson(&event).unwrap(),
+                    "active": true,
+                    "deleted_at": null
+                },
+                None,
+            )
+            .await?
+            .try_collect::<Vec<Webhook>>()
+            .await?;
+
+        if webhooks.is_empty() {
+            return Ok(());
+        }
+
+        let payload = envelope(ObjectId::new(), event, team_id, data);
+        for webhook in &webhooks {
+            enqueue(db, webhook, event, payload.clone(), dedup_key.clone()).await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(error) = result {
+        eprintln!("[WEBHOOKS][{:?}] ~ {}", event, error);
+    }
+}
+
+/// Team of a config, none if it has no team
+pub(crate) async fn team_of_config(db: &Database, config_id: Option<ObjectId>) -> Option<ObjectId> {
+    let config_id = config_id?;
+
+    match db
+        .collection::<MeetingConfig>("meeting_configs")
+        .find_one(doc! { "_id": config_id }, None)
+        .await
+    {
+        Ok(config) => config?.team_id,
+        Err(error) => {
+            eprintln!("[WEBHOOKS] ~ {}", error);
+            None
+        }
+    }
+}
+
+/// Emits an event about a meeting to the team of its config, if it has one
+pub async fn emit_for_meeting<T: Serialize>(
+    db: &Database,
+    meeting: &Meeting,
+    event: WebhookEvent,
+    dedup_key: Option<String>,
+    data: &T,
+) {
+    if let Some(team_id) = team_of_config(db, meeting.config_id).await {
+        emit(db, team_id, event, dedup_key, data).await;
+    }
+}
+
+/// Claims the first due delivery matching `filter` (oldest first), a pending one
+/// or one whose claim expired
+async fn claim(
+    db: &Database,
+    mut filter: bson::Document,
+) -> Result<Option<WebhookDelivery>, Error> {
+    let now = Utc::now();
+    filter.insert("status", doc! { "$in": ["PENDING", "SENDING"] });
+    filter.insert("next_attempt_at", doc! { "$lte": now.timestamp_millis() });
+
+    db.collection::<WebhookDelivery>("webhook_deliveries")
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": {
+                    "status": "SENDING",
+                    "next_attempt_at": (now + Duration::seconds(CLAIM_SECONDS)).timestamp_millis()
+                }
+            },
+            FindOneAndUpdateOptions::builder()
+                .sort(doc! { "next_attempt_at": 1 })
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+}
+
+/// Posts a delivery claimed by [`claim`] to its webhook and records the attempt,
+/// scheduling the next one if it failed
+async fn deliver(
+    db: &Database,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> Result<WebhookDelivery, Error> {
+    let delivery_id = delivery.id.unwrap();
+    let at = Utc::now();
+    let timestamp = at.timestamp();
+    let signature = sign(&webhook.secret, timestamp, &delivery.payload);
+    let timer = Instant::now();
+
+    let timeout = std::time::Duration::from_secs(TIMEOUT_SECONDS);
+    let result = match client_for(&webhook.url, timeout).await {
+        Ok(client) => client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Event", format!("{:?}", delivery.event))
+            .header("X-Webhook-Delivery", delivery_id.to_hex())
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .header("X-Webhook-Signature", format!("sha256={}", signature))
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|error| error.to_string()),
+        Err(error) => Err(error),
+    };
+
+    let mut attempt = DeliveryAttempt {
+        at,
+        duration_ms: 0,
+        response_status: None,
+        error: None,
+    };
+    let delivered = match result {
+        Ok(response) => {
+            let status = response.status();
+            attempt.response_status = Some(status.as_u16() as i32);
+            if !status.is_success() {
+                attempt.error = Some(format!("the endpoint answered {}", status));
+            }
+            status.is_success()
+        }
+        Err(error) => {
+            attempt.error = Some(error);
+            false
+        }
+    };
+    attempt.duration_ms = timer.elapsed().as_millis() as i64;
+
+    let attempts = delivery.attempts.len() + 1;
+    let (status, next_attempt_at) = if delivered {
+        (DeliveryStatus::DELIVERED, None)
+    } else if attempts >= MAX_ATTEMPTS {
+        (DeliveryStatus::FAILED, None)
+    } else {
+        (
+            DeliveryStatus::PENDING,
+            Some(Utc::now() + retry_delay(attempts)),
+        )
+    };
+
+    let updated = db
+        .collection::<WebhookDelivery>("webhook_deliveries")
+        .find_one_and_update(
+            doc! { "_id": delivery_id },
+            doc! {
+                "$set": {
+                    "status": to_bson(&status).unwrap(),
+                    "next_attempt_at": next_attempt_at.map(|date| date.timestamp_millis())
+                },
+                "$push": { "attempts": to_bson(&attempt).unwrap() }
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?;
+
+    Ok(updated.unwrap_or_else(|| delivery.clone()))
+}
+
+/// Sends a `PING` event to the webhook right away, returning its delivery
+pub async fn ping(db: &Database, webhook: &Webhook) -> Result<WebhookDelivery, Error> {
+    let data = Ping {
+        webhook_id: webhook.id.unwrap().to_hex(),
+    };
+    let payload = envelope(ObjectId::new(), WebhookEvent::PING, webhook.team_id, &data);
+
+    // without a dedup key it's always enqueued
+    let delivery = enqueue(db, webhook, WebhookEvent::PING, payload, None)
+        .await?
+        .unwrap();
+
+    // the job may have claimed it first
+    match claim(db, doc! { "_id": delivery.id }).await? {
+        Some(delivery) => deliver(db, webhook, &delivery).await,
+        None => Ok(delivery),
+    }
+}
+
+/// Amount of deliveries posted and failed by a run of [`deliver_due`]
+#[derive(Debug, Default)]
+pub struct DeliveryReport {
+    pub delivered: u64,
+    pub retried: u64,
+    pub failed: u64,
+}
+
+/// Posts the due deliveries of the active webhooks, oldest first
+pub async fn deliver_due(db: &Database) -> Result<DeliveryReport, Error> {
+    let webhooks = db
+        .collection::<Webhook>("webhooks")
+        .find(doc! { "active": true, "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<Webhook>>()
+        .await?;
+
+    let mut report = DeliveryReport::default();
+    if webhooks.is_empty() {
+        return Ok(report);
+    }
+
+    let webhooks_id: Vec<ObjectId> = webhooks.iter().map(|webhook| webhook.id.unwrap()).collect();
+    for _ in 0..BATCH_SIZE {
+        let delivery = match claim(db, doc! { "webhook_id": { "$in": &webhooks_id } }).await? {
+            Some(delivery) => delivery,
+            None => break,
+        };
+        let webhook = webhooks
+            .iter()
+            .find(|webhook| webhook.id == Some(delivery.webhook_id))
+            .unwrap();
+
+        match deliver(db, webhook, &delivery).await?.status {
+            DeliveryStatus::DELIVERED => report.delivered += 1,
+            DeliveryStatus::PENDING | DeliveryStatus::SENDING => report.retried += 1,
+            DeliveryStatus::FAILED => report.failed += 1,
+        }
+    }
+
+    Ok(report)
+}